@@ -112,8 +112,16 @@ impl Client {
                 }
                 ice_servers.push(RTCIceServer {
                     urls: vec![link.uri.to_string().replacen("://", ":", 1)],
-                    username: link.queries.remove("username").unwrap_or("".to_owned()),
-                    credential: link.queries.remove("credential").unwrap_or("".to_owned()),
+                    username: link
+                        .params
+                        .remove("username")
+                        .or(link.queries.remove("username"))
+                        .unwrap_or("".to_owned()),
+                    credential: link
+                        .params
+                        .remove("credential")
+                        .or(link.queries.remove("credential"))
+                        .unwrap_or("".to_owned()),
                     credential_type: link
                         .params
                         .remove("credential-type")
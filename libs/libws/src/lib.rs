@@ -252,6 +252,15 @@ impl GroupsManager {
             .map_err(|_| GroupError::MessageSendFail)
     }
 
+    /// Sends to every group, returns how many groups had a receiver.
+    pub async fn send_message_to_all_groups(&self, data: Vec<u8>) -> usize {
+        let groups = self.inner.lock().await;
+        groups
+            .values()
+            .filter(|group| group.send(data.clone()).is_ok())
+            .count()
+    }
+
     pub async fn send_message_to_user(
         &self,
         group: String,
@@ -381,6 +390,16 @@ impl GroupsManager {
         groups.len()
     }
 
+    pub async fn group_user_counts(&self) -> HashMap<String, u32> {
+        let groups = self.inner.lock().await;
+
+        let mut counts = HashMap::new();
+        for (name, group) in groups.iter() {
+            counts.insert(name.clone(), group.user_count().await);
+        }
+        counts
+    }
+
     pub async fn get_user_receiver(
         &self,
         group: String,
@@ -1,9 +1,13 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::{env, fs, net::SocketAddr, str::FromStr};
+use std::net::{IpAddr, SocketAddr};
+use std::{env, fmt, fs, str::FromStr};
+use utoipa::ToSchema;
 use webrtc::{ice, ice_transport::ice_server::RTCIceServer, Error};
 
+use crate::REDACTED;
+
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Config {
     #[serde(default)]
@@ -11,11 +15,21 @@ pub struct Config {
     #[serde(default = "default_ice_servers")]
     pub ice_servers: Vec<IceServer>,
     #[serde(default)]
+    pub ice: Ice,
+    #[serde(default)]
+    pub turn: Option<Turn>,
+    #[serde(default)]
     pub auth: Auth,
     #[serde(default)]
+    pub admin: Auth,
+    #[serde(default)]
     pub log: Log,
     #[serde(default)]
     pub stream_info: StreamInfo,
+    #[serde(default)]
+    pub shutdown: Shutdown,
+    #[serde(default)]
+    pub recorder: Recorder,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -24,9 +38,107 @@ pub struct Http {
     pub listen: SocketAddr,
     #[serde(default)]
     pub cors: bool,
+    #[serde(default)]
+    pub tls: Option<Tls>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Tls {
+    pub cert: String,
+    pub key: String,
+    #[serde(default)]
+    pub client_ca: Option<String>,
+    #[serde(default)]
+    pub reload_interval: TlsReloadInterval,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsReloadInterval(pub u64);
+
+impl Default for TlsReloadInterval {
+    fn default() -> Self {
+        TlsReloadInterval(10000)
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ice {
+    #[serde(default)]
+    pub udp_port_min: Option<u16>,
+    #[serde(default)]
+    pub udp_port_max: Option<u16>,
+    #[serde(default)]
+    pub udp_mux_listen: Option<SocketAddr>,
+    #[serde(default)]
+    pub nat_1to1_ips: Vec<IpAddr>,
+    #[serde(default)]
+    pub nat_1to1_candidate_type: Nat1To1CandidateType,
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    #[serde(default)]
+    pub ips: Vec<IpAddr>,
+    #[serde(default)]
+    pub mdns: MulticastDns,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Nat1To1CandidateType {
+    #[default]
+    Host,
+    Srflx,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MulticastDns {
+    Disabled,
+    #[default]
+    QueryOnly,
+    QueryAndGather,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Turn {
+    #[serde(default = "default_turn_listen")]
+    pub listen: SocketAddr,
+    pub public_ip: IpAddr,
+    pub secret: String,
+    #[serde(default = "default_turn_realm")]
+    pub realm: String,
+    #[serde(default)]
+    pub credential_ttl: TurnCredentialTtl,
+    #[serde(default)]
+    pub relay_port_min: Option<u16>,
+    #[serde(default)]
+    pub relay_port_max: Option<u16>,
+}
+
+// the config is logged at startup, the secret is left out
+impl fmt::Debug for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Turn")
+            .field("listen", &self.listen)
+            .field("public_ip", &self.public_ip)
+            .field("secret", &REDACTED)
+            .field("realm", &self.realm)
+            .field("credential_ttl", &self.credential_ttl)
+            .field("relay_port_min", &self.relay_port_min)
+            .field("relay_port_max", &self.relay_port_max)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnCredentialTtl(pub u64);
+
+impl Default for TurnCredentialTtl {
+    fn default() -> Self {
+        TurnCredentialTtl(86400)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Auth {
     #[serde(default)]
     pub accounts: Vec<Account>,
@@ -34,6 +146,15 @@ pub struct Auth {
     pub tokens: Vec<String>,
 }
 
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("accounts", &self.accounts)
+            .field("tokens", &vec![REDACTED; self.tokens.len()])
+            .finish()
+    }
+}
+
 impl Auth {
     pub fn to_authorizations(&self) -> Vec<String> {
         let mut authorizations = vec![];
@@ -47,7 +168,7 @@ impl Auth {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Account {
     #[serde(default)]
     pub username: String,
@@ -55,6 +176,15 @@ pub struct Account {
     pub password: String,
 }
 
+impl fmt::Debug for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Account")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
 impl Account {
     pub fn to_authorization(&self) -> String {
         let encoded = STANDARD.encode(format!("{}:{}", self.username, self.password));
@@ -87,6 +217,59 @@ pub struct StreamInfo {
     pub reforward_close_sub: bool,
     #[serde(default)]
     pub publish_leave_timeout: PublishLeaveTimeout,
+    #[serde(default)]
+    pub keyframe_request_interval: KeyframeRequestInterval,
+    #[serde(default)]
+    pub gop_cache: bool,
+    #[serde(default)]
+    pub last_n: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyframeRequestInterval(pub u64);
+
+impl Default for KeyframeRequestInterval {
+    fn default() -> Self {
+        KeyframeRequestInterval(500)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorder {
+    #[serde(default = "default_recorder_dir")]
+    pub dir: String,
+    #[serde(default)]
+    pub auto_start: bool,
+    #[serde(default)]
+    pub max_size: u64,
+    #[serde(default)]
+    pub max_duration: u64,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder {
+            dir: default_recorder_dir(),
+            auto_start: false,
+            max_size: 0,
+            max_duration: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Shutdown {
+    #[serde(default)]
+    pub drain_period: DrainPeriod,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainPeriod(pub u64);
+
+impl Default for DrainPeriod {
+    fn default() -> Self {
+        DrainPeriod(10000)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -129,10 +312,19 @@ impl Default for Http {
         Self {
             listen: default_http_listen(),
             cors: Default::default(),
+            tls: Default::default(),
         }
     }
 }
 
+fn default_turn_listen() -> SocketAddr {
+    SocketAddr::from_str("0.0.0.0:3478").unwrap()
+}
+
+fn default_turn_realm() -> String {
+    "rust-server-for-multiplayer".to_string()
+}
+
 fn default_ice_servers() -> Vec<IceServer> {
     vec![IceServer {
         urls: vec!["stun:stun.l.google.com:19302".to_string()],
@@ -160,7 +352,11 @@ fn default_log_level() -> String {
     })
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+fn default_recorder_dir() -> String {
+    String::from("recordings")
+}
+
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
 pub struct IceServer {
     #[serde(default)]
     pub urls: Vec<String>,
@@ -172,6 +368,17 @@ pub struct IceServer {
     pub credential_type: String,
 }
 
+impl fmt::Debug for IceServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IceServer")
+            .field("urls", &self.urls)
+            .field("username", &self.username)
+            .field("credential", &REDACTED)
+            .field("credential_type", &self.credential_type)
+            .finish()
+    }
+}
+
 // from https://github.com/webrtc-rs/webrtc/blob/71157ba2153a891a8cfd819f3cf1441a7a0808d8/webrtc/src/ice_transport/ice_server.rs
 impl IceServer {
     pub(crate) fn parse_url(&self, url_str: &str) -> webrtc::error::Result<ice::url::Url> {
@@ -219,11 +426,12 @@ impl From<IceServer> for RTCIceServer {
 
 impl Config {
     pub(crate) fn parse(path: Option<String>) -> Self {
-        let result = fs::read_to_string(path.unwrap_or(String::from("rust-server-for-multiplayer.toml")))
-            .or(fs::read_to_string(
-                "/etc/rust-server-for-multiplayer/rust-server-for-multiplayer.toml",
-            ))
-            .unwrap_or("".to_string());
+        let result =
+            fs::read_to_string(path.unwrap_or(String::from("rust-server-for-multiplayer.toml")))
+                .or(fs::read_to_string(
+                    "/etc/rust-server-for-multiplayer/rust-server-for-multiplayer.toml",
+                ))
+                .unwrap_or("".to_string());
         let cfg: Self = toml::from_str(result.as_str()).expect("config parse error");
         match cfg.validate() {
             Ok(_) => cfg,
@@ -243,6 +451,40 @@ impl Config {
                 "stream_info.pub_max cannot be greater than stream_info.sub_max"
             ));
         }
+        if let Some(tls) = &self.http.tls {
+            if tls.cert.is_empty() || tls.key.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "http.tls.cert and http.tls.key are required"
+                ));
+            }
+            if tls.reload_interval.0 == 0 {
+                return Err(anyhow::anyhow!(
+                    "http.tls.reload_interval cannot be equal to 0"
+                ));
+            }
+        }
+        self.validate_ice()?;
+        if let Some(turn) = &self.turn {
+            if turn.secret.is_empty() {
+                return Err(anyhow::anyhow!("turn.secret cannot be empty"));
+            }
+            if turn.credential_ttl.0 == 0 {
+                return Err(anyhow::anyhow!("turn.credential_ttl cannot be equal to 0"));
+            }
+            match (turn.relay_port_min, turn.relay_port_max) {
+                (Some(min), Some(max)) if min == 0 || min > max => {
+                    return Err(anyhow::anyhow!(
+                        "turn.relay_port_min must be in 1..=turn.relay_port_max"
+                    ));
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    return Err(anyhow::anyhow!(
+                        "turn.relay_port_min and turn.relay_port_max must be set together"
+                    ));
+                }
+                _ => {}
+            }
+        }
         for ice_server in self.ice_servers.iter() {
             ice_server
                 .validate()
@@ -250,4 +492,41 @@ impl Config {
         }
         Ok(())
     }
+
+    fn validate_ice(&self) -> anyhow::Result<()> {
+        let ice = &self.ice;
+        match (ice.udp_port_min, ice.udp_port_max) {
+            (Some(min), Some(max)) if min > max => {
+                return Err(anyhow::anyhow!(
+                    "ice.udp_port_min cannot be greater than ice.udp_port_max"
+                ));
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "ice.udp_port_min and ice.udp_port_max must be set together"
+                ));
+            }
+            _ => {}
+        }
+        if ice.udp_mux_listen.is_some() && ice.udp_port_min.is_some() {
+            return Err(anyhow::anyhow!(
+                "ice.udp_mux_listen cannot be used with ice.udp_port_min/udp_port_max"
+            ));
+        }
+        if ice.nat_1to1_ips.is_empty() {
+            return Ok(());
+        }
+        match ice.nat_1to1_candidate_type {
+            Nat1To1CandidateType::Host if ice.mdns == MulticastDns::QueryAndGather => {
+                Err(anyhow::anyhow!(
+                    "ice.nat_1to1_ips with host candidates cannot be used with mdns gathering"
+                ))
+            }
+            // webrtc-ice does not gather server reflexive candidates on a muxed UDP network
+            Nat1To1CandidateType::Srflx if ice.udp_mux_listen.is_some() => Err(anyhow::anyhow!(
+                "ice.nat_1to1_ips with srflx candidates cannot be used with ice.udp_mux_listen"
+            )),
+            _ => Ok(()),
+        }
+    }
 }
@@ -1,15 +1,121 @@
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use http::StatusCode;
+use tracing::error;
+
+use crate::http::response::ErrorJson;
 
 #[derive(Debug)]
 pub enum AppError {
+    BadRequest(String, Option<String>),
+    InvalidOffer(String),
+    CodecNotSupported(String, Option<String>),
+    RoomNotFound(String),
+    RoomFull(String),
+    HostExists(String),
+    BadKey(String),
+    TokenInvalid(String),
+    Forbidden(String),
+    UserNotFound(String),
     StreamNotFound(String),
     StreamAlreadyExists(String),
+    SessionNotFound(String),
+    LayerNotFound(String),
+    PublishNotReady(String),
+    ServerDraining(String),
     Throw(String),
     InternalServerError(anyhow::Error),
 }
 
 impl AppError {
+    pub fn bad_request<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::BadRequest(t.to_string(), None)
+    }
+
+    /// `details` names the offending field or carries the parser's error.
+    pub fn bad_request_details<T, D>(t: T, details: D) -> Self
+    where
+        T: ToString,
+        D: ToString,
+    {
+        AppError::BadRequest(t.to_string(), Some(details.to_string()))
+    }
+
+    pub fn invalid_offer<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::InvalidOffer(t.to_string())
+    }
+
+    pub fn codec_not_supported<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::CodecNotSupported(t.to_string(), None)
+    }
+
+    /// `details` lists the codecs which are not supported.
+    pub fn codec_not_supported_details<T, D>(t: T, details: D) -> Self
+    where
+        T: ToString,
+        D: ToString,
+    {
+        AppError::CodecNotSupported(t.to_string(), Some(details.to_string()))
+    }
+
+    pub fn room_not_found<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::RoomNotFound(t.to_string())
+    }
+
+    pub fn room_full<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::RoomFull(t.to_string())
+    }
+
+    pub fn host_exists<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::HostExists(t.to_string())
+    }
+
+    pub fn bad_key<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::BadKey(t.to_string())
+    }
+
+    pub fn token_invalid<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::TokenInvalid(t.to_string())
+    }
+
+    pub fn forbidden<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::Forbidden(t.to_string())
+    }
+
+    pub fn user_not_found<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::UserNotFound(t.to_string())
+    }
+
     pub fn stream_not_found<T>(t: T) -> Self
     where
         T: ToString,
@@ -24,24 +130,124 @@ impl AppError {
         AppError::StreamAlreadyExists(t.to_string())
     }
 
+    pub fn session_not_found<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::SessionNotFound(t.to_string())
+    }
+
+    pub fn layer_not_found<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::LayerNotFound(t.to_string())
+    }
+
+    pub fn publish_not_ready<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::PublishNotReady(t.to_string())
+    }
+
+    pub fn server_draining<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::ServerDraining(t.to_string())
+    }
+
     pub fn throw<T>(t: T) -> Self
     where
         T: ToString,
     {
         AppError::Throw(t.to_string())
     }
+
+    /// Stable, machine-readable code sent to clients in the `code` field.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(..) => "BAD_REQUEST",
+            AppError::InvalidOffer(_) => "INVALID_OFFER",
+            AppError::CodecNotSupported(..) => "CODEC_NOT_SUPPORTED",
+            AppError::RoomNotFound(_) => "ROOM_NOT_FOUND",
+            AppError::RoomFull(_) => "ROOM_FULL",
+            AppError::HostExists(_) => "HOST_EXISTS",
+            AppError::BadKey(_) => "BAD_KEY",
+            AppError::TokenInvalid(_) => "TOKEN_INVALID",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::UserNotFound(_) => "USER_NOT_FOUND",
+            AppError::StreamNotFound(_) => "STREAM_NOT_FOUND",
+            AppError::StreamAlreadyExists(_) => "STREAM_EXISTS",
+            AppError::SessionNotFound(_) => "SESSION_NOT_FOUND",
+            AppError::LayerNotFound(_) => "LAYER_NOT_FOUND",
+            AppError::PublishNotReady(_) => "PUBLISH_NOT_READY",
+            AppError::ServerDraining(_) => "SERVER_DRAINING",
+            AppError::Throw(_) | AppError::InternalServerError(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(..)
+            | AppError::InvalidOffer(_)
+            | AppError::CodecNotSupported(..) => StatusCode::BAD_REQUEST,
+            AppError::RoomNotFound(_)
+            | AppError::UserNotFound(_)
+            | AppError::StreamNotFound(_)
+            | AppError::SessionNotFound(_)
+            | AppError::LayerNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::TokenInvalid(_) => StatusCode::UNAUTHORIZED,
+            AppError::BadKey(_) | AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::RoomFull(_) | AppError::HostExists(_) | AppError::StreamAlreadyExists(_) => {
+                StatusCode::CONFLICT
+            }
+            AppError::PublishNotReady(_) | AppError::ServerDraining(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AppError::Throw(_) | AppError::InternalServerError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> ErrorJson {
+        let (message, details) = match self {
+            // the chain may name files and internals, it is only logged
+            AppError::InternalServerError(_) => ("Internal Server Error".to_string(), None),
+            AppError::BadRequest(message, details)
+            | AppError::CodecNotSupported(message, details) => (message.clone(), details.clone()),
+            AppError::InvalidOffer(message)
+            | AppError::RoomNotFound(message)
+            | AppError::RoomFull(message)
+            | AppError::HostExists(message)
+            | AppError::BadKey(message)
+            | AppError::TokenInvalid(message)
+            | AppError::Forbidden(message)
+            | AppError::UserNotFound(message)
+            | AppError::StreamNotFound(message)
+            | AppError::StreamAlreadyExists(message)
+            | AppError::SessionNotFound(message)
+            | AppError::LayerNotFound(message)
+            | AppError::PublishNotReady(message)
+            | AppError::ServerDraining(message)
+            | AppError::Throw(message) => (message.clone(), None),
+        };
+        ErrorJson {
+            code: self.code().to_string(),
+            message,
+            details,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        match self {
-            AppError::StreamNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
-            AppError::StreamAlreadyExists(err) => (StatusCode::CONFLICT, err).into_response(),
-            AppError::InternalServerError(err) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
-            }
-            AppError::Throw(err) => (StatusCode::INTERNAL_SERVER_ERROR, err).into_response(),
+        if let AppError::InternalServerError(err) = &self {
+            error!("internal server error: {:#}", err);
         }
+        (self.status(), Json(self.to_json())).into_response()
     }
 }
 
@@ -53,3 +259,26 @@ where
         AppError::InternalServerError(err.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_details_only_when_set() {
+        let json = AppError::bad_request("kind must be audio or video").to_json();
+        assert_eq!(json.code, "BAD_REQUEST");
+        assert_eq!(json.details, None);
+
+        let json = AppError::codec_not_supported_details("no codec", "video/H265").to_json();
+        assert_eq!(json.code, "CODEC_NOT_SUPPORTED");
+        assert_eq!(json.details.as_deref(), Some("video/H265"));
+    }
+
+    #[test]
+    fn test_internal_error_hides_details() {
+        let json = AppError::from(anyhow::anyhow!("/etc/secret: denied")).to_json();
+        assert_eq!(json.message, "Internal Server Error");
+        assert_eq!(json.details, None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::{Arc, Weak};
+
+use libws::GroupsManager;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::RwLock;
+
+use crate::metrics;
+use crate::room::Room;
+use crate::rtc::Forwarder;
+
+/// Frame typ for server events, next to struct (0), open (1) and close (2).
+pub const TYPE_SERVER_EVENT: u8 = 3;
+/// `from` and `to` of server event frames, no user is ever given this id.
+pub const SERVER_USER_ID: u32 = u32::MAX;
+
+/// Events the server pushes to clients over WebSocket groups and data channels,
+/// serialized as json after the frame header.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// The server stops after `drain_period` milliseconds.
+    Shutdown { drain_period: u64 },
+    /// The audio of `user_id` rose above the speech level.
+    SpeakingStarted { user_id: u32 },
+    /// `user_id` is silent, or stopped publishing.
+    SpeakingStopped { user_id: u32 },
+    /// The loudest speaker of the room changed, `None` once it stopped publishing.
+    DominantSpeakerChanged { user_id: Option<u32> },
+    /// The publisher of `stream` enabled or disabled its `kind` tracks for everyone.
+    ResourceChanged {
+        user_id: u32,
+        stream: String,
+        kind: String,
+        enabled: bool,
+    },
+}
+
+impl ServerEvent {
+    /// typ (1) + from (0 ~ 3) + to (4 ~ 7) + json, the same layout as relayed messages.
+    pub fn to_frame(&self) -> Vec<u8> {
+        let mut frame = vec![TYPE_SERVER_EVENT];
+        frame.extend_from_slice(&SERVER_USER_ID.to_le_bytes());
+        frame.extend_from_slice(&SERVER_USER_ID.to_le_bytes());
+        frame.extend_from_slice(&serde_json::to_vec(self).unwrap());
+        frame
+    }
+
+    /// Sends the event to every WebSocket group and data channel of the room.
+    pub async fn send_to_room(&self, room: &Room) {
+        self.send(&room.group_manager(), &room.forwarder()).await;
+    }
+
+    async fn send(&self, group_manager: &RwLock<GroupsManager>, forwarder: &RwLock<Forwarder>) {
+        let frame = self.to_frame();
+
+        let group_manager = group_manager.read().await;
+        group_manager
+            .send_message_to_all_groups(frame.clone())
+            .await;
+        drop(group_manager);
+
+        let forwarder = forwarder.read().await;
+        forwarder.send_server_event(frame).await;
+        drop(forwarder);
+    }
+
+    /// Sends the events of a room's forwarder to the room, until the
+    /// forwarder is dropped with the room.
+    pub async fn relay(
+        mut events: broadcast::Receiver<ServerEvent>,
+        forwarder: Weak<RwLock<Forwarder>>,
+        group_manager: Arc<RwLock<GroupsManager>>,
+    ) {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(n)) => {
+                    metrics::BROADCAST_LAGGED
+                        .with_label_values(&[metrics::CHANNEL_SERVER_EVENT])
+                        .inc_by(n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let Some(forwarder) = forwarder.upgrade() else {
+                break;
+            };
+            event.send(&group_manager, &forwarder).await;
+        }
+    }
+}
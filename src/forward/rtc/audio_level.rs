@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use webrtc::rtp::packet::Packet;
+
+// weight of a new packet in the smoothed level, packets come every 20 ms
+const SMOOTHING: f64 = 0.2;
+// without packets for this long the track is silent, e.g. DTX
+const SILENCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The smoothed level of a publisher audio track, from the ssrc-audio-level
+/// header extension (RFC 6464).
+pub(crate) struct AudioLevel {
+    extension_id: u8,
+    // 0 (silence, -127 dBov) to 127 (0 dBov)
+    smoothed: f64,
+    updated: Instant,
+}
+
+impl AudioLevel {
+    pub(crate) fn new(extension_id: u8) -> Self {
+        Self {
+            extension_id,
+            smoothed: 0.0,
+            updated: Instant::now(),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, packet: &Packet) {
+        let Some(extension) = packet.header.get_extension(self.extension_id) else {
+            return;
+        };
+        // V bit, then the level in -dBov
+        let Some(&byte) = extension.first() else {
+            return;
+        };
+        let loudness = (127 - (byte & 0x7F)) as f64;
+        self.smoothed += SMOOTHING * (loudness - self.smoothed);
+        self.updated = Instant::now();
+    }
+
+    /// The smoothed level, 0 (silence) to 127 (0 dBov).
+    pub(crate) fn level(&self) -> u8 {
+        if self.updated.elapsed() > SILENCE_TIMEOUT {
+            return 0;
+        }
+        self.smoothed.round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use webrtc::rtp::header::Header;
+
+    use super::*;
+
+    const EXTENSION_ID: u8 = 1;
+
+    fn packet(extension: Option<u8>) -> Packet {
+        let mut header = Header::default();
+        if let Some(byte) = extension {
+            header
+                .set_extension(EXTENSION_ID, vec![byte].into())
+                .unwrap();
+        }
+        Packet {
+            header,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_smoothed_level() {
+        let mut level = AudioLevel::new(EXTENSION_ID);
+        assert_eq!(level.level(), 0);
+        // -27 dBov, with the V bit set
+        level.observe(&packet(Some(0x80 | 27)));
+        assert_eq!(level.level(), 20);
+        for _ in 0..50 {
+            level.observe(&packet(Some(27)));
+        }
+        assert_eq!(level.level(), 100);
+        // silence is 127 dBov down
+        level.observe(&packet(Some(127)));
+        assert_eq!(level.level(), 80);
+    }
+
+    #[test]
+    fn test_without_extension() {
+        let mut level = AudioLevel::new(EXTENSION_ID);
+        level.observe(&packet(None));
+        assert_eq!(level.level(), 0);
+        let mut level = AudioLevel::new(EXTENSION_ID + 1);
+        level.observe(&packet(Some(0)));
+        assert_eq!(level.level(), 0);
+    }
+
+    #[test]
+    fn test_silent_without_packets() {
+        let mut level = AudioLevel::new(EXTENSION_ID);
+        for _ in 0..50 {
+            level.observe(&packet(Some(0)));
+        }
+        assert_eq!(level.level(), 127);
+        level.updated -= SILENCE_TIMEOUT * 2;
+        assert_eq!(level.level(), 0);
+    }
+}
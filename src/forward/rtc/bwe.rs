@@ -0,0 +1,411 @@
+use std::time::{Duration, Instant};
+
+use webrtc::rtcp::packet::Packet;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+};
+
+// a REMB older than this is no longer trusted
+const REMB_TIMEOUT: Duration = Duration::from_secs(5);
+const LOSS_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_BITRATE: u64 = 30_000;
+const MAX_BITRATE: u64 = 50_000_000;
+
+// upswitch only when the budget exceeds the next layer by this factor ...
+const UP_MARGIN: f64 = 1.15;
+// ... for this many consecutive ticks
+const UP_TICKS: u32 = 3;
+const DOWN_TICKS: u32 = 2;
+
+/// Estimates the bandwidth available towards one subscriber, from the REMB
+/// and transport-cc feedback it sends. The estimate is shared by all senders
+/// of the peer, the video senders not paused split it evenly.
+pub(crate) struct BandwidthEstimator {
+    video_senders: usize,
+    // paused video senders get no share
+    paused_video_senders: usize,
+    remb: Option<(u64, Instant)>,
+    // loss based estimate, in the spirit of GCC: grows while the loss is low
+    // and shrinks with it when it is high
+    loss_based: Option<u64>,
+    received: u64,
+    lost: u64,
+    loss_update: Instant,
+    sent_bytes: u64,
+    sent_bitrate: u64,
+    sent_since: Instant,
+}
+
+impl BandwidthEstimator {
+    pub(crate) fn new(video_senders: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            video_senders,
+            paused_video_senders: 0,
+            remb: None,
+            loss_based: None,
+            received: 0,
+            lost: 0,
+            loss_update: now,
+            sent_bytes: 0,
+            sent_bitrate: 0,
+            sent_since: now,
+        }
+    }
+
+    /// Counts `bytes` sent to the subscriber, the loss based estimate starts
+    /// from the measured send bitrate.
+    pub(crate) fn on_sent(&mut self, bytes: usize) {
+        self.sent_bytes += bytes as u64;
+        let elapsed = self.sent_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.sent_bitrate = self.sent_bytes * 8 * 1000 / elapsed.as_millis() as u64;
+            self.sent_bytes = 0;
+            self.sent_since = Instant::now();
+        }
+    }
+
+    pub(crate) fn on_video_paused(&mut self, paused: bool) {
+        if paused {
+            self.paused_video_senders += 1;
+        } else {
+            self.paused_video_senders = self.paused_video_senders.saturating_sub(1);
+        }
+    }
+
+    pub(crate) fn on_rtcp(&mut self, packets: &[Box<dyn Packet + Send + Sync>]) {
+        for packet in packets {
+            let any = packet.as_any();
+            if let Some(remb) = any.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+                self.remb = Some((remb.bitrate as u64, Instant::now()));
+            } else if let Some(twcc) = any.downcast_ref::<TransportLayerCc>() {
+                self.on_transport_cc(twcc);
+            }
+        }
+    }
+
+    fn on_transport_cc(&mut self, twcc: &TransportLayerCc) {
+        let mut remaining = twcc.packet_status_count as u64;
+        for chunk in &twcc.packet_chunks {
+            let (lost, count) = match chunk {
+                PacketStatusChunk::RunLengthChunk(chunk) => {
+                    let count = (chunk.run_length as u64).min(remaining);
+                    let lost = match chunk.packet_status_symbol {
+                        SymbolTypeTcc::PacketNotReceived => count,
+                        _ => 0,
+                    };
+                    (lost, count)
+                }
+                PacketStatusChunk::StatusVectorChunk(chunk) => {
+                    // the last chunk may be padded with symbols past the count
+                    let symbols = chunk.symbol_list.iter().take(remaining as usize);
+                    let count = symbols.len() as u64;
+                    let lost = symbols
+                        .filter(|symbol| **symbol == SymbolTypeTcc::PacketNotReceived)
+                        .count() as u64;
+                    (lost, count)
+                }
+            };
+            self.lost += lost;
+            self.received += count - lost;
+            remaining -= count;
+        }
+
+        if self.loss_update.elapsed() < LOSS_UPDATE_INTERVAL || self.received + self.lost == 0 {
+            return;
+        }
+        let loss = self.lost as f64 / (self.received + self.lost) as f64;
+        let base = self.loss_based.unwrap_or(self.sent_bitrate) as f64;
+        // the estimate grows from its own value, not from the send bitrate,
+        // so that a sender held on a low layer can switch back up
+        let estimate = if loss < 0.02 {
+            base * 1.08
+        } else if loss > 0.1 {
+            base * (1.0 - 0.5 * loss)
+        } else {
+            base
+        };
+        self.loss_based = Some((estimate as u64).clamp(MIN_BITRATE, MAX_BITRATE));
+        self.received = 0;
+        self.lost = 0;
+        self.loss_update = Instant::now();
+    }
+
+    /// The bitrate available to each video sender, `None` before any feedback.
+    pub(crate) fn video_budget(&self) -> Option<u64> {
+        let remb = self
+            .remb
+            .filter(|(_, at)| at.elapsed() < REMB_TIMEOUT)
+            .map(|(bitrate, _)| bitrate);
+        let estimate = match (remb, self.loss_based) {
+            (Some(remb), Some(loss_based)) => remb.min(loss_based),
+            (estimate, None) | (None, estimate) => estimate?,
+        };
+        let active = self.video_senders.saturating_sub(self.paused_video_senders);
+        Some(estimate / active.max(1) as u64)
+    }
+}
+
+/// Picks the simulcast layer of one sender from its bandwidth budget. Going
+/// down needs the budget to stay under the current layer for `DOWN_TICKS`
+/// ticks, going up needs headroom over the next layer for `UP_TICKS` ticks,
+/// and only moves one layer at a time.
+#[derive(Default)]
+pub(crate) struct LayerSelector {
+    up_ticks: u32,
+    down_ticks: u32,
+}
+
+impl LayerSelector {
+    pub(crate) fn reset(&mut self) {
+        self.up_ticks = 0;
+        self.down_ticks = 0;
+    }
+
+    /// `layers` are the rids of the source with their measured bitrate.
+    /// Returns the rid to switch to, if it differs from `current`.
+    pub(crate) fn next_layer(
+        &mut self,
+        layers: &[(String, u64)],
+        current: &str,
+        budget: u64,
+    ) -> Option<String> {
+        // a layer without bitrate has not been measured yet
+        let mut layers: Vec<&(String, u64)> = layers
+            .iter()
+            .filter(|(rid, bitrate)| *bitrate > 0 || rid == current)
+            .collect();
+        layers.sort_by_key(|(_, bitrate)| *bitrate);
+        let position = layers.iter().position(|(rid, _)| rid == current)?;
+        let current_bitrate = layers[position].1;
+
+        let target = if current_bitrate > budget {
+            self.up_ticks = 0;
+            self.down_ticks += 1;
+            if self.down_ticks < DOWN_TICKS {
+                return None;
+            }
+            layers
+                .iter()
+                .rev()
+                .find(|(_, bitrate)| *bitrate <= budget)
+                .or(layers.first())
+        } else if let Some(next) = layers
+            .get(position + 1)
+            .filter(|(_, bitrate)| budget as f64 >= *bitrate as f64 * UP_MARGIN)
+        {
+            self.down_ticks = 0;
+            self.up_ticks += 1;
+            if self.up_ticks < UP_TICKS {
+                return None;
+            }
+            Some(next)
+        } else {
+            self.reset();
+            return None;
+        };
+        self.reset();
+        target
+            .map(|(rid, _)| rid.clone())
+            .filter(|rid| rid != current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtcp::transport_feedbacks::transport_layer_cc::RunLengthChunk;
+
+    fn feedback(received: u16, lost: u16) -> TransportLayerCc {
+        let chunk = |symbol, run_length| {
+            PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                packet_status_symbol: symbol,
+                run_length,
+                ..Default::default()
+            })
+        };
+        TransportLayerCc {
+            packet_status_count: received + lost,
+            packet_chunks: vec![
+                chunk(SymbolTypeTcc::PacketReceivedSmallDelta, received),
+                chunk(SymbolTypeTcc::PacketNotReceived, lost),
+            ],
+            ..Default::default()
+        }
+    }
+
+    // an estimator sending at `sent_bitrate`, due for a loss update
+    fn estimator(sent_bitrate: u64) -> BandwidthEstimator {
+        let mut estimator = BandwidthEstimator::new(1);
+        estimator.sent_bitrate = sent_bitrate;
+        estimator.loss_update -= LOSS_UPDATE_INTERVAL;
+        estimator
+    }
+
+    fn tick(estimator: &mut BandwidthEstimator, received: u16, lost: u16) {
+        estimator.loss_update -= LOSS_UPDATE_INTERVAL;
+        estimator.on_transport_cc(&feedback(received, lost));
+    }
+
+    #[test]
+    fn test_no_budget_without_feedback() {
+        assert_eq!(BandwidthEstimator::new(1).video_budget(), None);
+    }
+
+    #[test]
+    fn test_high_loss_backs_off() {
+        let mut estimator = estimator(1_000_000);
+        tick(&mut estimator, 80, 20);
+        assert_eq!(estimator.video_budget(), Some(900_000));
+        tick(&mut estimator, 80, 20);
+        assert_eq!(estimator.video_budget(), Some(810_000));
+    }
+
+    #[test]
+    fn test_moderate_loss_holds() {
+        let mut estimator = estimator(1_000_000);
+        tick(&mut estimator, 95, 5);
+        assert_eq!(estimator.video_budget(), Some(1_000_000));
+    }
+
+    #[test]
+    fn test_growth_not_bound_to_sent_bitrate() {
+        let mut estimator = estimator(100_000);
+        for _ in 0..20 {
+            tick(&mut estimator, 100, 0);
+        }
+        assert!(estimator.video_budget().unwrap() > 400_000);
+    }
+
+    #[test]
+    fn test_remb_bounds_loss_based() {
+        let mut estimator = estimator(1_000_000);
+        tick(&mut estimator, 100, 0);
+        estimator.remb = Some((600_000, Instant::now()));
+        assert_eq!(estimator.video_budget(), Some(600_000));
+    }
+
+    #[test]
+    fn test_budget_split_between_active_senders() {
+        let mut estimator = BandwidthEstimator::new(3);
+        estimator.remb = Some((900_000, Instant::now()));
+        assert_eq!(estimator.video_budget(), Some(300_000));
+        estimator.on_video_paused(true);
+        assert_eq!(estimator.video_budget(), Some(450_000));
+    }
+
+    fn layers() -> Vec<(String, u64)> {
+        vec![
+            ("q".to_owned(), 150_000),
+            ("h".to_owned(), 500_000),
+            ("f".to_owned(), 1_500_000),
+        ]
+    }
+
+    #[test]
+    fn test_down_after_ticks() {
+        let mut selector = LayerSelector::default();
+        assert_eq!(selector.next_layer(&layers(), "f", 400_000), None);
+        assert_eq!(
+            selector.next_layer(&layers(), "f", 400_000),
+            Some("q".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_down_to_lowest_under_any_budget() {
+        let mut selector = LayerSelector::default();
+        selector.next_layer(&layers(), "h", 50_000);
+        assert_eq!(
+            selector.next_layer(&layers(), "h", 50_000),
+            Some("q".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_up_one_layer_after_ticks() {
+        let mut selector = LayerSelector::default();
+        for _ in 1..UP_TICKS {
+            assert_eq!(selector.next_layer(&layers(), "q", 10_000_000), None);
+        }
+        assert_eq!(
+            selector.next_layer(&layers(), "q", 10_000_000),
+            Some("h".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_up_needs_margin() {
+        let mut selector = LayerSelector::default();
+        for _ in 0..UP_TICKS * 2 {
+            assert_eq!(selector.next_layer(&layers(), "q", 560_000), None);
+        }
+    }
+
+    #[test]
+    fn test_hysteresis_resets() {
+        let mut selector = LayerSelector::default();
+        for _ in 1..UP_TICKS {
+            selector.next_layer(&layers(), "q", 10_000_000);
+        }
+        // a tick without headroom starts the count over
+        assert_eq!(selector.next_layer(&layers(), "q", 300_000), None);
+        for _ in 1..UP_TICKS {
+            assert_eq!(selector.next_layer(&layers(), "q", 10_000_000), None);
+        }
+        assert_eq!(
+            selector.next_layer(&layers(), "q", 10_000_000),
+            Some("h".to_owned())
+        );
+
+        let mut selector = LayerSelector::default();
+        selector.next_layer(&layers(), "f", 400_000);
+        assert_eq!(selector.next_layer(&layers(), "f", 2_000_000), None);
+        assert_eq!(selector.next_layer(&layers(), "f", 400_000), None);
+    }
+
+    #[test]
+    fn test_down_on_loss_and_back_up() {
+        let mut estimator = estimator(1_600_000);
+        let mut selector = LayerSelector::default();
+        let mut current = "f".to_owned();
+        let mut step = |estimator: &mut BandwidthEstimator, current: &mut String, lost| {
+            tick(estimator, 100 - lost, lost);
+            let budget = estimator.video_budget().unwrap();
+            let next = selector.next_layer(&layers(), current, budget);
+            if let Some(next) = &next {
+                *current = next.clone();
+            }
+            next
+        };
+
+        for _ in 0..20 {
+            if current == "q" {
+                break;
+            }
+            step(&mut estimator, &mut current, 40);
+        }
+        assert_eq!(current, "q");
+
+        // the sender on q sends little, the estimate grows anyway
+        estimator.sent_bitrate = 150_000;
+        let mut switched = None;
+        for _ in 0..30 {
+            switched = step(&mut estimator, &mut current, 0);
+            if switched.is_some() {
+                break;
+            }
+        }
+        assert_eq!(switched, Some("h".to_owned()));
+    }
+
+    #[test]
+    fn test_unmeasured_layers_skipped() {
+        let mut selector = LayerSelector::default();
+        let layers = vec![("q".to_owned(), 150_000), ("h".to_owned(), 0)];
+        for _ in 0..UP_TICKS * 2 {
+            assert_eq!(selector.next_layer(&layers, "q", 10_000_000), None);
+        }
+    }
+}
@@ -6,8 +6,8 @@ use crate::{error::AppError, Result};
 
 #[derive(Clone)]
 pub struct Client {
-    _name: String,
-    _id: i32,
+    name: String,
+    id: i32,
     token: u32,
     stream_map: Arc<RwLock<Vec<String>>>,
 }
@@ -15,13 +15,21 @@ pub struct Client {
 impl Client {
     pub async fn new(id: i32, token: u32, name: String) -> Result<Self> {
         Ok(Self {
-            _name: name,
-            _id: id,
+            name,
+            id,
             token: token,
             stream_map: Arc::new(RwLock::new(Vec::new())),
         })
     }
 
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn check_token(&self, token: u32) -> bool {
         self.token == token
     }
@@ -40,12 +48,9 @@ impl Client {
 
     pub async fn remove_stream(&mut self, stream: String) -> Result<()> {
         let mut streams = self.stream_map.write().await;
-        if streams.contains(&stream) {
-            let index = streams.binary_search(&stream);
-            if index.is_ok() {
-                streams.remove(index.unwrap());
-                return Ok(());
-            }
+        if let Some(index) = streams.iter().position(|s| *s == stream) {
+            streams.remove(index);
+            return Ok(());
         }
 
         Err(AppError::stream_not_found(stream.clone()))
@@ -0,0 +1,123 @@
+use super::keyframe::is_keyframe;
+use super::track::ForwardData;
+
+// a GOP longer than this is dropped until the next keyframe
+const GOP_CACHE_MAX: usize = 1024;
+
+/// The packets of a publisher video track since its last keyframe, replayed
+/// to new subscribers so that they start without waiting for a keyframe.
+pub(crate) struct GopCache {
+    mime_type: String,
+    packets: Vec<ForwardData>,
+}
+
+impl GopCache {
+    pub(crate) fn new(mime_type: String) -> Self {
+        Self {
+            mime_type,
+            packets: Vec::new(),
+        }
+    }
+
+    /// A cache continuing `packets`, which start with a keyframe.
+    pub(crate) fn with_packets(mime_type: String, packets: Vec<ForwardData>) -> Self {
+        Self { mime_type, packets }
+    }
+
+    pub(crate) fn push(&mut self, packet: ForwardData) {
+        // the packets of a keyframe share its timestamp, an H264 keyframe
+        // starts with SPS and PPS before the IDR
+        let starts_gop = is_keyframe(&self.mime_type, &packet.payload)
+            && self
+                .packets
+                .first()
+                .is_none_or(|first| first.header.timestamp != packet.header.timestamp);
+        if starts_gop {
+            self.packets.clear();
+        } else if self.packets.is_empty() {
+            return;
+        } else if self.packets.len() >= GOP_CACHE_MAX {
+            self.packets.clear();
+            return;
+        }
+        self.packets.push(packet);
+    }
+
+    pub(crate) fn packets(&self) -> Vec<ForwardData> {
+        self.packets.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use webrtc::rtp::header::Header;
+    use webrtc::rtp::packet::Packet;
+
+    use super::*;
+
+    const KEYFRAME: &[u8] = &[0x10, 0x00];
+    const DELTA: &[u8] = &[0x10, 0x01];
+
+    fn packet(sequence_number: u16, timestamp: u32, payload: &'static [u8]) -> ForwardData {
+        Arc::new(Packet {
+            header: Header {
+                sequence_number,
+                timestamp,
+                ..Default::default()
+            },
+            payload: payload.into(),
+        })
+    }
+
+    fn sequence_numbers(cache: &GopCache) -> Vec<u16> {
+        cache
+            .packets()
+            .iter()
+            .map(|packet| packet.header.sequence_number)
+            .collect()
+    }
+
+    #[test]
+    fn test_starts_at_keyframe() {
+        let mut cache = GopCache::new("video/VP8".to_owned());
+        cache.push(packet(1, 0, DELTA));
+        assert!(cache.packets().is_empty());
+        cache.push(packet(2, 3000, KEYFRAME));
+        cache.push(packet(3, 6000, DELTA));
+        assert_eq!(sequence_numbers(&cache), [2, 3]);
+    }
+
+    #[test]
+    fn test_reset_on_keyframe() {
+        let mut cache = GopCache::new("video/VP8".to_owned());
+        cache.push(packet(1, 0, KEYFRAME));
+        cache.push(packet(2, 3000, DELTA));
+        cache.push(packet(3, 6000, KEYFRAME));
+        assert_eq!(sequence_numbers(&cache), [3]);
+    }
+
+    #[test]
+    fn test_keyframe_packets_kept_together() {
+        // an H264 keyframe: SPS, PPS then IDR, sharing a timestamp
+        let mut cache = GopCache::new("video/H264".to_owned());
+        cache.push(packet(1, 3000, &[0x67]));
+        cache.push(packet(2, 3000, &[0x68]));
+        cache.push(packet(3, 3000, &[0x65]));
+        cache.push(packet(4, 6000, &[0x41]));
+        assert_eq!(sequence_numbers(&cache), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_dropped_when_too_long() {
+        let mut cache = GopCache::new("video/VP8".to_owned());
+        cache.push(packet(0, 0, KEYFRAME));
+        for sequence_number in 1..=GOP_CACHE_MAX as u16 {
+            cache.push(packet(sequence_number, sequence_number as u32, DELTA));
+        }
+        assert!(cache.packets().is_empty());
+        cache.push(packet(2000, 2000, DELTA));
+        assert!(cache.packets().is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc, OnceLock};
+
+use tokio::net::UdpSocket;
+use tracing::info;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::ice::udp_mux::{UDPMuxDefault, UDPMuxParams};
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+
+use crate::config::{Ice, MulticastDns, Nat1To1CandidateType};
+
+static SETTING_ENGINE_BUILDER: OnceLock<SettingEngineBuilder> = OnceLock::new();
+
+/// Builds the `SettingEngine` of every peer from the `[ice]` config. The UDP mux
+/// socket is bound once here and shared by all peers of all rooms.
+pub struct SettingEngineBuilder {
+    ice: Ice,
+    udp_mux: Option<Arc<UDPMuxDefault>>,
+}
+
+impl SettingEngineBuilder {
+    async fn new(ice: Ice) -> anyhow::Result<Self> {
+        let udp_mux = match ice.udp_mux_listen {
+            Some(listen) => {
+                let socket = UdpSocket::bind(listen).await?;
+                info!("ICE UDP mux listening on {}", socket.local_addr()?);
+                Some(UDPMuxDefault::new(UDPMuxParams::new(socket)))
+            }
+            None => None,
+        };
+        Ok(Self { ice, udp_mux })
+    }
+
+    pub fn build(&self) -> webrtc::error::Result<SettingEngine> {
+        let mut s = SettingEngine::default();
+        s.detach_data_channels();
+
+        if let Some(udp_mux) = &self.udp_mux {
+            s.set_udp_network(UDPNetwork::Muxed(udp_mux.clone()));
+        } else if let (Some(min), Some(max)) = (self.ice.udp_port_min, self.ice.udp_port_max) {
+            s.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(min, max)?));
+        }
+
+        if !self.ice.nat_1to1_ips.is_empty() {
+            let candidate_type = match self.ice.nat_1to1_candidate_type {
+                Nat1To1CandidateType::Host => RTCIceCandidateType::Host,
+                Nat1To1CandidateType::Srflx => RTCIceCandidateType::Srflx,
+            };
+            let ips = self
+                .ice
+                .nat_1to1_ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect();
+            s.set_nat_1to1_ips(ips, candidate_type);
+        }
+
+        if !self.ice.interfaces.is_empty() {
+            let interfaces = self.ice.interfaces.clone();
+            s.set_interface_filter(Box::new(move |name: &str| {
+                interfaces.iter().any(|interface| interface == name)
+            }));
+        }
+        // Every host candidate of a muxed agent reads from the same socket, and
+        // webrtc-ice drops packets read by a candidate of the other IP family.
+        // So only gather the address (or family) the mux socket is bound to.
+        let mux_listen = self.udp_mux.as_ref().and(self.ice.udp_mux_listen);
+        if !self.ice.ips.is_empty() || mux_listen.is_some() {
+            let ips = self.ice.ips.clone();
+            s.set_ip_filter(Box::new(move |ip| {
+                let allowed = ips.is_empty() || ips.contains(&ip);
+                let muxed = mux_listen.is_none_or(|listen| {
+                    if listen.ip().is_unspecified() {
+                        listen.is_ipv4() == ip.is_ipv4()
+                    } else {
+                        listen.ip() == ip
+                    }
+                });
+                allowed && muxed
+            }));
+        }
+
+        s.set_ice_multicast_dns_mode(match self.ice.mdns {
+            MulticastDns::Disabled => MulticastDnsMode::Disabled,
+            MulticastDns::QueryOnly => MulticastDnsMode::QueryOnly,
+            MulticastDns::QueryAndGather => MulticastDnsMode::QueryAndGather,
+        });
+
+        Ok(s)
+    }
+}
+
+/// Must be called once at startup, before any peer is created.
+pub async fn init(ice: Ice) -> anyhow::Result<()> {
+    let builder = SettingEngineBuilder::new(ice).await?;
+    if SETTING_ENGINE_BUILDER.set(builder).is_err() {
+        return Err(anyhow::anyhow!("ice settings already initialized"));
+    }
+    Ok(())
+}
+
+pub fn setting_engine() -> webrtc::error::Result<SettingEngine> {
+    SETTING_ENGINE_BUILDER
+        .get()
+        .expect("ice settings not initialized")
+        .build()
+}
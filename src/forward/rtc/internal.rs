@@ -3,15 +3,17 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::vec;
 
-use crate::forward::rtc::message::ForwardInfo;
+use crate::forward::rtc::message::{ForwardInfo, TrackInfo};
 use crate::result::Result;
 use chrono::Utc;
 
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{debug, info};
-use webrtc::api::interceptor_registry::register_default_interceptors;
-use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_OPUS, MIME_TYPE_VP8};
-use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::interceptor_registry::{
+    configure_rtcp_reports, configure_twcc, register_default_interceptors,
+};
+use webrtc::api::media_engine::MediaEngine;
 use webrtc::api::APIBuilder;
 use webrtc::data::data_channel::DataChannel;
 use webrtc::data_channel::RTCDataChannel;
@@ -21,25 +23,28 @@ use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::rtp_transceiver::rtp_codec::{
-    RTCRtpCodecCapability, RTCRtpHeaderExtensionCapability, RTPCodecType,
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability, RTPCodecType,
 };
 use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
 use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
-use webrtc::rtp_transceiver::{RTCPFeedback, RTCRtpTransceiverInit};
-use webrtc::sdp::extmap::{SDES_MID_URI, SDES_RTP_STREAM_ID_URI};
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+use webrtc::sdp::extmap::{AUDIO_LEVEL_URI, SDES_MID_URI, SDES_RTP_STREAM_ID_URI};
 
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_remote::TrackRemote;
 
 use crate::error::AppError;
+use crate::metrics;
 
 use super::get_peer_id;
+use super::ice;
 use super::media::MediaInfo;
 use super::message::{ForwardEvent, ForwardEventType};
 use super::publish::PublishRTCPeerConnection;
+use super::recorder::{RecordConfig, Recorder};
 use super::rtcp::RtcpMessage;
 use super::subscribe::SubscribeRTCPeerConnection;
-use super::track::PublishTrackRemote;
+use super::track::{sender_track_id, sort_tracks, source_tracks, PublishTrackRemote};
 
 const MESSAGE_SIZE: usize = 1024 * 16;
 
@@ -63,15 +68,31 @@ pub(crate) struct PeerForwardInternal {
     publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
     publish_tracks_change: (broadcast::Sender<()>, broadcast::Receiver<()>),
     publish_rtcp_channel: PublishRtcpChannel,
+    // the kinds the publisher muted for every subscriber
+    publish_muted: watch::Sender<Vec<RTPCodecType>>,
     subscribe_group: RwLock<Vec<SubscribeRTCPeerConnection>>,
     user_sender_map: Arc<RwLock<HashMap<u32, broadcast::Sender<Vec<u8>>>>>,
     data_channel_forward: DataChannelForward,
     ice_server: Vec<RTCIceServer>,
+    // ms between two keyframe requests sent to the publisher, per track
+    keyframe_request_interval: u64,
+    // replay the packets since the last keyframe to new subscribers
+    gop_cache: bool,
+    record: RecordConfig,
+    recorder: RwLock<Option<Recorder>>,
+    // the file publisher stops when it is dropped
+    player: RwLock<Option<watch::Sender<()>>>,
     event_sender: broadcast::Sender<ForwardEvent>,
 }
 
 impl PeerForwardInternal {
-    pub(crate) fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub(crate) fn new(
+        stream: impl ToString,
+        ice_server: Vec<RTCIceServer>,
+        keyframe_request_interval: u64,
+        gop_cache: bool,
+        record: RecordConfig,
+    ) -> Self {
         let publish_tracks_change = broadcast::channel(100);
         let data_channel_forward_channel = broadcast::channel(100);
         let data_channel_forward = DataChannelForward {
@@ -89,10 +110,16 @@ impl PeerForwardInternal {
             publish_tracks: Arc::new(RwLock::new(Vec::new())),
             publish_tracks_change,
             publish_rtcp_channel: broadcast::channel(100),
+            publish_muted: watch::Sender::new(vec![]),
             subscribe_group: RwLock::new(Vec::new()),
             user_sender_map: Arc::new(RwLock::new(HashMap::new())),
             data_channel_forward,
             ice_server,
+            keyframe_request_interval,
+            gop_cache,
+            record,
+            recorder: RwLock::new(None),
+            player: RwLock::new(None),
             event_sender,
         }
     }
@@ -115,26 +142,102 @@ impl PeerForwardInternal {
                 .as_ref()
                 .map(|publish| publish.info()),
             subscribe_session_infos,
+            publish_tracks: self.publish_track_infos().await,
+            recording: self.recorder.read().await.is_some(),
         }
     }
 
-    // This function has not used currently, but seems worth to keep retain
-    // pub(crate) async fn remove_peer(&self, id: String) -> Result<bool> {
-    //     let publish = self.publish.read().await;
-    //     if publish.is_some() && publish.as_ref().unwrap().id == id {
-    //         publish.as_ref().unwrap().peer.close().await?;
-    //         return Ok(true);
-    //     }
-
-    //     let subscribe_group = self.subscribe_group.read().await;
-    //     for subscribe in subscribe_group.iter() {
-    //         if subscribe.id == id {
-    //             subscribe.peer.close().await?;
-    //             break;
-    //         }
-    //     }
-    //     Ok(false)
-    // }
+    async fn publish_track_infos(&self) -> Vec<TrackInfo> {
+        let publish_tracks = self.publish_tracks.read().await;
+        let mut infos: Vec<TrackInfo> = vec![];
+        for publish_track in publish_tracks.iter() {
+            match infos.last_mut() {
+                Some(info) if info.kind == publish_track.kind && info.mid == publish_track.mid => {
+                    info.rids.push(publish_track.rid.clone());
+                }
+                _ => infos.push(TrackInfo {
+                    kind: publish_track.kind,
+                    mid: publish_track.mid.clone(),
+                    id: publish_track.track.id(),
+                    stream_id: publish_track.track.stream_id(),
+                    rids: vec![publish_track.rid.clone()],
+                    codec: publish_track.track.codec().capability.mime_type,
+                }),
+            }
+        }
+        infos
+    }
+
+    /// Returns the user who owns the publish or subscribe session.
+    pub(crate) async fn session_owner(&self, id: String) -> Option<u32> {
+        let publish = self.publish.read().await;
+        if let Some(publish) = publish.as_ref() {
+            if publish.id == id {
+                return Some(publish.user_id);
+            }
+        }
+        drop(publish);
+
+        let subscribe_group = self.subscribe_group.read().await;
+        subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| subscribe.user_id)
+    }
+
+    /// The user who publishes the stream.
+    pub(crate) async fn publish_user(&self) -> Option<u32> {
+        let publish = self.publish.read().await;
+        publish.as_ref().map(|publish| publish.user_id)
+    }
+
+    /// True when the publisher sends video.
+    pub(crate) async fn publish_has_video(&self) -> bool {
+        let publish = self.publish.read().await;
+        publish
+            .as_ref()
+            .is_some_and(|publish| publish.media_info.video_transceiver.0 > 0)
+    }
+
+    /// The subscribe sessions with the user who owns each.
+    pub(crate) async fn subscribe_sessions(&self) -> Vec<(String, u32)> {
+        let subscribe_group = self.subscribe_group.read().await;
+        subscribe_group
+            .iter()
+            .map(|subscribe| (subscribe.id.clone(), subscribe.user_id))
+            .collect()
+    }
+
+    // The session is removed before the peer is closed, so that the cleanup does
+    // not wait for the state change callback. The `Closed` callback which follows
+    // finds nothing left to remove.
+    pub(crate) async fn remove_peer(&self, id: String) -> Result<bool> {
+        let publish = self.publish.read().await;
+        let target = publish
+            .as_ref()
+            .filter(|publish| publish.id == id)
+            .map(|publish| (publish.user_id, publish.peer.clone()));
+        drop(publish);
+        if let Some((user_id, peer)) = target {
+            self.remove_publish(user_id, peer.clone()).await?;
+            peer.close().await?;
+            return Ok(true);
+        }
+
+        let subscribe_group = self.subscribe_group.read().await;
+        let target = subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| (subscribe.user_id, subscribe.peer.clone()));
+        drop(subscribe_group);
+        if let Some((user_id, peer)) = target {
+            self.remove_subscribe(user_id, peer.clone()).await?;
+            peer.close().await?;
+            return Ok(false);
+        }
+
+        Err(AppError::session_not_found("not found session"))
+    }
 
     pub(crate) async fn close(&self) -> Result<()> {
         let publish = self.publish.read().await;
@@ -221,6 +324,12 @@ impl PeerForwardInternal {
             if n == 0 {
                 break;
             }
+            metrics::DATA_CHANNEL_MESSAGES
+                .with_label_values(&[metrics::DIRECTION_IN])
+                .inc();
+            metrics::DATA_CHANNEL_BYTES
+                .with_label_values(&[metrics::DIRECTION_IN])
+                .inc_by(n as u64);
 
             let is_broadcast = buffer[1..5] == buffer[5..9];
             if is_broadcast {
@@ -249,12 +358,29 @@ impl PeerForwardInternal {
         d: Arc<DataChannel>,
         mut user_receiver: broadcast::Receiver<Vec<u8>>,
     ) {
-        while let Ok(msg) = user_receiver.recv().await {
+        loop {
+            let msg = match user_receiver.recv().await {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(n)) => {
+                    metrics::BROADCAST_LAGGED
+                        .with_label_values(&[metrics::CHANNEL_DATA_CHANNEL])
+                        .inc_by(n);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let len = msg.len();
             if let Err(_err) = d.write(&msg.into()).await {
                 // Maybe stream has been closed
                 // info!("write data channel err: {}", _err);
                 return;
             };
+            metrics::DATA_CHANNEL_MESSAGES
+                .with_label_values(&[metrics::DIRECTION_OUT])
+                .inc();
+            metrics::DATA_CHANNEL_BYTES
+                .with_label_values(&[metrics::DIRECTION_OUT])
+                .inc_by(len as u64);
         }
     }
 
@@ -263,7 +389,17 @@ impl PeerForwardInternal {
         user_sender: broadcast::Sender<Vec<u8>>,
         mut group_receiver: broadcast::Receiver<Vec<u8>>,
     ) {
-        while let Ok(msg) = group_receiver.recv().await {
+        loop {
+            let msg = match group_receiver.recv().await {
+                Ok(msg) => msg,
+                Err(RecvError::Lagged(n)) => {
+                    metrics::BROADCAST_LAGGED
+                        .with_label_values(&[metrics::CHANNEL_DATA_CHANNEL])
+                        .inc_by(n);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
             let from = u32::from_be_bytes([msg[4], msg[3], msg[2], msg[1]]);
             if from == id {
                 continue; // This message was sent from own
@@ -296,6 +432,27 @@ impl PeerForwardInternal {
         }
     }
 
+    pub(crate) fn send_server_event(&self, frame: Vec<u8>) {
+        if let Err(err) = self.data_channel_forward.sender.send(frame) {
+            debug!("[{}] send server event err: {}", self.stream, err);
+        }
+    }
+
+    /// The publisher's user id and the loudest level of its audio tracks,
+    /// `None` without a publisher sending audio levels.
+    pub(crate) async fn audio_level(&self) -> Option<(u32, u8)> {
+        let user_id = self.publish.read().await.as_ref()?.user_id;
+        if self.publish_muted.borrow().contains(&RTPCodecType::Audio) {
+            return None;
+        }
+        let publish_tracks = self.publish_tracks.read().await;
+        let level = publish_tracks
+            .iter()
+            .filter_map(|track| track.audio_level())
+            .max()?;
+        Some((user_id, level))
+    }
+
     pub(crate) async fn publish_is_some(&self) -> bool {
         let publish = self.publish.read().await;
         publish.is_some()
@@ -308,7 +465,7 @@ impl PeerForwardInternal {
                 == RTCPeerConnectionState::Connected
     }
 
-    pub(crate) async fn set_publish(&self, peer: Arc<RTCPeerConnection>) -> Result<()> {
+    pub(crate) async fn set_publish(&self, id: u32, peer: Arc<RTCPeerConnection>) -> Result<()> {
         {
             let mut publish = self.publish.write().await;
             if publish.is_some() {
@@ -318,13 +475,22 @@ impl PeerForwardInternal {
             }
             let publish_peer = PublishRTCPeerConnection::new(
                 self.stream.clone(),
+                id,
                 peer.clone(),
-                self.publish_rtcp_channel.0.subscribe(),
+                (
+                    self.publish_rtcp_channel.0.subscribe(),
+                    self.keyframe_request_interval,
+                ),
             )
             .await?;
             info!("[{}] [publish] set {}", self.stream, publish_peer.id);
             *publish = Some(publish_peer);
         }
+        if self.record.auto_start {
+            if let Err(err) = self.start_record().await {
+                info!("[{}] [publish] record err: {:?}", self.stream, err);
+            }
+        }
         {
             let mut publish_leave_time = self.publish_leave_time.write().await;
             *publish_leave_time = 0;
@@ -350,6 +516,10 @@ impl PeerForwardInternal {
             publish_tracks.clear();
             let _ = self.publish_tracks_change.0.send(());
         }
+        self.publish_muted.send_replace(vec![]);
+        // a recording belongs to its publisher
+        *self.recorder.write().await = None;
+        *self.player.write().await = None;
         {
             let mut publish_leave_time = self.publish_leave_time.write().await;
             *publish_leave_time = Utc::now().timestamp_millis();
@@ -360,20 +530,64 @@ impl PeerForwardInternal {
         user_sender_map.remove(&id);
         drop(user_sender_map);
 
+        self.notice_network_event(id, false);
         self.send_event(ForwardEventType::PublishDown, get_peer_id(&peer))
             .await;
         Ok(())
     }
 
+    pub(crate) async fn start_record(&self) -> Result<()> {
+        let user_id = self
+            .publish_user()
+            .await
+            .ok_or(AppError::publish_not_ready("publish is none"))?;
+        let mut recorder = self.recorder.write().await;
+        if recorder.is_some() {
+            return Err(AppError::stream_already_exists(
+                "stream is already recording",
+            ));
+        }
+        *recorder = Some(Recorder::start(
+            self.stream.clone(),
+            user_id,
+            self.record.clone(),
+            (
+                self.publish_tracks.clone(),
+                self.publish_tracks_change.0.subscribe(),
+            ),
+            self.publish_rtcp_channel.0.clone(),
+        ));
+        Ok(())
+    }
+
+    pub(crate) async fn stop_record(&self) -> Result<()> {
+        let mut recorder = self.recorder.write().await;
+        if recorder.take().is_none() {
+            return Err(AppError::stream_not_found("stream is not recording"));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn set_player(&self, stop: watch::Sender<()>) {
+        *self.player.write().await = Some(stop);
+    }
+
+    pub(crate) async fn stop_playback(&self) -> Result<()> {
+        let mut player = self.player.write().await;
+        if player.take().is_none() {
+            return Err(AppError::stream_not_found(
+                "stream is not played from files",
+            ));
+        }
+        Ok(())
+    }
+
     pub(crate) async fn new_virtual_publish_peer(&self) -> Result<Arc<RTCPeerConnection>> {
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
-        let mut s = SettingEngine::default();
-        s.detach_data_channels();
         let mut registry = Registry::new();
         registry = register_default_interceptors(registry, &mut m)?;
-        let mut s = SettingEngine::default();
-        s.detach_data_channels();
+        let s = ice::setting_engine()?;
         let api = APIBuilder::new()
             .with_media_engine(m)
             .with_interceptor_registry(registry)
@@ -391,9 +605,6 @@ impl PeerForwardInternal {
         &self,
         media_info: MediaInfo,
     ) -> Result<Arc<RTCPeerConnection>> {
-        if media_info.video_transceiver.0 > 1 || media_info.audio_transceiver.0 > 1 {
-            return Err(AppError::throw("sendonly is more than 1"));
-        }
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
         m.register_header_extension(
@@ -410,10 +621,17 @@ impl PeerForwardInternal {
             RTPCodecType::Video,
             Some(RTCRtpTransceiverDirection::Recvonly),
         )?;
+        // for active speaker detection
+        m.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: AUDIO_LEVEL_URI.to_owned(),
+            },
+            RTPCodecType::Audio,
+            Some(RTCRtpTransceiverDirection::Recvonly),
+        )?;
         let mut registry = Registry::new();
         registry = register_default_interceptors(registry, &mut m)?;
-        let mut s = SettingEngine::default();
-        s.detach_data_channels();
+        let s = ice::setting_engine()?;
         let api = APIBuilder::new()
             .with_media_engine(m)
             .with_interceptor_registry(registry)
@@ -425,10 +643,10 @@ impl PeerForwardInternal {
         };
         let peer = Arc::new(api.new_peer_connection(config).await?);
         let mut transceiver_kinds = vec![];
-        if media_info.video_transceiver.0 > 0 {
+        for _ in 0..media_info.video_transceiver.0 {
             transceiver_kinds.push(RTPCodecType::Video);
         }
-        if media_info.audio_transceiver.0 > 0 {
+        for _ in 0..media_info.audio_transceiver.0 {
             transceiver_kinds.push(RTPCodecType::Audio);
         }
         for kind in transceiver_kinds {
@@ -455,11 +673,12 @@ impl PeerForwardInternal {
 
     pub async fn publish_svc_rids(&self) -> Result<Vec<String>> {
         let publish_tracks = self.publish_tracks.read().await;
-        let rids = publish_tracks
-            .iter()
-            .filter(|t| t.kind == RTPCodecType::Video)
-            .map(|t| t.rid.clone())
-            .collect::<Vec<_>>();
+        let mut rids: Vec<String> = vec![];
+        for track in publish_tracks.iter() {
+            if track.kind == RTPCodecType::Video && !rids.contains(&track.rid) {
+                rids.push(track.rid.clone());
+            }
+        }
         Ok(rids)
     }
 
@@ -467,23 +686,60 @@ impl PeerForwardInternal {
         let subscribe_group = self.subscribe_group.read().await;
         for subscribe in subscribe_group.iter() {
             if subscribe.id == id {
-                subscribe.select_kind_rid(kind, rid)?;
-                break;
+                return subscribe.select_kind_rid(kind, rid);
             }
         }
-        Ok(())
+        Err(AppError::session_not_found("not found session"))
+    }
+
+    /// Enables or disables the tracks of `kind` for the session. For the publish
+    /// session this mutes the tracks for every subscriber, and returns true.
+    pub(crate) async fn change_resource(
+        &self,
+        id: String,
+        kind: RTPCodecType,
+        enabled: bool,
+    ) -> Result<bool> {
+        let publish = self.publish.read().await;
+        if publish.as_ref().is_some_and(|publish| publish.id == id) {
+            self.publish_muted.send_modify(|muted| {
+                muted.retain(|muted_kind| *muted_kind != kind);
+                if !enabled {
+                    muted.push(kind);
+                }
+            });
+            info!("[{}] [publish] {} enabled: {}", self.stream, kind, enabled);
+            return Ok(true);
+        }
+        drop(publish);
+
+        let subscribe_group = self.subscribe_group.read().await;
+        for subscribe in subscribe_group.iter() {
+            if subscribe.id == id {
+                subscribe.change_resource(kind, enabled)?;
+                return Ok(false);
+            }
+        }
+        Err(AppError::session_not_found("not found session"))
     }
 
     pub(crate) async fn publish_track_up(
         &self,
         peer: Arc<RTCPeerConnection>,
+        mid: String,
         track: Arc<TrackRemote>,
     ) -> Result<()> {
-        let publish_track_remote =
-            PublishTrackRemote::new(self.stream.clone(), get_peer_id(&peer), track).await;
+        let publish_track_remote = PublishTrackRemote::new(
+            self.stream.clone(),
+            get_peer_id(&peer),
+            mid,
+            track,
+            self.gop_cache,
+        )
+        .await;
         let mut publish_tracks = self.publish_tracks.write().await;
         publish_tracks.push(publish_track_remote);
-        publish_tracks.sort_by(|a, b| a.rid.cmp(&b.rid));
+        sort_tracks(&mut publish_tracks);
         let _ = self.publish_tracks_change.0.send(());
         Ok(())
     }
@@ -518,20 +774,27 @@ impl PeerForwardInternal {
 impl PeerForwardInternal {
     pub(crate) async fn new_subscription_peer(
         &self,
+        id: u32,
         media_info: MediaInfo,
     ) -> Result<Arc<RTCPeerConnection>> {
         if !self.publish_is_some().await {
-            return Err(AppError::throw("publish is none"));
-        }
-        if media_info.video_transceiver.1 > 1 || media_info.audio_transceiver.1 > 1 {
-            return Err(AppError::throw("recvonly is more than 1"));
+            return Err(AppError::publish_not_ready("publish is none"));
         }
         let mut m = MediaEngine::default();
-        m.register_default_codecs()?;
+        let video_codecs = self
+            .subscription_codecs(&mut m, &media_info, RTPCodecType::Video)
+            .await?;
+        let audio_codecs = self
+            .subscription_codecs(&mut m, &media_info, RTPCodecType::Audio)
+            .await?;
+        // transport-cc in both directions, so that the subscriber sends the
+        // feedback the simulcast layer is picked from. No NACK responder, the
+        // NACKs are answered from the publisher's tracks (see `answer_nacks`),
+        // and the subscriber's codecs already carry its nack feedback.
         let mut registry = Registry::new();
-        registry = register_default_interceptors(registry, &mut m)?;
-        let mut s = SettingEngine::default();
-        s.detach_data_channels();
+        registry = configure_rtcp_reports(registry);
+        registry = configure_twcc(registry, &mut m)?;
+        let s = ice::setting_engine()?;
         let api = APIBuilder::new()
             .with_media_engine(m)
             .with_interceptor_registry(registry)
@@ -542,21 +805,26 @@ impl PeerForwardInternal {
             ..Default::default()
         };
         let peer = Arc::new(api.new_peer_connection(config).await?);
+        let mut video_senders = vec![];
+        for (index, codec) in video_codecs.into_iter().enumerate() {
+            video_senders.push(Self::new_sender(&peer, RTPCodecType::Video, index, codec).await?);
+        }
+        let mut audio_senders = vec![];
+        for (index, codec) in audio_codecs.into_iter().enumerate() {
+            audio_senders.push(Self::new_sender(&peer, RTPCodecType::Audio, index, codec).await?);
+        }
         {
             let s = SubscribeRTCPeerConnection::new(
                 self.stream.clone(),
+                id,
                 peer.clone(),
                 self.publish_rtcp_channel.0.clone(),
                 (
                     self.publish_tracks.clone(),
                     self.publish_tracks_change.0.clone(),
+                    self.publish_muted.subscribe(),
                 ),
-                (
-                    Self::new_sender(&peer, RTPCodecType::Video, media_info.video_transceiver.1)
-                        .await?,
-                    Self::new_sender(&peer, RTPCodecType::Audio, media_info.audio_transceiver.1)
-                        .await?,
-                ),
+                (video_senders, audio_senders),
             )
             .await;
             self.subscribe_group.write().await.push(s);
@@ -568,81 +836,132 @@ impl PeerForwardInternal {
         Ok(peer)
     }
 
+    // The codec of every track of `kind` the publisher sends, in transceiver order.
+    async fn publish_codecs(&self, kind: RTPCodecType) -> Vec<RTCRtpCodecCapability> {
+        let publish = self.publish.read().await;
+        let (count, negotiated) = match publish.as_ref() {
+            Some(publish) => (publish.track_count(kind), publish.codec(kind)),
+            None => return vec![],
+        };
+        drop(publish);
+        let publish_tracks = self.publish_tracks.read().await;
+        (0..count)
+            .filter_map(|index| {
+                source_tracks(&publish_tracks, kind, index)
+                    .first()
+                    .map(|publish_track| publish_track.track.codec().capability)
+                    .or(negotiated.clone())
+            })
+            .collect()
+    }
+
+    // Registers the codecs of `kind` the subscriber may negotiate: only the ones
+    // compatible with the publisher's codecs, so that its media is forwarded as is.
+    // Returns the codec of every sender of `kind`, one per recvonly transceiver.
+    async fn subscription_codecs(
+        &self,
+        m: &mut MediaEngine,
+        media_info: &MediaInfo,
+        kind: RTPCodecType,
+    ) -> Result<Vec<RTCRtpCodecCapability>> {
+        let recv_sender = match kind {
+            RTPCodecType::Video => media_info.video_transceiver.1,
+            _ => media_info.audio_transceiver.1,
+        } as usize;
+        let publish_codecs = self.publish_codecs(kind).await;
+        let mut codecs: Vec<RTCRtpCodecParameters> = vec![];
+        for publish_codec in publish_codecs.iter().take(recv_sender) {
+            let compatible_codecs = media_info.compatible_codecs(publish_codec);
+            if compatible_codecs.is_empty() {
+                let offered: Vec<_> = media_info
+                    .codecs(kind)
+                    .into_iter()
+                    .map(|codec| codec.capability.mime_type)
+                    .collect();
+                return Err(AppError::codec_not_supported_details(
+                    format!(
+                        "no {} codec of the offer is compatible with {} [{}] of the publisher",
+                        kind, publish_codec.mime_type, publish_codec.sdp_fmtp_line
+                    ),
+                    offered.join(", "),
+                ));
+            }
+            for codec in compatible_codecs {
+                if codecs.iter().any(|c| c.payload_type == codec.payload_type) {
+                    continue;
+                }
+                codecs.push(RTCRtpCodecParameters {
+                    capability: RTCRtpCodecCapability {
+                        rtcp_feedback: codec.capability.rtcp_feedback,
+                        ..publish_codec.clone()
+                    },
+                    payload_type: codec.payload_type,
+                    stats_id: String::new(),
+                });
+            }
+        }
+        if codecs.is_empty() {
+            // nothing will be forwarded, accept whatever the subscriber offers
+            codecs = media_info.codecs(kind);
+        }
+        let default_codec = codecs.first().map(|codec| codec.capability.clone());
+        for codec in codecs {
+            m.register_codec(codec, kind)?;
+        }
+        (0..recv_sender)
+            .map(|index| {
+                publish_codecs
+                    .get(index)
+                    .cloned()
+                    .or(default_codec.clone())
+                    .ok_or(AppError::codec_not_supported(format!(
+                        "the offer has no {} codec",
+                        kind
+                    )))
+            })
+            .collect()
+    }
+
     async fn new_sender(
         peer: &Arc<RTCPeerConnection>,
         kind: RTPCodecType,
-        recv_sender: u8,
-    ) -> Result<Option<Arc<RTCRtpSender>>> {
-        Ok(if recv_sender > 0 {
-            let sender = peer
-                .add_transceiver_from_kind(
-                    kind,
-                    Some(RTCRtpTransceiverInit {
-                        direction: RTCRtpTransceiverDirection::Sendonly,
-                        send_encodings: Vec::new(),
-                    }),
-                )
-                .await?
-                .sender()
-                .await;
+        index: usize,
+        codec: RTCRtpCodecCapability,
+    ) -> Result<Arc<RTCRtpSender>> {
+        let sender = peer
+            .add_transceiver_from_kind(
+                kind,
+                Some(RTCRtpTransceiverInit {
+                    direction: RTCRtpTransceiverDirection::Sendonly,
+                    send_encodings: Vec::new(),
+                }),
+            )
+            .await?
+            .sender()
+            .await;
 
-            let track = Arc::new(TrackLocalStaticRTP::new(
-                if kind == RTPCodecType::Video {
-                    RTCRtpCodecCapability {
-                        mime_type: MIME_TYPE_VP8.to_owned(),
-                        clock_rate: 90000,
-                        channels: 0,
-                        sdp_fmtp_line: "".to_owned(),
-                        rtcp_feedback: vec![
-                            RTCPFeedback {
-                                typ: "goog-remb".to_owned(),
-                                parameter: "".to_owned(),
-                            },
-                            RTCPFeedback {
-                                typ: "ccm".to_owned(),
-                                parameter: "fir".to_owned(),
-                            },
-                            RTCPFeedback {
-                                typ: "nack".to_owned(),
-                                parameter: "".to_owned(),
-                            },
-                            RTCPFeedback {
-                                typ: "nack".to_owned(),
-                                parameter: "pli".to_owned(),
-                            },
-                        ],
-                    }
-                } else {
-                    RTCRtpCodecCapability {
-                        mime_type: MIME_TYPE_OPUS.to_owned(),
-                        clock_rate: 48000,
-                        channels: 2,
-                        sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
-                        rtcp_feedback: vec![],
-                    }
-                },
-                "webrtc".to_string(),
-                format!("{}-{}", "webrtc", kind),
-            ));
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            codec,
+            sender_track_id(kind, index),
+            format!("{}-{}", "webrtc", kind),
+        ));
 
-            // ssrc for sdp
-            let _ = sender.replace_track(Some(track)).await;
-            info!(
-                "[{}] new sender , kind : {}, ssrc : {}",
-                get_peer_id(peer),
-                kind,
-                sender
-                    .get_parameters()
-                    .await
-                    .encodings
-                    .first()
-                    .unwrap()
-                    .ssrc
-            );
-            Some(sender)
-        } else {
-            None
-        })
+        // ssrc for sdp
+        let _ = sender.replace_track(Some(track)).await;
+        info!(
+            "[{}] new sender , kind : {}, index : {}, ssrc : {}",
+            get_peer_id(peer),
+            kind,
+            index,
+            sender
+                .get_parameters()
+                .await
+                .encodings
+                .first()
+                .unwrap()
+                .ssrc
+        );
+        Ok(sender)
     }
 
     pub async fn remove_subscribe(&self, id: u32, peer: Arc<RTCPeerConnection>) -> Result<()> {
@@ -670,9 +989,10 @@ impl PeerForwardInternal {
             user_sender_map.remove(&id);
             drop(user_sender_map);
 
+            self.notice_network_event(id, false);
             Ok(())
         } else {
-            Err(AppError::throw("not found session"))
+            Err(AppError::session_not_found("not found session"))
         }
     }
 
@@ -0,0 +1,463 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use webrtc::media::io::Writer;
+use webrtc::media::Error;
+use webrtc::rtp::codecs::vp8::Vp8Packet;
+use webrtc::rtp::codecs::vp9::Vp9Packet;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp::packetizer::Depacketizer;
+
+// the frame timestamps are written in RTP clock units
+const VIDEO_CLOCK_RATE: u32 = 90000;
+// temporal delimiter OBU, with its size field, starting every AV1 temporal unit
+const AV1_TEMPORAL_DELIMITER: [u8; 2] = [0x12, 0x00];
+const OBU_TYPE_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_TYPE_TILE_LIST: u8 = 8;
+
+type MediaResult<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Codec {
+    Vp8,
+    Vp9,
+    Av1,
+}
+
+/// Writes a VP8, VP9 or AV1 track to an IVF file, one frame per RTP frame
+/// with its RTP timestamp. A frame which lost packets is dropped.
+pub(crate) struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    codec: Codec,
+    frames: u32,
+    frame: Vec<u8>,
+    // the timestamp of the frame being received, `None` between frames
+    frame_timestamp: Option<u32>,
+    first_timestamp: Option<u32>,
+    last_sequence_number: Option<u16>,
+    // the frame misses packets, dropped at its end
+    broken: bool,
+    // AV1: the OBU continued in the next packet
+    obu_fragment: Vec<u8>,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// `None` when `mime_type` is not written to IVF.
+    pub(crate) fn new(mut writer: W, mime_type: &str) -> std::io::Result<Option<Self>> {
+        let (codec, four_cc) = match mime_type.to_lowercase().as_str() {
+            "video/vp8" => (Codec::Vp8, b"VP80"),
+            "video/vp9" => (Codec::Vp9, b"VP90"),
+            "video/av1" => (Codec::Av1, b"AV01"),
+            _ => return Ok(None),
+        };
+        writer.write_all(b"DKIF")?;
+        writer.write_all(&0u16.to_le_bytes())?; // version
+        writer.write_all(&32u16.to_le_bytes())?; // header size
+        writer.write_all(four_cc)?;
+        // the size is read from the bitstream
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(&VIDEO_CLOCK_RATE.to_le_bytes())?; // rate
+        writer.write_all(&1u32.to_le_bytes())?; // scale
+        writer.write_all(&0u32.to_le_bytes())?; // frame count, written on close
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Some(Self {
+            writer,
+            codec,
+            frames: 0,
+            frame: vec![],
+            frame_timestamp: None,
+            first_timestamp: None,
+            last_sequence_number: None,
+            broken: false,
+            obu_fragment: vec![],
+        }))
+    }
+
+    fn depacketize(&mut self, packet: &Packet) -> MediaResult<()> {
+        match self.codec {
+            Codec::Vp8 => {
+                let payload = Vp8Packet::default().depacketize(&packet.payload)?;
+                self.frame.extend_from_slice(&payload);
+            }
+            Codec::Vp9 => {
+                let payload = Vp9Packet::default().depacketize(&packet.payload)?;
+                self.frame.extend_from_slice(&payload);
+            }
+            Codec::Av1 => {
+                if self.frame.is_empty() {
+                    self.frame.extend_from_slice(&AV1_TEMPORAL_DELIMITER);
+                }
+                av1_depacketize(&packet.payload, &mut self.frame, &mut self.obu_fragment)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, timestamp: u32) -> std::io::Result<()> {
+        let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+        let pts = timestamp.wrapping_sub(first_timestamp) as u64;
+        self.writer
+            .write_all(&(self.frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&pts.to_le_bytes())?;
+        self.writer.write_all(&self.frame)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Writer for IvfWriter<W> {
+    fn write_rtp(&mut self, packet: &Packet) -> MediaResult<()> {
+        let header = &packet.header;
+        let lost = self
+            .last_sequence_number
+            .is_some_and(|last| header.sequence_number != last.wrapping_add(1));
+        self.last_sequence_number = Some(header.sequence_number);
+        if lost {
+            self.obu_fragment.clear();
+        }
+        if self.frame_timestamp != Some(header.timestamp) {
+            // the previous frame lost its last packet, it is dropped
+            self.frame.clear();
+            self.frame_timestamp = Some(header.timestamp);
+            // the loss may have taken the first packets of this frame too
+            self.broken = lost;
+        } else if lost {
+            self.broken = true;
+        }
+        if self.depacketize(packet).is_err() {
+            self.broken = true;
+        }
+        if !header.marker {
+            return Ok(());
+        }
+        if !self.broken && !self.frame.is_empty() {
+            self.write_frame(header.timestamp)?;
+        }
+        self.frame.clear();
+        self.frame_timestamp = None;
+        self.broken = false;
+        Ok(())
+    }
+
+    fn close(&mut self) -> MediaResult<()> {
+        self.writer.seek(SeekFrom::Start(24))?;
+        self.writer.write_all(&self.frames.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// https://aomediacodec.github.io/av1-rtp-spec/#44-av1-aggregation-header
+// The OBUs of the packet are appended to `frame` with their size field, as
+// IVF expects, the last one is kept in `fragment` when it continues.
+fn av1_depacketize(payload: &[u8], frame: &mut Vec<u8>, fragment: &mut Vec<u8>) -> MediaResult<()> {
+    let Some((&aggregation, mut rest)) = payload.split_first() else {
+        return Ok(());
+    };
+    // Z: the first element continues an OBU, Y: the last one is continued,
+    // W: the element count, the last one without its length, 0 when all have it
+    let continues = aggregation & 0x80 != 0;
+    let continued = aggregation & 0x40 != 0;
+    let count = (aggregation >> 4) & 0x03;
+    let mut index = 0;
+    while !rest.is_empty() {
+        index += 1;
+        let length = if count != 0 && index == count {
+            rest.len()
+        } else {
+            let (length, read) =
+                read_leb128(rest).ok_or(Error::Other("bad OBU element length".into()))?;
+            rest = &rest[read..];
+            length
+        };
+        if length > rest.len() {
+            return Err(Error::Other("short OBU element".into()));
+        }
+        let (element, tail) = rest.split_at(length);
+        rest = tail;
+        // the start of a continued OBU was lost
+        let skip = index == 1 && continues && fragment.is_empty();
+        if !(index == 1 && continues) {
+            fragment.clear();
+        }
+        if !skip {
+            fragment.extend_from_slice(element);
+        }
+        if rest.is_empty() && continued {
+            break;
+        }
+        if !skip {
+            push_obu(frame, fragment);
+        }
+        fragment.clear();
+    }
+    Ok(())
+}
+
+fn push_obu(frame: &mut Vec<u8>, obu: &[u8]) {
+    let Some(&header) = obu.first() else {
+        return;
+    };
+    let obu_type = (header >> 3) & 0x0F;
+    if obu_type == OBU_TYPE_TEMPORAL_DELIMITER || obu_type == OBU_TYPE_TILE_LIST {
+        return;
+    }
+    let header_size = if header & 0x04 != 0 { 2 } else { 1 };
+    if obu.len() < header_size {
+        return;
+    }
+    if header & 0x02 != 0 {
+        frame.extend_from_slice(obu);
+        return;
+    }
+    frame.push(header | 0x02);
+    frame.extend_from_slice(&obu[1..header_size]);
+    write_leb128(frame, obu.len() - header_size);
+    frame.extend_from_slice(&obu[header_size..]);
+}
+
+fn read_leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7F) as usize) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_leb128(frame: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            frame.push(byte);
+            return;
+        }
+        frame.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use webrtc::rtp::header::Header;
+
+    use super::*;
+
+    fn packet(sequence_number: u16, timestamp: u32, marker: bool, payload: &[u8]) -> Packet {
+        Packet {
+            header: Header {
+                sequence_number,
+                timestamp,
+                marker,
+                ..Default::default()
+            },
+            payload: payload.to_vec().into(),
+        }
+    }
+
+    // a VP8 packet starting the frame or continuing it, with `data`
+    fn vp8(start: bool, data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![if start { 0x10 } else { 0x00 }];
+        payload.extend_from_slice(data);
+        payload
+    }
+
+    // the frames of an IVF file with their pts
+    fn frames(file: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        let mut frames = vec![];
+        let mut rest = &file[32..];
+        while !rest.is_empty() {
+            let size = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            let pts = u64::from_le_bytes(rest[4..12].try_into().unwrap());
+            frames.push((pts, rest[12..12 + size].to_vec()));
+            rest = &rest[12 + size..];
+        }
+        frames
+    }
+
+    fn write(packets: &[Packet]) -> Vec<u8> {
+        let mut writer = IvfWriter::new(Cursor::new(vec![]), "video/VP8")
+            .unwrap()
+            .unwrap();
+        for packet in packets {
+            writer.write_rtp(packet).unwrap();
+        }
+        writer.close().unwrap();
+        let file = writer.writer.into_inner();
+        assert_eq!(
+            u32::from_le_bytes(file[24..28].try_into().unwrap()) as usize,
+            frames(&file).len()
+        );
+        file
+    }
+
+    #[test]
+    fn test_frames_written() {
+        let file = write(&[
+            packet(1, 1000, false, &vp8(true, &[1, 2, 3])),
+            packet(2, 1000, true, &vp8(false, &[4, 5, 6])),
+            packet(3, 4000, true, &vp8(true, &[7, 8, 9])),
+        ]);
+        assert_eq!(&file[..4], b"DKIF");
+        assert_eq!(&file[8..12], b"VP80");
+        assert_eq!(
+            frames(&file),
+            [(0, vec![1, 2, 3, 4, 5, 6]), (3000, vec![7, 8, 9])]
+        );
+    }
+
+    #[test]
+    fn test_frame_with_loss_dropped() {
+        let file = write(&[
+            packet(1, 1000, false, &vp8(true, &[1, 2, 3])),
+            packet(3, 1000, true, &vp8(false, &[7, 8, 9])),
+            packet(4, 4000, true, &vp8(true, &[4, 5, 6])),
+        ]);
+        assert_eq!(frames(&file), [(0, vec![4, 5, 6])]);
+    }
+
+    #[test]
+    fn test_loss_across_frames() {
+        // the end of the first frame and the start of the second are lost
+        let file = write(&[
+            packet(1, 1000, false, &vp8(true, &[1, 2, 3])),
+            packet(4, 4000, true, &vp8(false, &[4, 5, 6])),
+            packet(5, 7000, true, &vp8(true, &[7, 8, 9])),
+        ]);
+        assert_eq!(frames(&file), [(0, vec![7, 8, 9])]);
+    }
+
+    #[test]
+    fn test_lost_marker_without_loss_in_next_frame() {
+        // the first frame ends without its marker, the next one is intact
+        let file = write(&[
+            packet(1, 1000, false, &vp8(true, &[1, 2, 3])),
+            packet(2, 1000, false, &vp8(false, &[4, 5, 6])),
+            packet(3, 4000, true, &vp8(true, &[7, 8, 9])),
+        ]);
+        assert_eq!(frames(&file), [(0, vec![7, 8, 9])]);
+    }
+
+    #[test]
+    fn test_leb128_round_trip() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16383,
+            16384,
+            1 << 20,
+            u32::MAX as usize,
+        ] {
+            let mut encoded = vec![];
+            write_leb128(&mut encoded, value);
+            assert_eq!(read_leb128(&encoded), Some((value, encoded.len())));
+        }
+        let mut encoded = vec![];
+        write_leb128(&mut encoded, 300);
+        assert_eq!(encoded, [0xAC, 0x02]);
+        // trailing data is not read
+        assert_eq!(read_leb128(&[0x05, 0xFF]), Some((5, 1)));
+        assert_eq!(read_leb128(&[0x80, 0x80]), None);
+        assert_eq!(read_leb128(&[]), None);
+    }
+
+    // OBU headers without size field: a sequence header and a frame
+    const SEQUENCE_HEADER: u8 = 1 << 3;
+    const FRAME: u8 = 6 << 3;
+
+    #[test]
+    fn test_av1_aggregated_obus() {
+        // W = 0: every element has its length
+        let mut frame = vec![];
+        let mut fragment = vec![];
+        let payload = [0x00, 2, SEQUENCE_HEADER, 0xAA, 3, FRAME, 0xBB, 0xCC];
+        av1_depacketize(&payload, &mut frame, &mut fragment).unwrap();
+        assert_eq!(
+            frame,
+            [SEQUENCE_HEADER | 0x02, 1, 0xAA, FRAME | 0x02, 2, 0xBB, 0xCC]
+        );
+        assert!(fragment.is_empty());
+
+        // W = 2: the last element without its length
+        let mut frame = vec![];
+        let payload = [0x20, 2, SEQUENCE_HEADER, 0xAA, FRAME, 0xBB, 0xCC];
+        av1_depacketize(&payload, &mut frame, &mut fragment).unwrap();
+        assert_eq!(
+            frame,
+            [SEQUENCE_HEADER | 0x02, 1, 0xAA, FRAME | 0x02, 2, 0xBB, 0xCC]
+        );
+    }
+
+    #[test]
+    fn test_av1_temporal_delimiter_dropped() {
+        let mut frame = vec![];
+        let mut fragment = vec![];
+        let payload = [0x00, 1, OBU_TYPE_TEMPORAL_DELIMITER << 3, 2, FRAME, 0xBB];
+        av1_depacketize(&payload, &mut frame, &mut fragment).unwrap();
+        assert_eq!(frame, [FRAME | 0x02, 1, 0xBB]);
+    }
+
+    #[test]
+    fn test_av1_fragment_across_packets() {
+        let mut frame = vec![];
+        let mut fragment = vec![];
+        // Y: the OBU continues in the next packets
+        av1_depacketize(&[0x50, FRAME, 0x01, 0x02], &mut frame, &mut fragment).unwrap();
+        assert!(frame.is_empty());
+        // Z and Y: continues one and is continued
+        av1_depacketize(&[0xD0, 0x03, 0x04], &mut frame, &mut fragment).unwrap();
+        assert!(frame.is_empty());
+        // Z: the end of the OBU
+        av1_depacketize(&[0x90, 0x05], &mut frame, &mut fragment).unwrap();
+        assert_eq!(frame, [FRAME | 0x02, 5, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert!(fragment.is_empty());
+    }
+
+    #[test]
+    fn test_av1_fragment_without_start_dropped() {
+        let mut frame = vec![];
+        let mut fragment = vec![];
+        // Z with nothing to continue, then a whole OBU
+        av1_depacketize(
+            &[0xA0, 2, 0x03, 0x04, FRAME, 0xBB],
+            &mut frame,
+            &mut fragment,
+        )
+        .unwrap();
+        assert_eq!(frame, [FRAME | 0x02, 1, 0xBB]);
+    }
+
+    #[test]
+    fn test_av1_short_element() {
+        let mut frame = vec![];
+        let mut fragment = vec![];
+        assert!(av1_depacketize(&[0x00, 5, FRAME], &mut frame, &mut fragment).is_err());
+    }
+
+    #[test]
+    fn test_av1_frames_start_with_temporal_delimiter() {
+        let mut writer = IvfWriter::new(Cursor::new(vec![]), "video/AV1")
+            .unwrap()
+            .unwrap();
+        writer
+            .write_rtp(&packet(1, 1000, false, &[0x58, FRAME, 0x01]))
+            .unwrap();
+        writer
+            .write_rtp(&packet(2, 1000, true, &[0x90, 0x02]))
+            .unwrap();
+        writer.close().unwrap();
+        let file = writer.writer.into_inner();
+        assert_eq!(&file[8..12], b"AV01");
+        let mut expected = AV1_TEMPORAL_DELIMITER.to_vec();
+        expected.extend_from_slice(&[FRAME | 0x02, 2, 0x01, 0x02]);
+        assert_eq!(frames(&file), [(0, expected)]);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::rtcp::RtcpMessage;
+
+/// Returns true when `payload` starts a keyframe, so that a subscriber can
+/// switch to the stream at this packet. Audio packets are always decodable,
+/// and video codecs which are not parsed here are treated the same way.
+pub(crate) fn is_keyframe(mime_type: &str, payload: &[u8]) -> bool {
+    match mime_type.to_lowercase().as_str() {
+        "video/vp8" => vp8(payload),
+        "video/vp9" => vp9(payload),
+        "video/h264" => h264(payload),
+        "video/av1" => av1(payload),
+        _ => true,
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7741#section-4.2
+fn vp8(payload: &[u8]) -> bool {
+    let Some(&descriptor) = payload.first() else {
+        return false;
+    };
+    // only the first packet of the first partition carries the frame header
+    let start = descriptor & 0x10 != 0 && descriptor & 0x07 == 0;
+    if !start {
+        return false;
+    }
+    let mut offset = 1;
+    if descriptor & 0x80 != 0 {
+        let Some(&extension) = payload.get(offset) else {
+            return false;
+        };
+        offset += 1;
+        if extension & 0x80 != 0 {
+            // picture id, 15 bits when the M bit is set
+            let long = payload.get(offset).is_some_and(|id| id & 0x80 != 0);
+            offset += if long { 2 } else { 1 };
+        }
+        if extension & 0x40 != 0 {
+            offset += 1;
+        }
+        if extension & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+    // P bit of the frame header, 0 for keyframes
+    payload.get(offset).is_some_and(|header| header & 0x01 == 0)
+}
+
+// https://datatracker.ietf.org/doc/html/draft-ietf-payload-vp9-16#section-4.2
+fn vp9(payload: &[u8]) -> bool {
+    // not inter-picture predicted and the start of a frame
+    payload
+        .first()
+        .is_some_and(|descriptor| descriptor & 0x40 == 0 && descriptor & 0x08 != 0)
+}
+
+// https://datatracker.ietf.org/doc/html/rfc6184#section-5.2
+fn h264(payload: &[u8]) -> bool {
+    const IDR: u8 = 5;
+    const SPS: u8 = 7;
+    const STAP_A: u8 = 24;
+    const FU_A: u8 = 28;
+    let Some(&header) = payload.first() else {
+        return false;
+    };
+    match header & 0x1F {
+        IDR | SPS => true,
+        STAP_A => {
+            let mut offset = 1;
+            while offset + 2 < payload.len() {
+                let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+                let nal_type = payload[offset + 2] & 0x1F;
+                if nal_type == IDR || nal_type == SPS {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        FU_A => payload
+            .get(1)
+            .is_some_and(|fu_header| fu_header & 0x80 != 0 && fu_header & 0x1F == IDR),
+        _ => false,
+    }
+}
+
+// https://aomediacodec.github.io/av1-rtp-spec/#44-av1-aggregation-header
+fn av1(payload: &[u8]) -> bool {
+    // N: the first packet of a coded video sequence
+    payload.first().is_some_and(|header| header & 0x08 != 0)
+}
+
+struct TrackRequests {
+    sent_at: Instant,
+    pending: Option<RtcpMessage>,
+    fir_sequence_number: u8,
+}
+
+/// Coalesces the keyframe requests (PLI, FIR) sent to the publisher: at most
+/// one per track every `interval`. A request within the interval is held and
+/// sent when it ends, merged with the ones following it.
+pub(crate) struct KeyframeRequests {
+    interval: Duration,
+    tracks: HashMap<u32, TrackRequests>,
+}
+
+impl KeyframeRequests {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            tracks: HashMap::new(),
+        }
+    }
+
+    /// Returns the request to send now to `ssrc`, with its FIR sequence number.
+    pub(crate) fn request(&mut self, message: RtcpMessage, ssrc: u32) -> Option<(RtcpMessage, u8)> {
+        match self.tracks.get_mut(&ssrc) {
+            Some(track) if track.sent_at.elapsed() < self.interval => {
+                // a FIR asks for more than a PLI, keep it when merging
+                track.pending = match track.pending {
+                    Some(RtcpMessage::FullIntraRequest) => Some(RtcpMessage::FullIntraRequest),
+                    _ => Some(message),
+                };
+                None
+            }
+            Some(track) => Some(Self::send(track, message)),
+            None => {
+                let track = self.tracks.entry(ssrc).or_insert(TrackRequests {
+                    sent_at: Instant::now(),
+                    pending: None,
+                    fir_sequence_number: 0,
+                });
+                Some(Self::send(track, message))
+            }
+        }
+    }
+
+    fn send(track: &mut TrackRequests, message: RtcpMessage) -> (RtcpMessage, u8) {
+        track.sent_at = Instant::now();
+        track.pending = None;
+        if let RtcpMessage::FullIntraRequest = message {
+            track.fir_sequence_number = track.fir_sequence_number.wrapping_add(1);
+        }
+        (message, track.fir_sequence_number)
+    }
+
+    /// When the next held request is due.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.tracks
+            .values()
+            .filter(|track| track.pending.is_some())
+            .map(|track| track.sent_at + self.interval)
+            .min()
+    }
+
+    /// Takes the held requests whose interval has ended.
+    pub(crate) fn due(&mut self) -> Vec<(RtcpMessage, u32, u8)> {
+        let interval = self.interval;
+        self.tracks
+            .iter_mut()
+            .filter(|(_, track)| track.sent_at.elapsed() >= interval)
+            .filter_map(|(ssrc, track)| {
+                let message = track.pending?;
+                let (message, fir_sequence_number) = Self::send(track, message);
+                Some((message, *ssrc, fir_sequence_number))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_vp8_keyframe() {
+        assert!(is_keyframe("video/VP8", &[0x10, 0x00]));
+        // inter frame
+        assert!(!is_keyframe("video/VP8", &[0x10, 0x01]));
+        // not the start of the first partition
+        assert!(!is_keyframe("video/VP8", &[0x00, 0x00]));
+        assert!(!is_keyframe("video/VP8", &[0x11, 0x00]));
+        // X, I with a 15 bit picture id, L, T and K
+        assert!(is_keyframe(
+            "video/VP8",
+            &[0x90, 0xF0, 0x80, 0x01, 0x02, 0x03, 0x00]
+        ));
+        assert!(!is_keyframe(
+            "video/VP8",
+            &[0x90, 0xF0, 0x80, 0x01, 0x02, 0x03, 0x01]
+        ));
+        assert!(!is_keyframe("video/VP8", &[]));
+    }
+
+    #[test]
+    fn test_vp9_keyframe() {
+        assert!(is_keyframe("video/VP9", &[0x08]));
+        // inter-picture predicted
+        assert!(!is_keyframe("video/VP9", &[0x48]));
+        // not the start of a frame
+        assert!(!is_keyframe("video/VP9", &[0x00]));
+    }
+
+    #[test]
+    fn test_h264_keyframe() {
+        // IDR and SPS
+        assert!(is_keyframe("video/H264", &[0x65]));
+        assert!(is_keyframe("video/H264", &[0x67]));
+        assert!(!is_keyframe("video/H264", &[0x41]));
+        // STAP-A of an SEI then an SPS
+        assert!(is_keyframe(
+            "video/H264",
+            &[0x78, 0x00, 0x02, 0x06, 0x00, 0x00, 0x02, 0x67, 0x00]
+        ));
+        assert!(!is_keyframe(
+            "video/H264",
+            &[0x78, 0x00, 0x02, 0x06, 0x00, 0x00, 0x02, 0x41, 0x00]
+        ));
+        // FU-A, only its start
+        assert!(is_keyframe("video/H264", &[0x7C, 0x85]));
+        assert!(!is_keyframe("video/H264", &[0x7C, 0x05]));
+        assert!(!is_keyframe("video/H264", &[0x7C, 0x81]));
+    }
+
+    #[test]
+    fn test_av1_keyframe() {
+        assert!(is_keyframe("video/AV1", &[0x08]));
+        assert!(!is_keyframe("video/AV1", &[0x10]));
+    }
+
+    #[test]
+    fn test_audio_always_keyframe() {
+        assert!(is_keyframe("audio/opus", &[]));
+    }
+
+    // as if the last request to `ssrc` was sent an interval ago
+    fn elapse(requests: &mut KeyframeRequests, ssrc: u32) {
+        requests.tracks.get_mut(&ssrc).unwrap().sent_at -= INTERVAL;
+    }
+
+    #[test]
+    fn test_first_request_sent() {
+        let mut requests = KeyframeRequests::new(INTERVAL);
+        assert!(matches!(
+            requests.request(RtcpMessage::PictureLossIndication, 1),
+            Some((RtcpMessage::PictureLossIndication, 0))
+        ));
+        // tracks are throttled apart
+        assert!(requests
+            .request(RtcpMessage::PictureLossIndication, 2)
+            .is_some());
+        assert!(requests.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_coalesced_within_interval() {
+        let mut requests = KeyframeRequests::new(INTERVAL);
+        requests.request(RtcpMessage::PictureLossIndication, 1);
+        assert!(requests
+            .request(RtcpMessage::PictureLossIndication, 1)
+            .is_none());
+        assert!(requests
+            .request(RtcpMessage::PictureLossIndication, 1)
+            .is_none());
+        assert!(requests.due().is_empty());
+        assert!(requests.next_deadline().is_some());
+
+        elapse(&mut requests, 1);
+        let due = requests.due();
+        assert_eq!(due.len(), 1);
+        assert!(matches!(due[0], (RtcpMessage::PictureLossIndication, 1, 0)));
+        assert!(requests.next_deadline().is_none());
+        assert!(requests.due().is_empty());
+    }
+
+    #[test]
+    fn test_fir_kept_when_merging() {
+        let mut requests = KeyframeRequests::new(INTERVAL);
+        requests.request(RtcpMessage::PictureLossIndication, 1);
+        requests.request(RtcpMessage::FullIntraRequest, 1);
+        requests.request(RtcpMessage::PictureLossIndication, 1);
+        elapse(&mut requests, 1);
+        assert!(matches!(
+            requests.due()[..],
+            [(RtcpMessage::FullIntraRequest, 1, 1)]
+        ));
+    }
+
+    #[test]
+    fn test_fir_sequence_number_increments() {
+        let mut requests = KeyframeRequests::new(INTERVAL);
+        assert!(matches!(
+            requests.request(RtcpMessage::FullIntraRequest, 1),
+            Some((RtcpMessage::FullIntraRequest, 1))
+        ));
+        elapse(&mut requests, 1);
+        // a PLI leaves it as is
+        assert!(matches!(
+            requests.request(RtcpMessage::PictureLossIndication, 1),
+            Some((RtcpMessage::PictureLossIndication, 1))
+        ));
+        elapse(&mut requests, 1);
+        assert!(matches!(
+            requests.request(RtcpMessage::FullIntraRequest, 1),
+            Some((RtcpMessage::FullIntraRequest, 2))
+        ));
+    }
+
+    #[test]
+    fn test_fir_sequence_number_wraps() {
+        let mut requests = KeyframeRequests::new(INTERVAL);
+        requests.request(RtcpMessage::FullIntraRequest, 1);
+        requests.tracks.get_mut(&1).unwrap().fir_sequence_number = u8::MAX;
+        elapse(&mut requests, 1);
+        assert!(matches!(
+            requests.request(RtcpMessage::FullIntraRequest, 1),
+            Some((RtcpMessage::FullIntraRequest, 0))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use webrtc::{
     rtp_transceiver::{
         rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType},
@@ -7,7 +9,7 @@ use webrtc::{
 };
 
 pub(crate) struct MediaInfo {
-    pub(crate) _codec: Vec<RTCRtpCodecParameters>,
+    pub(crate) codec: Vec<RTCRtpCodecParameters>,
     pub(crate) video_transceiver: (u8, u8, bool), // (send,recv,svc)
     pub(crate) audio_transceiver: (u8, u8),       // (send,recv)
 }
@@ -50,13 +52,98 @@ impl TryFrom<SessionDescription> for MediaInfo {
             }
         }
         Ok(Self {
-            _codec: codec,
+            codec,
             video_transceiver,
             audio_transceiver: (audio_transceiver.0, audio_transceiver.1),
         })
     }
 }
 
+impl MediaInfo {
+    /// The codecs of `kind`, in the order of preference of the description.
+    pub(crate) fn codecs(&self, kind: RTPCodecType) -> Vec<RTCRtpCodecParameters> {
+        self.codec
+            .iter()
+            .filter(|codec| codec_kind(&codec.capability) == kind)
+            .cloned()
+            .collect()
+    }
+
+    /// The preferred codec of `kind` which carries media, i.e. not a
+    /// retransmission or FEC format.
+    pub(crate) fn primary_codec(&self, kind: RTPCodecType) -> Option<RTCRtpCodecParameters> {
+        self.codecs(kind)
+            .into_iter()
+            .find(|codec| !is_auxiliary_codec(&codec.capability))
+    }
+
+    /// The codecs which can receive the media of `capability`: same mime type
+    /// and compatible fmtp (H264 profile and packetization mode, VP9 and AV1
+    /// profile).
+    pub(crate) fn compatible_codecs(
+        &self,
+        capability: &RTCRtpCodecCapability,
+    ) -> Vec<RTCRtpCodecParameters> {
+        self.codec
+            .iter()
+            .filter(|codec| {
+                codec
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(&capability.mime_type)
+                    && fmtp_compatible(
+                        &capability.mime_type,
+                        &codec.capability.sdp_fmtp_line,
+                        &capability.sdp_fmtp_line,
+                    )
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+fn codec_kind(capability: &RTCRtpCodecCapability) -> RTPCodecType {
+    let mime_type = capability.mime_type.to_lowercase();
+    RTPCodecType::from(mime_type.split('/').next().unwrap_or_default())
+}
+
+fn is_auxiliary_codec(capability: &RTCRtpCodecCapability) -> bool {
+    let mime_type = capability.mime_type.to_lowercase();
+    ["/rtx", "/red", "/ulpfec", "/flexfec-03"]
+        .iter()
+        .any(|suffix| mime_type.ends_with(suffix))
+}
+
+fn fmtp_parameters(fmtp: &str) -> HashMap<String, String> {
+    fmtp.split(';')
+        .filter_map(|parameter| {
+            let (key, value) = parameter.split_once('=')?;
+            Some((key.trim().to_lowercase(), value.trim().to_lowercase()))
+        })
+        .collect()
+}
+
+fn fmtp_compatible(mime_type: &str, a: &str, b: &str) -> bool {
+    let (a, b) = (fmtp_parameters(a), fmtp_parameters(b));
+    let parameter = |fmtp: &HashMap<String, String>, key: &str, default: &str| {
+        fmtp.get(key).cloned().unwrap_or(default.to_string())
+    };
+    match mime_type.to_lowercase().as_str() {
+        "video/h264" => {
+            // The level (last byte of profile-level-id) may differ.
+            let profile = |fmtp: &HashMap<String, String>| {
+                let profile_level_id = parameter(fmtp, "profile-level-id", "42001f");
+                profile_level_id.get(..4).unwrap_or_default().to_string()
+            };
+            parameter(&a, "packetization-mode", "0") == parameter(&b, "packetization-mode", "0")
+                && profile(&a) == profile(&b)
+        }
+        "video/vp9" => parameter(&a, "profile-id", "0") == parameter(&b, "profile-id", "0"),
+        "video/av1" => parameter(&a, "profile", "0") == parameter(&b, "profile", "0"),
+        _ => true,
+    }
+}
+
 // from https://github.com/webrtc-rs/webrtc/blob/master/webrtc/src/peer_connection/sdp/mod.rs
 pub fn codecs_from_media_description(
     m: &MediaDescription,
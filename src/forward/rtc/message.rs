@@ -1,4 +1,5 @@
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 #[derive(Clone, Debug)]
 pub struct Layer {
@@ -13,6 +14,18 @@ pub struct ForwardInfo {
     pub subscribe_leave_time: i64,
     pub publish_session_info: Option<SessionInfo>,
     pub subscribe_session_infos: Vec<SessionInfo>,
+    pub publish_tracks: Vec<TrackInfo>,
+    pub recording: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackInfo {
+    pub kind: RTPCodecType,
+    pub mid: String,
+    pub id: String,
+    pub stream_id: String,
+    pub rids: Vec<String>,
+    pub codec: String,
 }
 #[derive(Clone, Debug)]
 pub struct SessionInfo {
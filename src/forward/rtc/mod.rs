@@ -2,9 +2,10 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify};
 use tracing::info;
 
+use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
 use webrtc::ice_transport::ice_gatherer::OnLocalCandidateHdlrFn;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
@@ -14,16 +15,28 @@ use webrtc::peer_connection::RTCPeerConnection;
 use internal::PeerForwardInternal;
 use media::MediaInfo;
 use message::{ForwardInfo, Layer};
+use player::{PlayConfig, Player};
+use recorder::RecordConfig;
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 use crate::error::AppError;
 use crate::result::Result;
 
+pub mod audio_level;
+pub mod bwe;
 pub mod client;
+pub mod gop;
+pub mod ice;
 pub mod internal;
+pub mod ivf;
+pub mod keyframe;
 pub mod media;
 pub mod message;
+pub mod munger;
+pub mod nack;
+pub mod player;
 pub mod publish;
+pub mod recorder;
 pub mod rtcp;
 pub mod subscribe;
 pub mod track;
@@ -38,10 +51,22 @@ pub type OnPeerConnectionEvtHdlrFn =
     Box<dyn (FnMut() -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>) + Send + Sync>;
 
 impl PeerForward {
-    pub fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub fn new(
+        stream: impl ToString,
+        ice_server: Vec<RTCIceServer>,
+        keyframe_request_interval: u64,
+        gop_cache: bool,
+        record: RecordConfig,
+    ) -> Self {
         PeerForward {
             publish_lock: Arc::new(Mutex::new(())),
-            internal: Arc::new(PeerForwardInternal::new(stream, ice_server)),
+            internal: Arc::new(PeerForwardInternal::new(
+                stream,
+                ice_server,
+                keyframe_request_interval,
+                gop_cache,
+                record,
+            )),
         }
     }
 
@@ -124,8 +149,7 @@ impl PeerForward {
                             let _ = pc.close().await;
                         }
                         RTCPeerConnectionState::Closed => {
-                            internal.notice_network_event(id.clone(), false);
-                            let _ = internal.remove_publish(id.clone(), pc).await;
+                            let _ = internal.remove_publish(id, pc).await;
                         }
                         _ => {}
                     };
@@ -135,10 +159,14 @@ impl PeerForward {
         }));
         let internal = Arc::downgrade(&self.internal);
         let pc = Arc::downgrade(&peer);
-        peer.on_track(Box::new(move |track, _, _| {
+        peer.on_track(Box::new(move |track, _, transceiver| {
             if let (Some(internal), Some(pc)) = (internal.upgrade(), pc.upgrade()) {
+                let mid = transceiver
+                    .mid()
+                    .map(|mid| mid.to_string())
+                    .unwrap_or_default();
                 tokio::spawn(async move {
-                    let _ = internal.publish_track_up(pc, track).await;
+                    let _ = internal.publish_track_up(pc, mid, track).await;
                 });
             }
             Box::pin(async {})
@@ -154,11 +182,94 @@ impl PeerForward {
             Box::pin(async {})
         }));
         let description = peer_complete(offer, peer.clone()).await?;
-        self.internal.set_publish(peer.clone()).await?;
+        self.internal.set_publish(id, peer.clone()).await?;
         let session = get_peer_id(&peer);
         Ok((peer, description, session))
     }
 
+    /// Publishes files from disk as user `id`, through a local peer connection
+    /// negotiated like a remote one. Returns the publish session.
+    pub async fn file_publish(&self, id: u32, config: PlayConfig) -> Result<String> {
+        let peer = self.internal.new_virtual_publish_peer().await?;
+        let player = match Player::new(self.internal.stream.clone(), &peer, config).await {
+            Ok(player) => player,
+            Err(err) => {
+                let _ = peer.close().await;
+                return Err(err);
+            }
+        };
+        // the player closes the peer when `stop` is dropped, also on any error below
+        let (stop, stop_recv) = watch::channel(());
+        let connected = Arc::new(Notify::new());
+        // once the files end, the publisher leaves without waiting for the timeout
+        let (session_sender, session_recv) = oneshot::channel();
+        let internal = Arc::downgrade(&self.internal);
+        let play = player.play(peer.clone(), connected.clone(), stop_recv);
+        tokio::spawn(async move {
+            play.await;
+            if let (Some(internal), Ok(session)) = (internal.upgrade(), session_recv.await) {
+                let _ = internal.remove_peer(session).await;
+            }
+        });
+        let pc = Arc::downgrade(&peer);
+        peer.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+            match s {
+                RTCPeerConnectionState::Connected => connected.notify_one(),
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Disconnected => {
+                    if let Some(pc) = pc.upgrade() {
+                        tokio::spawn(async move {
+                            let _ = pc.close().await;
+                        });
+                    }
+                }
+                _ => {}
+            };
+            Box::pin(async {})
+        }));
+        let offer = peer.create_offer(None).await?;
+        // the offer carries every candidate, the answer's are trickled
+        let mut gather_complete = peer.gathering_complete_promise().await;
+        peer.set_local_description(offer).await?;
+        let _ = gather_complete.recv().await;
+        let offer = peer
+            .local_description()
+            .await
+            .ok_or(anyhow::anyhow!("failed to get local description"))?;
+        let (candidate_sender, mut candidate_recv) = mpsc::unbounded_channel();
+        let (_, answer, session) = self
+            .set_publish(
+                id,
+                offer,
+                Box::new(move |candidate: Option<RTCIceCandidate>| {
+                    if let Some(candidate) = candidate {
+                        let _ = candidate_sender.send(candidate);
+                    }
+                    Box::pin(async {})
+                }),
+                Box::new(move || Box::pin(async {})),
+            )
+            .await?;
+        peer.set_remote_description(answer).await?;
+        let pc = Arc::downgrade(&peer);
+        tokio::spawn(async move {
+            while let Some(candidate) = candidate_recv.recv().await {
+                let Some(pc) = pc.upgrade() else {
+                    break;
+                };
+                if let Ok(candidate) = candidate.to_json() {
+                    let _ = pc.add_ice_candidate(candidate).await;
+                }
+            }
+        });
+        self.internal.set_player(stop).await;
+        let _ = session_sender.send(session.clone());
+        Ok(session)
+    }
+
+    pub async fn stop_playback(&self) -> Result<()> {
+        self.internal.stop_playback().await
+    }
+
     pub async fn publish_is_ok(&self) -> bool {
         return self.internal.publish_is_ok().await;
     }
@@ -171,11 +282,11 @@ impl PeerForward {
         on_peer_connected: OnPeerConnectionEvtHdlrFn,
     ) -> Result<(Arc<RTCPeerConnection>, RTCSessionDescription, String)> {
         if !self.internal.publish_is_ok().await {
-            return Err(AppError::throw("publish is not ok"));
+            return Err(AppError::publish_not_ready("publish is not ok"));
         }
         let peer = self
             .internal
-            .new_subscription_peer(MediaInfo::try_from(offer.unmarshal()?)?)
+            .new_subscription_peer(id, MediaInfo::try_from(offer.unmarshal()?)?)
             .await?;
         let internal = Arc::downgrade(&self.internal);
         let pc = Arc::downgrade(&peer);
@@ -201,8 +312,7 @@ impl PeerForward {
                             let _ = pc.close().await;
                         }
                         RTCPeerConnectionState::Closed => {
-                            internal.notice_network_event(id.clone(), false);
-                            let _ = internal.remove_subscribe(id.clone(), pc).await;
+                            let _ = internal.remove_subscribe(id, pc).await;
                         }
                         _ => {}
                     }
@@ -237,7 +347,7 @@ impl PeerForward {
             }
             Ok(layers)
         } else {
-            Err(AppError::throw("not layers"))
+            Err(AppError::layer_not_found("not layers"))
         }
     }
 
@@ -245,17 +355,67 @@ impl PeerForward {
         let rid = if let Some(layer) = layer {
             layer.encoding_id
         } else {
-            self.internal.publish_svc_rids().await?[0].clone()
+            self.internal
+                .publish_svc_rids()
+                .await?
+                .first()
+                .cloned()
+                .ok_or(AppError::layer_not_found("not layers"))?
         };
         self.internal
             .select_kind_rid(session, RTPCodecType::Video, rid)
             .await
     }
 
-    // This function has not used currently, but seems worth to keep retain
-    // pub async fn remove_peer(&self, session: String) -> Result<bool> {
-    //     self.internal.remove_peer(session).await
-    // }
+    /// Returns true when `session` is the publish session, muted or unmuted for everyone.
+    pub async fn change_resource(
+        &self,
+        session: String,
+        kind: RTPCodecType,
+        enabled: bool,
+    ) -> Result<bool> {
+        self.internal.change_resource(session, kind, enabled).await
+    }
+
+    pub async fn start_record(&self) -> Result<()> {
+        self.internal.start_record().await
+    }
+
+    pub async fn stop_record(&self) -> Result<()> {
+        self.internal.stop_record().await
+    }
+
+    pub async fn session_owner(&self, session: String) -> Result<u32> {
+        self.internal
+            .session_owner(session)
+            .await
+            .ok_or(AppError::session_not_found("not found session"))
+    }
+
+    pub async fn publish_user(&self) -> Option<u32> {
+        self.internal.publish_user().await
+    }
+
+    pub async fn publish_has_video(&self) -> bool {
+        self.internal.publish_has_video().await
+    }
+
+    pub async fn subscribe_sessions(&self) -> Vec<(String, u32)> {
+        self.internal.subscribe_sessions().await
+    }
+
+    pub async fn remove_peer(&self, session: String) -> Result<bool> {
+        self.internal.remove_peer(session).await
+    }
+
+    pub fn send_server_event(&self, frame: Vec<u8>) {
+        self.internal.send_server_event(frame);
+    }
+
+    /// The publisher's user id and its audio level, 0 (silence) to 127.
+    pub async fn audio_level(&self) -> Option<(u32, u8)> {
+        self.internal.audio_level().await
+    }
 
     pub async fn close(&self) -> Result<()> {
         self.internal.close().await?;
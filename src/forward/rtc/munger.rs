@@ -0,0 +1,430 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use bytes::Bytes;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+
+use super::keyframe::is_keyframe;
+
+// the previous sources kept to map NACKs of packets sent before a switch
+const SOURCE_HISTORY: usize = 4;
+
+// a is newer than b, with wraparound
+fn seq_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+#[derive(Clone, Copy, Default)]
+struct Vp8Offsets {
+    picture_id: u16,
+    tl0_pic_idx: u8,
+}
+
+struct Source {
+    ssrc: u32,
+    // the first forwarded sequence number, older packets are dropped
+    start_seq: u16,
+    seq_offset: u16,
+    timestamp_offset: u32,
+    vp8: Vp8Offsets,
+}
+
+impl Source {
+    fn start_out(&self) -> u16 {
+        self.start_seq.wrapping_add(self.seq_offset)
+    }
+
+    fn rewrite(&self, packet: &Packet, codec: &RTCRtpCodecCapability) -> Packet {
+        let mut packet = packet.clone();
+        packet.header.sequence_number = packet.header.sequence_number.wrapping_add(self.seq_offset);
+        packet.header.timestamp = packet.header.timestamp.wrapping_add(self.timestamp_offset);
+        if codec.mime_type.eq_ignore_ascii_case("video/vp8") {
+            packet.payload = rewrite_vp8(&packet.payload, self.vp8);
+        }
+        packet
+    }
+}
+
+struct Output {
+    sequence_number: u16,
+    timestamp: u32,
+    at: Instant,
+    picture_id: Option<u16>,
+    tl0_pic_idx: Option<u8>,
+}
+
+/// Rewrites the packets of one subscriber sender, so that sequence numbers,
+/// timestamps and VP8 picture ids stay continuous when its source changes:
+/// a simulcast layer switch, or a publisher reconnecting with a new track.
+/// A new source is recognized by its ssrc, and video is only forwarded from
+/// its first keyframe on.
+#[derive(Default)]
+pub(crate) struct RtpMunger {
+    // the current source first
+    sources: VecDeque<Source>,
+    last: Option<Output>,
+    // start over from the next keyframe, even from the same source
+    resync: bool,
+}
+
+impl RtpMunger {
+    /// Returns the packet to send, or `None` to drop it.
+    pub(crate) fn process(
+        &mut self,
+        packet: &Packet,
+        codec: &RTCRtpCodecCapability,
+    ) -> Option<Packet> {
+        let header = &packet.header;
+        if self.resync || self.sources.front().map(|s| s.ssrc) != Some(header.ssrc) {
+            if !is_keyframe(&codec.mime_type, &packet.payload) {
+                return None;
+            }
+            self.switch_source(packet, codec.clock_rate);
+        }
+        let source = self.sources.front()?;
+        if seq_newer(source.start_seq, header.sequence_number) {
+            return None;
+        }
+
+        let packet = source.rewrite(packet, codec);
+        let newest = self
+            .last
+            .as_ref()
+            .is_none_or(|last| seq_newer(packet.header.sequence_number, last.sequence_number));
+        if newest {
+            let descriptor = Vp8Descriptor::parse(&packet.payload)
+                .filter(|_| codec.mime_type.eq_ignore_ascii_case("video/vp8"));
+            let last = self.last.as_ref();
+            self.last = Some(Output {
+                sequence_number: packet.header.sequence_number,
+                timestamp: packet.header.timestamp,
+                at: Instant::now(),
+                picture_id: descriptor
+                    .as_ref()
+                    .and_then(|d| d.picture_id(&packet.payload))
+                    .or(last.and_then(|last| last.picture_id)),
+                tl0_pic_idx: descriptor
+                    .as_ref()
+                    .and_then(|d| d.tl0_pic_idx(&packet.payload))
+                    .or(last.and_then(|last| last.tl0_pic_idx)),
+            });
+        }
+        Some(packet)
+    }
+
+    fn switch_source(&mut self, packet: &Packet, clock_rate: u32) {
+        let header = &packet.header;
+        let mut source = Source {
+            ssrc: header.ssrc,
+            start_seq: header.sequence_number,
+            seq_offset: 0,
+            timestamp_offset: 0,
+            vp8: Vp8Offsets::default(),
+        };
+        // continue right after the last packet sent, moving the timestamp by
+        // the time elapsed since
+        if let Some(last) = &self.last {
+            let elapsed = last.at.elapsed().as_millis() as u64 * clock_rate as u64 / 1000;
+            let timestamp = last.timestamp.wrapping_add(elapsed.max(1) as u32);
+            source.seq_offset = last
+                .sequence_number
+                .wrapping_add(1)
+                .wrapping_sub(header.sequence_number);
+            source.timestamp_offset = timestamp.wrapping_sub(header.timestamp);
+            if let Some(descriptor) = Vp8Descriptor::parse(&packet.payload) {
+                if let (Some(last), Some(picture_id)) =
+                    (last.picture_id, descriptor.picture_id(&packet.payload))
+                {
+                    source.vp8.picture_id = last.wrapping_add(1).wrapping_sub(picture_id) & 0x7FFF;
+                }
+                if let (Some(last), Some(tl0_pic_idx)) =
+                    (last.tl0_pic_idx, descriptor.tl0_pic_idx(&packet.payload))
+                {
+                    source.vp8.tl0_pic_idx = last.wrapping_add(1).wrapping_sub(tl0_pic_idx);
+                }
+            }
+        }
+        self.sources.push_front(source);
+        self.sources.truncate(SOURCE_HISTORY);
+        self.resync = false;
+    }
+
+    /// Forwarding stops: when it resumes, the output continues right after
+    /// the last packet sent, from the next keyframe.
+    pub(crate) fn pause(&mut self) {
+        self.resync = true;
+    }
+
+    /// Maps a sequence number sent to the subscriber back to the ssrc and
+    /// sequence number of the publisher's packet.
+    pub(crate) fn lookup(&self, sequence_number: u16) -> Option<(u32, u16)> {
+        let mut newer_start: Option<u16> = None;
+        for source in &self.sources {
+            let start = source.start_out();
+            let in_range = !seq_newer(start, sequence_number)
+                && newer_start.is_none_or(|newer| seq_newer(newer, sequence_number));
+            if in_range {
+                return Some((source.ssrc, sequence_number.wrapping_sub(source.seq_offset)));
+            }
+            newer_start = Some(start);
+        }
+        None
+    }
+
+    /// Rewrites a publisher packet forwarded before, to retransmit it.
+    pub(crate) fn retransmit(
+        &self,
+        packet: &Packet,
+        codec: &RTCRtpCodecCapability,
+    ) -> Option<Packet> {
+        // a source resumed after a pause appears once per resumption
+        self.sources
+            .iter()
+            .find(|source| {
+                source.ssrc == packet.header.ssrc
+                    && !seq_newer(source.start_seq, packet.header.sequence_number)
+            })
+            .map(|source| source.rewrite(packet, codec))
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7741#section-4.2
+struct Vp8Descriptor {
+    // offset of the picture id and whether it is 15 bits long
+    picture_id: Option<(usize, bool)>,
+    tl0_pic_idx: Option<usize>,
+}
+
+impl Vp8Descriptor {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.first()? & 0x80 == 0 {
+            return None;
+        }
+        let extension = *payload.get(1)?;
+        let mut offset = 2;
+        let mut picture_id = None;
+        if extension & 0x80 != 0 {
+            let long = payload.get(offset)? & 0x80 != 0;
+            if long {
+                payload.get(offset + 1)?;
+            }
+            picture_id = Some((offset, long));
+            offset += if long { 2 } else { 1 };
+        }
+        let mut tl0_pic_idx = None;
+        if extension & 0x40 != 0 {
+            payload.get(offset)?;
+            tl0_pic_idx = Some(offset);
+        }
+        Some(Self {
+            picture_id,
+            tl0_pic_idx,
+        })
+    }
+
+    fn picture_id(&self, payload: &[u8]) -> Option<u16> {
+        self.picture_id.map(|(offset, long)| {
+            if long {
+                u16::from_be_bytes([payload[offset] & 0x7F, payload[offset + 1]])
+            } else {
+                payload[offset] as u16
+            }
+        })
+    }
+
+    fn tl0_pic_idx(&self, payload: &[u8]) -> Option<u8> {
+        self.tl0_pic_idx.map(|offset| payload[offset])
+    }
+}
+
+fn rewrite_vp8(payload: &Bytes, offsets: Vp8Offsets) -> Bytes {
+    let Some(descriptor) = Vp8Descriptor::parse(payload) else {
+        return payload.clone();
+    };
+    let mut payload = payload.to_vec();
+    if let Some((offset, long)) = descriptor.picture_id {
+        let picture_id = descriptor
+            .picture_id(&payload)
+            .unwrap_or_default()
+            .wrapping_add(offsets.picture_id);
+        if long {
+            let picture_id = 0x8000 | (picture_id & 0x7FFF);
+            payload[offset..offset + 2].copy_from_slice(&picture_id.to_be_bytes());
+        } else {
+            payload[offset] = picture_id as u8 & 0x7F;
+        }
+    }
+    if let Some(offset) = descriptor.tl0_pic_idx {
+        payload[offset] = payload[offset].wrapping_add(offsets.tl0_pic_idx);
+    }
+    Bytes::from(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::rtp::header::Header;
+
+    const KEYFRAME: &[u8] = &[0x10, 0x00];
+    const DELTA: &[u8] = &[0x10, 0x01];
+
+    fn vp8() -> RTCRtpCodecCapability {
+        RTCRtpCodecCapability {
+            mime_type: "video/VP8".to_owned(),
+            clock_rate: 90000,
+            ..Default::default()
+        }
+    }
+
+    fn packet(ssrc: u32, sequence_number: u16, timestamp: u32, payload: &[u8]) -> Packet {
+        Packet {
+            header: Header {
+                ssrc,
+                sequence_number,
+                timestamp,
+                ..Default::default()
+            },
+            payload: Bytes::copy_from_slice(payload),
+        }
+    }
+
+    fn out(munger: &mut RtpMunger, packet: Packet) -> Option<(u16, u32)> {
+        munger
+            .process(&packet, &vp8())
+            .map(|packet| (packet.header.sequence_number, packet.header.timestamp))
+    }
+
+    // the timestamp moves forward by the time elapsed, at least one tick
+    fn assert_continues(timestamp: u32, last: u32) {
+        let delta = timestamp.wrapping_sub(last);
+        assert!((1..9000).contains(&delta), "{} after {}", timestamp, last);
+    }
+
+    #[test]
+    fn test_first_source_unchanged() {
+        let mut munger = RtpMunger::default();
+        assert_eq!(out(&mut munger, packet(1, 100, 3000, DELTA)), None);
+        assert_eq!(
+            out(&mut munger, packet(1, 101, 6000, KEYFRAME)),
+            Some((101, 6000))
+        );
+        assert_eq!(
+            out(&mut munger, packet(1, 102, 9000, DELTA)),
+            Some((102, 9000))
+        );
+        // older than the first packet forwarded
+        assert_eq!(out(&mut munger, packet(1, 100, 3000, DELTA)), None);
+    }
+
+    #[test]
+    fn test_layer_switch_continues() {
+        let mut munger = RtpMunger::default();
+        out(&mut munger, packet(1, 100, 3000, KEYFRAME));
+        out(&mut munger, packet(1, 101, 6000, DELTA));
+
+        // the new layer waits for its keyframe, the old one goes on meanwhile
+        assert_eq!(out(&mut munger, packet(2, 5000, 700_000, DELTA)), None);
+        let (sequence_number, timestamp) =
+            out(&mut munger, packet(2, 5001, 703_000, KEYFRAME)).unwrap();
+        assert_eq!(sequence_number, 102);
+        assert_continues(timestamp, 6000);
+        assert_eq!(
+            out(&mut munger, packet(2, 5002, 706_000, DELTA)),
+            Some((103, timestamp.wrapping_add(3000)))
+        );
+        // packets of the layer from before the switch are dropped
+        assert_eq!(out(&mut munger, packet(2, 5000, 700_000, DELTA)), None);
+    }
+
+    #[test]
+    fn test_switch_across_wraparound() {
+        let mut munger = RtpMunger::default();
+        out(&mut munger, packet(1, 65534, 0xFFFF_F000, KEYFRAME));
+        assert_eq!(
+            out(&mut munger, packet(1, 65535, 0xFFFF_FFF0, DELTA)),
+            Some((65535, 0xFFFF_FFF0))
+        );
+
+        let (sequence_number, timestamp) = out(&mut munger, packet(2, 10, 500, KEYFRAME)).unwrap();
+        assert_eq!(sequence_number, 0);
+        assert_continues(timestamp, 0xFFFF_FFF0);
+        assert_eq!(
+            out(&mut munger, packet(2, 11, 3500, DELTA)),
+            Some((1, timestamp.wrapping_add(3000)))
+        );
+
+        // the input wraps too
+        let mut munger = RtpMunger::default();
+        out(&mut munger, packet(1, 100, 1000, KEYFRAME));
+        out(&mut munger, packet(2, 65535, u32::MAX, KEYFRAME));
+        let (sequence_number, timestamp) = out(&mut munger, packet(2, 0, 2999, DELTA)).unwrap();
+        assert_eq!(sequence_number, 102);
+        assert_continues(timestamp, 1000);
+    }
+
+    #[test]
+    fn test_resume_after_pause() {
+        let mut munger = RtpMunger::default();
+        out(&mut munger, packet(1, 100, 3000, KEYFRAME));
+        munger.pause();
+        assert_eq!(out(&mut munger, packet(1, 200, 303_000, DELTA)), None);
+        let (sequence_number, timestamp) =
+            out(&mut munger, packet(1, 201, 306_000, KEYFRAME)).unwrap();
+        assert_eq!(sequence_number, 101);
+        assert_continues(timestamp, 3000);
+    }
+
+    #[test]
+    fn test_lookup_and_retransmit() {
+        let mut munger = RtpMunger::default();
+        out(&mut munger, packet(1, 100, 3000, KEYFRAME));
+        out(&mut munger, packet(1, 101, 6000, DELTA));
+        out(&mut munger, packet(2, 65535, 9000, KEYFRAME));
+        out(&mut munger, packet(2, 0, 12000, DELTA));
+
+        assert_eq!(munger.lookup(99), None);
+        assert_eq!(munger.lookup(101), Some((1, 101)));
+        assert_eq!(munger.lookup(102), Some((2, 65535)));
+        assert_eq!(munger.lookup(103), Some((2, 0)));
+
+        let retransmitted = munger
+            .retransmit(&packet(1, 101, 6000, DELTA), &vp8())
+            .unwrap();
+        assert_eq!(retransmitted.header.sequence_number, 101);
+        let retransmitted = munger
+            .retransmit(&packet(2, 0, 12000, DELTA), &vp8())
+            .unwrap();
+        assert_eq!(retransmitted.header.sequence_number, 103);
+        assert!(munger
+            .retransmit(&packet(2, 65000, 0, DELTA), &vp8())
+            .is_none());
+    }
+
+    #[test]
+    fn test_vp8_picture_id_continues() {
+        // X, I and L set, a 15 bit picture id and a TL0PICIDX
+        let payload = |picture_id: u16, tl0_pic_idx: u8, keyframe: bool| {
+            let [high, low] = (0x8000 | picture_id).to_be_bytes();
+            vec![0x90, 0xC0, high, low, tl0_pic_idx, !keyframe as u8]
+        };
+        let picture = |packet: Packet| {
+            let descriptor = Vp8Descriptor::parse(&packet.payload).unwrap();
+            (
+                descriptor.picture_id(&packet.payload).unwrap(),
+                descriptor.tl0_pic_idx(&packet.payload).unwrap(),
+            )
+        };
+
+        let mut munger = RtpMunger::default();
+        munger.process(&packet(1, 1, 0, &payload(0x7FFF, 255, true)), &vp8());
+        let switched = munger
+            .process(&packet(2, 1, 0, &payload(40, 7, true)), &vp8())
+            .unwrap();
+        assert_eq!(picture(switched), (0, 0));
+        let next = munger
+            .process(&packet(2, 2, 0, &payload(41, 8, false)), &vp8())
+            .unwrap();
+        assert_eq!(picture(next), (1, 1));
+    }
+}
@@ -0,0 +1,80 @@
+use super::track::ForwardData;
+
+// about 3 seconds of a 1.5 Mbps video layer
+const CACHE_SIZE: usize = 512;
+
+/// The last packets received on a publisher track, by sequence number, to
+/// answer the NACKs of subscribers.
+pub(crate) struct PacketCache {
+    packets: Vec<Option<ForwardData>>,
+}
+
+impl PacketCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            packets: vec![None; CACHE_SIZE],
+        }
+    }
+
+    pub(crate) fn push(&mut self, packet: ForwardData) {
+        let slot = packet.header.sequence_number as usize % CACHE_SIZE;
+        self.packets[slot] = Some(packet);
+    }
+
+    pub(crate) fn get(&self, sequence_number: u16) -> Option<ForwardData> {
+        self.packets[sequence_number as usize % CACHE_SIZE]
+            .as_ref()
+            .filter(|packet| packet.header.sequence_number == sequence_number)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use webrtc::rtp::header::Header;
+    use webrtc::rtp::packet::Packet;
+
+    use super::*;
+
+    fn packet(sequence_number: u16) -> ForwardData {
+        Arc::new(Packet {
+            header: Header {
+                sequence_number,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_hit() {
+        let mut cache = PacketCache::new();
+        cache.push(packet(65535));
+        cache.push(packet(0));
+        assert_eq!(cache.get(65535).unwrap().header.sequence_number, 65535);
+        assert_eq!(cache.get(0).unwrap().header.sequence_number, 0);
+    }
+
+    #[test]
+    fn test_miss() {
+        let mut cache = PacketCache::new();
+        assert!(cache.get(10).is_none());
+        cache.push(packet(10));
+        assert!(cache.get(11).is_none());
+        // same slot, other sequence number
+        assert!(cache.get(10 + CACHE_SIZE as u16).is_none());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut cache = PacketCache::new();
+        for sequence_number in 0..=CACHE_SIZE as u16 {
+            cache.push(packet(sequence_number));
+        }
+        assert!(cache.get(0).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(CACHE_SIZE as u16).is_some());
+    }
+}
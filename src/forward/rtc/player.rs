@@ -0,0 +1,507 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::time::{sleep_until, Instant};
+use tracing::{info, warn};
+use webrtc::api::media_engine::{MIME_TYPE_AV1, MIME_TYPE_OPUS, MIME_TYPE_VP8, MIME_TYPE_VP9};
+use webrtc::media::io::ivf_reader::IVFReader;
+use webrtc::media::Sample;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::TrackLocal;
+
+use crate::error::AppError;
+use crate::result::Result;
+
+const OPUS_CLOCK_RATE: u32 = 48000;
+// frames read ahead of the playback
+const FRAME_QUEUE: usize = 16;
+
+/// What a file publisher plays.
+#[derive(Clone, Debug, Default)]
+pub struct PlayConfig {
+    /// one track per file, IVF (VP8, VP9, AV1) or Ogg (Opus), played together
+    pub files: Vec<PathBuf>,
+    /// start over when every file has ended, instead of leaving
+    pub looping: bool,
+}
+
+/// `file` under `dir`, `None` when it is absolute or goes up.
+pub fn resolve(dir: &Path, file: &str) -> Option<PathBuf> {
+    let file = Path::new(file);
+    file.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| dir.join(file))
+}
+
+/// Publishes files from disk at real-time pace through a local peer
+/// connection, which the stream sees as any other publisher.
+pub(crate) struct Player {
+    stream: String,
+    tracks: Vec<(Arc<TrackLocalStaticSample>, PathBuf)>,
+    looping: bool,
+}
+
+impl Player {
+    /// Adds a track for each file to `peer`, to be offered to the stream.
+    pub(crate) async fn new(
+        stream: String,
+        peer: &Arc<RTCPeerConnection>,
+        config: PlayConfig,
+    ) -> Result<Self> {
+        if config.files.is_empty() {
+            return Err(AppError::bad_request_details("no file to play", "files"));
+        }
+        let mut tracks = vec![];
+        for (index, path) in config.files.into_iter().enumerate() {
+            let codec = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || PlayFile::open(&path).map(|file| file.codec()))
+                    .await??
+            };
+            let kind = if codec.mime_type == MIME_TYPE_OPUS {
+                RTPCodecType::Audio
+            } else {
+                RTPCodecType::Video
+            };
+            let track = Arc::new(TrackLocalStaticSample::new(
+                codec,
+                format!("{}-{}", kind, index),
+                stream.clone(),
+            ));
+            let rtp_sender = peer
+                .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
+                .await?;
+            // the RTCP of the stream goes through the interceptors, nothing to answer
+            tokio::spawn(async move {
+                let mut b = vec![0u8; 1500];
+                while rtp_sender.read(&mut b).await.is_ok() {}
+            });
+            tracks.push((track, path));
+        }
+        Ok(Self {
+            stream,
+            tracks,
+            looping: config.looping,
+        })
+    }
+
+    /// Plays once `peer` is connected, until the files end or `stop` changes,
+    /// then closes `peer`.
+    pub(crate) async fn play(
+        self,
+        peer: Arc<RTCPeerConnection>,
+        connected: Arc<Notify>,
+        mut stop: watch::Receiver<()>,
+    ) {
+        tokio::select! {
+            _ = connected.notified() => {}
+            _ = stop.changed() => {
+                let _ = peer.close().await;
+                return;
+            }
+        }
+        info!("[{}] [player] start", self.stream);
+        loop {
+            // the files of a round start together
+            let start = Instant::now();
+            let rounds = self.tracks.iter().map(|(track, path)| {
+                Self::play_file(
+                    (self.stream.clone(), start),
+                    track.clone(),
+                    path.clone(),
+                    stop.clone(),
+                )
+            });
+            let played = futures_util::future::join_all(rounds).await;
+            if !self.looping || !played.into_iter().all(|played| played) {
+                break;
+            }
+        }
+        info!("[{}] [player] stop", self.stream);
+        let _ = peer.close().await;
+    }
+
+    // false when stopped or the file can not be read
+    async fn play_file(
+        (stream, start): (String, Instant),
+        track: Arc<TrackLocalStaticSample>,
+        path: PathBuf,
+        mut stop: watch::Receiver<()>,
+    ) -> bool {
+        // the file is read on a blocking thread, a few frames ahead
+        let (frames_sender, mut frames) = mpsc::channel(FRAME_QUEUE);
+        tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || read_frames(&path, frames_sender)
+        });
+        let mut duration = Duration::ZERO;
+        let mut next = frames.recv().await;
+        loop {
+            let (data, at) = match next {
+                Some(Ok(frame)) => frame,
+                None => return true,
+                Some(Err(err)) => {
+                    warn!("[{}] [player] {} err: {:?}", stream, path.display(), err);
+                    return false;
+                }
+            };
+            next = frames.recv().await;
+            // the last frame lasts as long as the one before
+            if let Some(Ok((_, next_at))) = &next {
+                duration = next_at.saturating_sub(at);
+            }
+            tokio::select! {
+                _ = sleep_until(start + at) => {}
+                _ = stop.changed() => return false,
+            }
+            let sample = Sample {
+                data,
+                duration,
+                ..Default::default()
+            };
+            if let Err(err) = track.write_sample(&sample).await {
+                warn!("[{}] [player] write err: {}", stream, err);
+                return false;
+            }
+        }
+    }
+}
+
+// Sends the frames of the file at `path` until its end, an error, or the
+// receiver is dropped.
+fn read_frames(path: &Path, frames: mpsc::Sender<Result<(Bytes, Duration)>>) {
+    let mut file = match PlayFile::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            let _ = frames.blocking_send(Err(err));
+            return;
+        }
+    };
+    loop {
+        let frame = match file.next_frame() {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => return,
+            Err(err) => Err(err),
+        };
+        let last = frame.is_err();
+        if frames.blocking_send(frame).is_err() || last {
+            return;
+        }
+    }
+}
+
+enum PlayFile {
+    Ivf {
+        reader: IVFReader<BufReader<File>>,
+        mime_type: &'static str,
+        // a frame timestamp lasts numerator / denominator seconds
+        timebase: (u64, u64),
+    },
+    Ogg {
+        reader: OggReader<BufReader<File>>,
+        // in 48 kHz samples
+        position: u64,
+    },
+}
+
+impl PlayFile {
+    fn open(path: &Path) -> Result<Self> {
+        let reader = BufReader::new(File::open(path).map_err(|err| {
+            AppError::bad_request_details(format!("{}: {}", path.display(), err), "files")
+        })?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ivf") => {
+                let (reader, header) = IVFReader::new(reader)?;
+                let mime_type = match &header.four_cc {
+                    b"VP80" => MIME_TYPE_VP8,
+                    b"VP90" => MIME_TYPE_VP9,
+                    b"AV01" => MIME_TYPE_AV1,
+                    four_cc => {
+                        let four_cc = String::from_utf8_lossy(four_cc);
+                        return Err(AppError::codec_not_supported_details(
+                            format!("{}: {} is not played", path.display(), four_cc),
+                            four_cc,
+                        ));
+                    }
+                };
+                Ok(Self::Ivf {
+                    reader,
+                    mime_type,
+                    timebase: (
+                        header.timebase_numerator.max(1) as u64,
+                        header.timebase_denominator.max(1) as u64,
+                    ),
+                })
+            }
+            Some("ogg") | Some("opus") => {
+                let mut reader = OggReader::new(reader);
+                let head = reader.next_packet()?.unwrap_or_default();
+                if !head.starts_with(b"OpusHead") {
+                    return Err(AppError::codec_not_supported(format!(
+                        "{}: not Opus",
+                        path.display()
+                    )));
+                }
+                // OpusTags
+                reader.next_packet()?;
+                Ok(Self::Ogg {
+                    reader,
+                    position: 0,
+                })
+            }
+            _ => Err(AppError::codec_not_supported(format!(
+                "{}: only .ivf and .ogg files are played",
+                path.display()
+            ))),
+        }
+    }
+
+    fn codec(&self) -> RTCRtpCodecCapability {
+        match self {
+            Self::Ivf { mime_type, .. } => RTCRtpCodecCapability {
+                mime_type: mime_type.to_string(),
+                clock_rate: 90000,
+                sdp_fmtp_line: if *mime_type == MIME_TYPE_VP9 {
+                    "profile-id=0".to_owned()
+                } else {
+                    String::new()
+                },
+                ..Default::default()
+            },
+            // Opus is always negotiated with 2 channels
+            Self::Ogg { .. } => RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_OPUS.to_owned(),
+                clock_rate: OPUS_CLOCK_RATE,
+                channels: 2,
+                sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The next frame, with its time from the start of the file.
+    fn next_frame(&mut self) -> Result<Option<(Bytes, Duration)>> {
+        match self {
+            Self::Ivf {
+                reader,
+                timebase: (numerator, denominator),
+                ..
+            } => match reader.parse_next_frame() {
+                Ok((frame, header)) => {
+                    let at = Duration::from_nanos(
+                        (header.timestamp as u128 * *numerator as u128 * 1_000_000_000
+                            / *denominator as u128) as u64,
+                    );
+                    Ok(Some((frame.freeze(), at)))
+                }
+                Err(webrtc::media::Error::Io(err))
+                    if err.0.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    Ok(None)
+                }
+                Err(err) => Err(err.into()),
+            },
+            Self::Ogg {
+                reader, position, ..
+            } => {
+                let Some(packet) = reader.next_packet()? else {
+                    return Ok(None);
+                };
+                let at = Duration::from_micros(*position * 1_000_000 / OPUS_CLOCK_RATE as u64);
+                *position += opus_samples(&packet);
+                Ok(Some((packet.into(), at)))
+            }
+        }
+    }
+}
+
+/// Reads the packets of an Ogg stream, the first logical stream only.
+struct OggReader<R: Read> {
+    reader: R,
+    serial: Option<u32>,
+    // lacing values and data of the current page left to read
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl<R: Read> OggReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            serial: None,
+            lacing: vec![],
+            data: vec![],
+        }
+    }
+
+    fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut packet = vec![];
+        loop {
+            if self.lacing.is_empty() && !self.next_page()? {
+                return Ok(None);
+            }
+            let size = self.lacing.remove(0) as usize;
+            let size = size.min(self.data.len());
+            packet.extend(self.data.drain(..size));
+            // a segment shorter than 255 ends the packet
+            if size < 255 {
+                return Ok(Some(packet));
+            }
+        }
+    }
+
+    // false at the end of the stream
+    fn next_page(&mut self) -> io::Result<bool> {
+        loop {
+            let mut header = [0u8; 27];
+            match self.reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(err) => return Err(err),
+            }
+            if &header[..4] != b"OggS" {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad Ogg page"));
+            }
+            let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+            self.lacing = vec![0u8; header[26] as usize];
+            self.reader.read_exact(&mut self.lacing)?;
+            self.data = vec![0u8; self.lacing.iter().map(|size| *size as usize).sum()];
+            self.reader.read_exact(&mut self.data)?;
+            if *self.serial.get_or_insert(serial) == serial {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc6716#section-3.1
+// The duration of an Opus packet in 48 kHz samples, from its TOC byte.
+fn opus_samples(packet: &[u8]) -> u64 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = toc >> 3;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][(config % 4) as usize],
+        12..=15 => [480, 960][(config % 2) as usize],
+        _ => [120, 240, 480, 960][(config % 4) as usize],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| count & 0x3F) as u64,
+    };
+    frame * frames
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // an Ogg page of `serial` with its lacing values and data, the CRC is not checked
+    fn page(serial: u32, lacing: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 10]); // version, header type, granule position
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]); // sequence number, CRC
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(lacing);
+        page.extend_from_slice(data);
+        page
+    }
+
+    fn packets(file: Vec<u8>) -> Vec<Vec<u8>> {
+        let mut reader = OggReader::new(Cursor::new(file));
+        let mut packets = vec![];
+        while let Some(packet) = reader.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        packets
+    }
+
+    #[test]
+    fn test_ogg_packets_in_page() {
+        let file = page(1, &[3, 2], &[1, 2, 3, 4, 5]);
+        assert_eq!(packets(file), [vec![1, 2, 3], vec![4, 5]]);
+    }
+
+    #[test]
+    fn test_ogg_packet_spanning_pages() {
+        let mut file = page(1, &[255], &[7; 255]);
+        file.extend(page(1, &[255, 45, 1], &[8; 301]));
+        let packets = packets(file);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].len(), 555);
+        assert_eq!(&packets[0][..255], &[7; 255]);
+        assert_eq!(&packets[0][255..], &[8; 300]);
+        assert_eq!(packets[1], [8]);
+    }
+
+    #[test]
+    fn test_ogg_packet_of_255_bytes() {
+        // ends with a zero lacing value
+        let mut file = page(1, &[255, 0], &[7; 255]);
+        file.extend(page(1, &[1], &[9]));
+        assert_eq!(packets(file), [vec![7; 255], vec![9]]);
+    }
+
+    #[test]
+    fn test_ogg_first_stream_only() {
+        let mut file = page(1, &[1], &[1]);
+        file.extend(page(2, &[1], &[2]));
+        file.extend(page(1, &[1], &[3]));
+        assert_eq!(packets(file), [vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn test_ogg_bad_page() {
+        let mut reader = OggReader::new(Cursor::new(b"RIFF".repeat(10)));
+        assert!(reader.next_packet().is_err());
+        let mut reader = OggReader::new(Cursor::new(vec![]));
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_opus_samples() {
+        let toc = |config: u8, code: u8| (config << 3) | code;
+        assert_eq!(opus_samples(&[]), 0);
+        // SILK 10, 20, 40 and 60 ms
+        assert_eq!(opus_samples(&[toc(0, 0)]), 480);
+        assert_eq!(opus_samples(&[toc(1, 0)]), 960);
+        assert_eq!(opus_samples(&[toc(10, 0)]), 1920);
+        assert_eq!(opus_samples(&[toc(11, 0)]), 2880);
+        // Hybrid 10 and 20 ms
+        assert_eq!(opus_samples(&[toc(14, 0)]), 480);
+        assert_eq!(opus_samples(&[toc(15, 0)]), 960);
+        // CELT 2.5 and 20 ms
+        assert_eq!(opus_samples(&[toc(28, 0)]), 120);
+        assert_eq!(opus_samples(&[toc(31, 0)]), 960);
+        // two frames, then a frame count
+        assert_eq!(opus_samples(&[toc(1, 1)]), 1920);
+        assert_eq!(opus_samples(&[toc(1, 2)]), 1920);
+        assert_eq!(opus_samples(&[toc(31, 3), 0x80 | 3]), 2880);
+        assert_eq!(opus_samples(&[toc(31, 3)]), 0);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new("/records");
+        assert_eq!(
+            resolve(dir, "1/st/a.ivf"),
+            Some(PathBuf::from("/records/1/st/a.ivf"))
+        );
+        assert_eq!(resolve(dir, "../a.ivf"), None);
+        assert_eq!(resolve(dir, "1/../../a.ivf"), None);
+        assert_eq!(resolve(dir, "/etc/a.ivf"), None);
+        assert_eq!(resolve(dir, "./a.ivf"), None);
+    }
+}
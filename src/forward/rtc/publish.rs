@@ -1,29 +1,38 @@
 use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tracing::debug;
 use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTPCodecType};
 
 use crate::forward::rtc::message::SessionInfo;
 use crate::forward::rtc::rtcp::RtcpMessage;
+use crate::metrics;
 
 use super::get_peer_id;
+use super::keyframe::KeyframeRequests;
 use super::media::MediaInfo;
 
 pub(crate) struct PublishRTCPeerConnection {
     pub(crate) id: String,
+    pub(crate) user_id: u32,
     pub(crate) peer: Arc<RTCPeerConnection>,
     pub(crate) media_info: MediaInfo,
+    // the codecs accepted in our answer, the publisher sends the first of each kind
+    answer_info: MediaInfo,
     pub(crate) create_time: i64,
 }
 
 impl PublishRTCPeerConnection {
     pub(crate) async fn new(
         path: String,
+        user_id: u32,
         peer: Arc<RTCPeerConnection>,
-        rtcp_recv: broadcast::Receiver<(RtcpMessage, u32)>,
+        (rtcp_recv, keyframe_request_interval): (broadcast::Receiver<(RtcpMessage, u32)>, u64),
     ) -> Result<Self> {
         let id = get_peer_id(&peer);
         let peer_weak = Arc::downgrade(&peer);
@@ -33,15 +42,48 @@ impl PublishRTCPeerConnection {
                 .ok_or(anyhow!("not set remote_description"))?
                 .unmarshal()?,
         )?;
-        tokio::spawn(Self::peer_send_rtcp(path, id.clone(), peer_weak, rtcp_recv));
+        let answer_info = MediaInfo::try_from(
+            peer.local_description()
+                .await
+                .ok_or(anyhow!("not set local_description"))?
+                .unmarshal()?,
+        )?;
+        tokio::spawn(Self::peer_send_rtcp(
+            path,
+            id.clone(),
+            peer_weak,
+            rtcp_recv,
+            KeyframeRequests::new(Duration::from_millis(keyframe_request_interval)),
+        ));
         Ok(Self {
             id,
+            user_id,
             peer,
             media_info,
+            answer_info,
             create_time: Utc::now().timestamp_millis(),
         })
     }
 
+    /// The number of tracks of `kind` the publisher sends.
+    pub(crate) fn track_count(&self, kind: RTPCodecType) -> usize {
+        match kind {
+            RTPCodecType::Video => self.media_info.video_transceiver.0 as usize,
+            RTPCodecType::Audio => self.media_info.audio_transceiver.0 as usize,
+            _ => 0,
+        }
+    }
+
+    /// The codec the publisher negotiated for `kind`, if it sends that kind.
+    pub(crate) fn codec(&self, kind: RTPCodecType) -> Option<RTCRtpCodecCapability> {
+        if self.track_count(kind) == 0 {
+            return None;
+        }
+        self.answer_info
+            .primary_codec(kind)
+            .map(|codec| codec.capability)
+    }
+
     pub(crate) fn info(&self) -> SessionInfo {
         SessionInfo {
             id: self.id.clone(),
@@ -55,18 +97,60 @@ impl PublishRTCPeerConnection {
         id: String,
         peer: Weak<RTCPeerConnection>,
         mut recv: broadcast::Receiver<(RtcpMessage, u32)>,
+        mut keyframe_requests: KeyframeRequests,
     ) {
-        while let (Ok((rtcp_message, media_ssrc)), Some(pc)) = (recv.recv().await, peer.upgrade()) {
-            debug!(
-                "[{}] [{}] ssrc : {} ,send rtcp : {:?}",
-                path, id, media_ssrc, rtcp_message
-            );
-            if pc
-                .write_rtcp(&[rtcp_message.to_rtcp_packet(media_ssrc)])
-                .await
-                .is_err()
-            {
+        loop {
+            let deadline = keyframe_requests.next_deadline();
+            let messages = tokio::select! {
+                result = recv.recv() => {
+                    let (rtcp_message, media_ssrc) = match result {
+                        Ok(message) => message,
+                        Err(RecvError::Lagged(n)) => {
+                            metrics::BROADCAST_LAGGED
+                                .with_label_values(&[metrics::CHANNEL_RTCP])
+                                .inc_by(n);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if !rtcp_message.is_keyframe_request() {
+                        vec![(rtcp_message, media_ssrc, 0)]
+                    } else {
+                        metrics::KEYFRAME_REQUESTS
+                            .with_label_values(&[metrics::KEYFRAME_REQUESTED])
+                            .inc();
+                        match keyframe_requests.request(rtcp_message, media_ssrc) {
+                            Some((rtcp_message, fir_sequence_number)) => {
+                                vec![(rtcp_message, media_ssrc, fir_sequence_number)]
+                            }
+                            None => continue,
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                    keyframe_requests.due()
+                }
+            };
+            let Some(pc) = peer.upgrade() else {
                 break;
+            };
+            for (rtcp_message, media_ssrc, fir_sequence_number) in messages {
+                debug!(
+                    "[{}] [{}] ssrc : {} ,send rtcp : {:?}",
+                    path, id, media_ssrc, rtcp_message
+                );
+                if rtcp_message.is_keyframe_request() {
+                    metrics::KEYFRAME_REQUESTS
+                        .with_label_values(&[metrics::KEYFRAME_FORWARDED])
+                        .inc();
+                }
+                if pc
+                    .write_rtcp(&[rtcp_message.to_rtcp_packet(media_ssrc, fir_sequence_number)])
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
             }
         }
     }
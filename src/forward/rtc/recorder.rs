@@ -0,0 +1,438 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, watch, RwLock};
+use tracing::{debug, info, warn};
+use webrtc::media::io::h264_writer::H264Writer;
+use webrtc::media::io::ogg_writer::OggWriter;
+use webrtc::media::io::Writer;
+use webrtc::media::Error as MediaError;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+use crate::metrics;
+
+use super::ivf::IvfWriter;
+use super::keyframe::is_keyframe;
+use super::rtcp::RtcpMessage;
+use super::track::{ForwardData, PublishTrackRemote};
+
+// keyframe requests while waiting for one, at most this often
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+// the layers of a simulcast track start together and their bitrate is measured
+// every second: the layer to record is chosen once they all are, or at the latest
+const LAYER_SETTLE: Duration = Duration::from_secs(2);
+const LAYER_TIMEOUT: Duration = Duration::from_secs(5);
+// packets waiting for the file writer, a slow disk makes the track lag
+const FILE_QUEUE: usize = 256;
+
+/// Where and how the streams of a room are recorded.
+#[derive(Clone, Debug, Default)]
+pub struct RecordConfig {
+    /// the files go to `dir/room_id/stream/`
+    pub dir: PathBuf,
+    pub room_id: i32,
+    /// record every stream from the moment it is published
+    pub auto_start: bool,
+    /// a file is continued in a new one past this size in bytes, 0 for no limit
+    pub max_size: u64,
+    /// or past this duration, zero for no limit
+    pub max_duration: Duration,
+}
+
+/// Records the tracks of a publisher, one file per track, as an internal
+/// subscriber of their broadcast. The recording stops when it is dropped.
+pub(crate) struct Recorder {
+    _stop: watch::Sender<()>,
+}
+
+impl Recorder {
+    pub(crate) fn start(
+        stream: String,
+        user_id: u32,
+        config: RecordConfig,
+        (publish_tracks, publish_tracks_change): (
+            Arc<RwLock<Vec<PublishTrackRemote>>>,
+            broadcast::Receiver<()>,
+        ),
+        publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
+    ) -> Self {
+        let (stop, stop_recv) = watch::channel(());
+        info!("[{}] [record] start, user {}", stream, user_id);
+        tokio::spawn(Self::record_stream(
+            (stream, user_id, config),
+            (publish_tracks, publish_tracks_change),
+            publish_rtcp_sender,
+            stop_recv,
+        ));
+        Self { _stop: stop }
+    }
+
+    // Starts recording each track as it is published. For a simulcast track
+    // the layer with the highest bitrate is recorded, chosen once its layers
+    // have been measured.
+    async fn record_stream(
+        (stream, user_id, config): (String, u32, RecordConfig),
+        (publish_tracks, mut publish_tracks_change): (
+            Arc<RwLock<Vec<PublishTrackRemote>>>,
+            broadcast::Receiver<()>,
+        ),
+        publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
+        mut stop: watch::Receiver<()>,
+    ) {
+        let mut recording = HashSet::new();
+        // the simulcast tracks waiting for their layers, by mid, since when
+        let mut settling: HashMap<String, Instant> = HashMap::new();
+        let mut settle_tick = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            {
+                let publish_tracks = publish_tracks.read().await;
+                for publish_track in publish_tracks.iter() {
+                    if recording.contains(&publish_track.mid) {
+                        continue;
+                    }
+                    let layers: Vec<&PublishTrackRemote> = publish_tracks
+                        .iter()
+                        .filter(|t| t.mid == publish_track.mid)
+                        .collect();
+                    if !publish_track.rid.is_empty() {
+                        let elapsed = settling
+                            .entry(publish_track.mid.clone())
+                            .or_insert_with(Instant::now)
+                            .elapsed();
+                        let measured = layers.iter().all(|t| t.bitrate() > 0);
+                        if !(measured && elapsed >= LAYER_SETTLE || elapsed >= LAYER_TIMEOUT) {
+                            continue;
+                        }
+                    }
+                    let Some(layer) = layers.into_iter().max_by_key(|t| t.bitrate()) else {
+                        continue;
+                    };
+                    recording.insert(publish_track.mid.clone());
+                    settling.remove(&publish_track.mid);
+                    tokio::spawn(Self::record_track(
+                        (stream.clone(), user_id, config.clone()),
+                        layer.clone(),
+                        publish_rtcp_sender.clone(),
+                        stop.clone(),
+                    ));
+                }
+            }
+            tokio::select! {
+                change = publish_tracks_change.recv() => {
+                    if let Err(RecvError::Closed) = change {
+                        break;
+                    }
+                }
+                _ = settle_tick.tick(), if !settling.is_empty() => {}
+                _ = stop.changed() => break,
+            }
+        }
+        info!("[{}] [record] stop", stream);
+    }
+
+    async fn record_track(
+        (stream, user_id, config): (String, u32, RecordConfig),
+        publish_track: PublishTrackRemote,
+        publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
+        mut stop: watch::Receiver<()>,
+    ) {
+        let kind = publish_track.kind;
+        let mime_type = publish_track.track.codec().capability.mime_type;
+        let ssrc = publish_track.track.ssrc();
+        if extension(&mime_type).is_none() {
+            warn!("[{}] [record] {} is not recorded", stream, mime_type);
+            return;
+        }
+        let mut recv = publish_track.subscribe();
+        // the files are written on a blocking thread, the current one is
+        // tracked here by when it was created and its size
+        let (files, commands) = mpsc::channel(FILE_QUEUE);
+        tokio::task::spawn_blocking({
+            let (stream, config, publish_track) =
+                (stream.clone(), config.clone(), publish_track.clone());
+            move || Self::write_files((&stream, user_id, &config), &publish_track, commands)
+        });
+        let mut file: Option<(Instant, u64)> = None;
+        // a video file starts with a keyframe, and continues from one after a loss
+        let mut waiting_keyframe = kind == RTPCodecType::Video;
+        let mut keyframe_requested: Option<Instant> = None;
+        let mut last_sequence_number: Option<u16> = None;
+        let request_keyframe = |requested: &mut Option<Instant>| {
+            if requested.is_some_and(|at| at.elapsed() < KEYFRAME_REQUEST_INTERVAL) {
+                return;
+            }
+            *requested = Some(Instant::now());
+            let _ = publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, ssrc));
+        };
+        loop {
+            let packet = tokio::select! {
+                packet = recv.recv() => match packet {
+                    Ok(packet) => packet,
+                    Err(RecvError::Lagged(n)) => {
+                        metrics::BROADCAST_LAGGED
+                            .with_label_values(&[metrics::CHANNEL_RTP])
+                            .inc_by(n);
+                        waiting_keyframe = kind == RTPCodecType::Video;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = stop.changed() => break,
+            };
+            let sequence_number = packet.header.sequence_number;
+            if let Some(last) = last_sequence_number {
+                // late packets are dropped, the files are written in order
+                if (sequence_number.wrapping_sub(last) as i16) <= 0 {
+                    continue;
+                }
+                if sequence_number != last.wrapping_add(1) && kind == RTPCodecType::Video {
+                    waiting_keyframe = true;
+                }
+            }
+            last_sequence_number = Some(sequence_number);
+
+            let keyframe = kind == RTPCodecType::Video && is_keyframe(&mime_type, &packet.payload);
+            if waiting_keyframe && !keyframe {
+                request_keyframe(&mut keyframe_requested);
+                continue;
+            }
+            waiting_keyframe = false;
+            if file.is_some_and(|file| full(&config, file)) {
+                if kind == RTPCodecType::Audio || keyframe {
+                    file = None;
+                } else {
+                    request_keyframe(&mut keyframe_requested);
+                }
+            }
+            let bytes = packet.payload.len() as u64;
+            let command = match file.as_mut() {
+                Some((_, size)) => {
+                    *size += bytes;
+                    FileCommand::Write(packet)
+                }
+                None => {
+                    file = Some((Instant::now(), bytes));
+                    FileCommand::Open(packet)
+                }
+            };
+            // the writer stopped on an error
+            if files.send(command).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // Writes the packets of a track to its files, until the sender is dropped.
+    fn write_files(
+        (stream, user_id, config): (&str, u32, &RecordConfig),
+        publish_track: &PublishTrackRemote,
+        mut commands: mpsc::Receiver<FileCommand>,
+    ) {
+        let mut file: Option<TrackFile> = None;
+        while let Some(command) = commands.blocking_recv() {
+            let packet = match command {
+                FileCommand::Open(packet) => {
+                    if let Some(file) = file.take() {
+                        file.close();
+                    }
+                    match TrackFile::create((stream, user_id, config), publish_track, &packet) {
+                        Ok(created) => file = Some(created),
+                        Err(err) => {
+                            warn!("[{}] [record] create file err: {}", stream, err);
+                            return;
+                        }
+                    }
+                    packet
+                }
+                FileCommand::Write(packet) => packet,
+            };
+            let Some(file) = file.as_mut() else {
+                continue;
+            };
+            if let Err(err) = file.write(&packet) {
+                warn!("[{}] [record] write err: {}", stream, err);
+                break;
+            }
+        }
+        if let Some(file) = file {
+            file.close();
+        }
+    }
+}
+
+// A file past the size or duration limits, given when it was created and its
+// size, is continued in a new one.
+fn full(config: &RecordConfig, (created, size): (Instant, u64)) -> bool {
+    (config.max_size > 0 && size >= config.max_size)
+        || (!config.max_duration.is_zero() && created.elapsed() >= config.max_duration)
+}
+
+fn extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type.to_lowercase().as_str() {
+        "video/vp8" | "video/vp9" | "video/av1" => Some("ivf"),
+        "video/h264" => Some("h264"),
+        "audio/opus" => Some("ogg"),
+        _ => None,
+    }
+}
+
+// stream names and mids come from clients
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Written next to each file, when it is created and when it is closed.
+#[derive(Serialize)]
+struct Sidecar {
+    room_id: i32,
+    stream: String,
+    user_id: u32,
+    kind: String,
+    mime_type: String,
+    mid: String,
+    rid: String,
+    file: String,
+    /// ms since the epoch
+    start_time: i64,
+    /// ms since the epoch, `None` while the file is written
+    end_time: Option<i64>,
+    clock_rate: u32,
+    /// the RTP timestamp of the first packet, the file starts from it
+    first_rtp_timestamp: u32,
+    packets: u64,
+    bytes: u64,
+}
+
+enum FileCommand {
+    // closes the current file, and starts a new one with the packet
+    Open(ForwardData),
+    Write(ForwardData),
+}
+
+struct TrackFile {
+    writer: Box<dyn Writer + Send>,
+    sidecar: Sidecar,
+    sidecar_path: PathBuf,
+    // audio timestamps restart from 1 in each file, as the Ogg writer expects
+    rebase_timestamps: bool,
+}
+
+impl TrackFile {
+    fn create(
+        (stream, user_id, config): (&str, u32, &RecordConfig),
+        publish_track: &PublishTrackRemote,
+        first: &Packet,
+    ) -> Result<Self, MediaError> {
+        let codec = publish_track.track.codec();
+        let mime_type = codec.capability.mime_type;
+        let extension = extension(&mime_type)
+            .ok_or(MediaError::Other(format!("{} is not recorded", mime_type)))?;
+        let dir = config
+            .dir
+            .join(config.room_id.to_string())
+            .join(sanitize(stream));
+        fs::create_dir_all(&dir)?;
+        let start_time = Utc::now().timestamp_millis();
+        let name = format!(
+            "{}-{}-{}-{}.{}",
+            user_id,
+            publish_track.kind,
+            sanitize(&publish_track.mid),
+            start_time,
+            extension
+        );
+        let path = dir.join(&name);
+        let file = BufWriter::new(File::create(&path)?);
+        let writer: Box<dyn Writer + Send> = match extension {
+            "ivf" => Box::new(
+                IvfWriter::new(file, &mime_type)?
+                    .ok_or(MediaError::Other(format!("{} is not IVF", mime_type)))?,
+            ),
+            "h264" => Box::new(H264Writer::new(file)),
+            _ => Box::new(OggWriter::new(
+                file,
+                codec.capability.clock_rate,
+                codec.capability.channels.max(1) as u8,
+            )?),
+        };
+        info!("[{}] [record] file {}", stream, path.display());
+        let track_file = Self {
+            writer,
+            sidecar: Sidecar {
+                room_id: config.room_id,
+                stream: stream.to_owned(),
+                user_id,
+                kind: publish_track.kind.to_string(),
+                mime_type,
+                mid: publish_track.mid.clone(),
+                rid: publish_track.rid.clone(),
+                file: name,
+                start_time,
+                end_time: None,
+                clock_rate: codec.capability.clock_rate,
+                first_rtp_timestamp: first.header.timestamp,
+                packets: 0,
+                bytes: 0,
+            },
+            sidecar_path: path.with_extension("json"),
+            rebase_timestamps: publish_track.kind == RTPCodecType::Audio,
+        };
+        track_file.write_sidecar();
+        Ok(track_file)
+    }
+
+    fn write(&mut self, packet: &Packet) -> Result<(), MediaError> {
+        if self.rebase_timestamps {
+            let mut packet = packet.clone();
+            packet.header.timestamp = packet
+                .header
+                .timestamp
+                .wrapping_sub(self.sidecar.first_rtp_timestamp)
+                .wrapping_add(1);
+            self.writer.write_rtp(&packet)?;
+        } else {
+            self.writer.write_rtp(packet)?;
+        }
+        self.sidecar.packets += 1;
+        self.sidecar.bytes += packet.payload.len() as u64;
+        metrics::RECORDED_BYTES
+            .with_label_values(&[&self.sidecar.kind])
+            .inc_by(packet.payload.len() as u64);
+        Ok(())
+    }
+
+    fn close(mut self) {
+        if let Err(err) = self.writer.close() {
+            warn!("[{}] [record] close err: {}", self.sidecar.stream, err);
+        }
+        self.sidecar.end_time = Some(Utc::now().timestamp_millis());
+        self.write_sidecar();
+        debug!(
+            "[{}] [record] closed {}, {} packets",
+            self.sidecar.stream, self.sidecar.file, self.sidecar.packets
+        );
+    }
+
+    fn write_sidecar(&self) {
+        let json = serde_json::to_vec_pretty(&self.sidecar).unwrap();
+        if let Err(err) = fs::write(&self.sidecar_path, json) {
+            warn!("[{}] [record] sidecar err: {}", self.sidecar.stream, err);
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use webrtc::rtcp::packet::Packet;
-use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::{FirEntry, FullIntraRequest};
 use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
 use webrtc::rtcp::payload_feedbacks::slice_loss_indication::SliceLossIndication;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::{NackPair, TransportLayerNack};
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum RtcpMessage {
     FullIntraRequest,
     PictureLossIndication,
     SliceLossIndication,
+    // only sent upstream, the NACKs of subscribers are answered by the SFU
+    Nack(NackPair),
 }
 
 impl RtcpMessage {
@@ -23,12 +26,28 @@ impl RtcpMessage {
         None
     }
 
-    pub(crate) fn to_rtcp_packet(self, ssrc: u32) -> Box<dyn Packet + Send + Sync> {
+    pub(crate) fn is_keyframe_request(self) -> bool {
+        matches!(
+            self,
+            RtcpMessage::FullIntraRequest | RtcpMessage::PictureLossIndication
+        )
+    }
+
+    /// `fir_sequence_number` is the sequence number of a FIR to `ssrc`,
+    /// incremented for every new request (RFC 5104 section 4.3.1.1).
+    pub(crate) fn to_rtcp_packet(
+        self,
+        ssrc: u32,
+        fir_sequence_number: u8,
+    ) -> Box<dyn Packet + Send + Sync> {
         match self {
             RtcpMessage::FullIntraRequest => Box::new(FullIntraRequest {
                 sender_ssrc: 0,
-                media_ssrc: ssrc,
-                fir: vec![],
+                media_ssrc: 0,
+                fir: vec![FirEntry {
+                    ssrc,
+                    sequence_number: fir_sequence_number,
+                }],
             }),
             RtcpMessage::PictureLossIndication => Box::new(PictureLossIndication {
                 sender_ssrc: 0,
@@ -39,6 +58,11 @@ impl RtcpMessage {
                 media_ssrc: ssrc,
                 sli_entries: vec![],
             }),
+            RtcpMessage::Nack(nack_pair) => Box::new(TransportLayerNack {
+                sender_ssrc: 0,
+                media_ssrc: ssrc,
+                nacks: vec![nack_pair],
+            }),
         }
     }
 }
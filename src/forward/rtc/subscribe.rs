@@ -1,65 +1,105 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch, RwLock};
 use tracing::{debug, info};
+use webrtc::dtls_transport::dtls_transport_state::RTCDtlsTransportState;
 use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::{
+    nack_pairs_from_sequence_numbers, TransportLayerNack,
+};
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::TrackLocalWriter;
+use webrtc::util::MarshalSize;
 
 use crate::error::AppError;
+use crate::forward::rtc::bwe::{BandwidthEstimator, LayerSelector};
+use crate::forward::rtc::gop::GopCache;
+use crate::forward::rtc::keyframe::is_keyframe;
 use crate::forward::rtc::message::SessionInfo;
+use crate::forward::rtc::munger::RtpMunger;
 use crate::forward::rtc::rtcp::RtcpMessage;
 use crate::forward::rtc::track::ForwardData;
 use crate::result::Result;
-use crate::{constant, new_broadcast_channel};
+use crate::{constant, metrics, new_broadcast_channel};
 
 use super::get_peer_id;
-use super::track::PublishTrackRemote;
+use super::track::{sender_track_id, source_tracks, PublishTrackRemote};
 
 type SelectLayerBody = (RTPCodecType, String);
+type ChangeResourceBody = (RTPCodecType, bool);
+
+// The state of one subscriber sender: its munger, and the bandwidth estimate
+// shared by all senders of the peer.
+type SenderState = (
+    Arc<RTCRtpSender>,
+    Arc<Mutex<RtpMunger>>,
+    Arc<Mutex<BandwidthEstimator>>,
+);
+
+// The publisher's tracks, their changes and the kinds it muted, as seen by
+// a subscriber.
+type PublishState = (
+    Arc<RwLock<Vec<PublishTrackRemote>>>,
+    broadcast::Sender<()>, // use subscribe
+    watch::Receiver<Vec<RTPCodecType>>,
+);
 
 struct SubscribeForwardChannel {
     publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
-    select_layer_recv: broadcast::Receiver<SelectLayerBody>,
+    // the latest selection wins, older ones not applied yet are moot
+    select_layer_recv: watch::Receiver<Option<SelectLayerBody>>,
+    change_resource_recv: broadcast::Receiver<ChangeResourceBody>,
     publish_track_change: broadcast::Receiver<()>,
+    // the kinds the publisher muted for everyone
+    publish_muted: watch::Receiver<Vec<RTPCodecType>>,
 }
 
 pub(crate) struct SubscribeRTCPeerConnection {
     pub(crate) id: String,
+    pub(crate) user_id: u32,
     pub(crate) peer: Arc<RTCPeerConnection>,
     pub(crate) create_time: i64,
-    select_layer_sender: broadcast::Sender<SelectLayerBody>,
+    select_layer_sender: watch::Sender<Option<SelectLayerBody>>,
+    change_resource_sender: broadcast::Sender<ChangeResourceBody>,
 }
 
 impl SubscribeRTCPeerConnection {
     pub(crate) async fn new(
         stream: String,
+        user_id: u32,
         peer: Arc<RTCPeerConnection>,
         publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
-        (publish_tracks, publish_track_change): (
-            Arc<RwLock<Vec<PublishTrackRemote>>>,
-            broadcast::Sender<()>, // use subscribe
-        ),
-        (video_sender, audio_sender): (Option<Arc<RTCRtpSender>>, Option<Arc<RTCRtpSender>>),
+        (publish_tracks, publish_track_change, publish_muted): PublishState,
+        (video_senders, audio_senders): (Vec<Arc<RTCRtpSender>>, Vec<Arc<RTCRtpSender>>),
     ) -> Self {
-        let select_layer_sender = new_broadcast_channel!(1);
+        let (select_layer_sender, _) = watch::channel(None);
+        let change_resource_sender = new_broadcast_channel!(1);
         let id = get_peer_id(&peer);
         let track_binding_publish_rid = Arc::new(RwLock::new(HashMap::new()));
-        for (sender, kind) in [
-            (video_sender, RTPCodecType::Video),
-            (audio_sender, RTPCodecType::Audio),
-        ] {
-            if sender.is_none() {
-                continue;
-            }
-            let sender = sender.unwrap();
+        let bandwidth = Arc::new(Mutex::new(BandwidthEstimator::new(video_senders.len())));
+        // the n-th sender of a kind forwards the n-th published track of that kind
+        let senders = video_senders
+            .into_iter()
+            .enumerate()
+            .map(|(index, sender)| (sender, RTPCodecType::Video, index))
+            .chain(
+                audio_senders
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, sender)| (sender, RTPCodecType::Audio, index)),
+            );
+        for (sender, kind, index) in senders {
+            // kept across track changes, the subscriber sees a single stream
+            let munger = Arc::new(Mutex::new(RtpMunger::default()));
             tokio::spawn(Self::sender_forward_rtcp(
-                kind,
-                sender.clone(),
+                (kind, index),
+                (sender.clone(), munger.clone(), bandwidth.clone()),
                 publish_tracks.clone(),
                 track_binding_publish_rid.clone(),
                 publish_rtcp_sender.clone(),
@@ -67,23 +107,27 @@ impl SubscribeRTCPeerConnection {
             tokio::spawn(Self::sender_forward_rtp(
                 stream.clone(),
                 id.clone(),
-                sender,
-                kind,
+                (sender, munger, bandwidth.clone()),
+                (kind, index),
                 track_binding_publish_rid.clone(),
                 publish_tracks.clone(),
                 SubscribeForwardChannel {
                     publish_rtcp_sender: publish_rtcp_sender.clone(),
                     select_layer_recv: select_layer_sender.subscribe(),
+                    change_resource_recv: change_resource_sender.subscribe(),
                     publish_track_change: publish_track_change.subscribe(),
+                    publish_muted: publish_muted.clone(),
                 },
             ));
         }
         let _ = publish_track_change.send(());
         Self {
             id,
+            user_id,
             peer,
             create_time: Utc::now().timestamp_millis(),
             select_layer_sender,
+            change_resource_sender,
         }
     }
 
@@ -96,29 +140,62 @@ impl SubscribeRTCPeerConnection {
     }
 
     pub(crate) fn select_kind_rid(&self, kind: RTPCodecType, rid: String) -> Result<()> {
-        if let Err(err) = self.select_layer_sender.send((kind, rid)) {
+        if let Err(err) = self.select_layer_sender.send(Some((kind, rid))) {
             Err(AppError::throw(format!("select layer send err: {}", err)))
         } else {
             Ok(())
         }
     }
 
+    /// Pauses or resumes forwarding the tracks of `kind` to this subscriber.
+    pub(crate) fn change_resource(&self, kind: RTPCodecType, enabled: bool) -> Result<()> {
+        if let Err(err) = self.change_resource_sender.send((kind, enabled)) {
+            Err(AppError::throw(format!(
+                "change resource send err: {}",
+                err
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
     async fn sender_forward_rtp(
         stream: String,
         id: String,
-        sender: Arc<RTCRtpSender>,
-        kind: RTPCodecType,
+        (sender, munger, bandwidth): SenderState,
+        (kind, index): (RTPCodecType, usize),
         track_binding_publish_rid: Arc<RwLock<HashMap<String, String>>>,
         publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
         mut forward_channel: SubscribeForwardChannel,
     ) {
-        info!("[{}] [{}] {} up", stream, id, kind);
+        info!("[{}] [{}] {} {} up", stream, id, kind, index);
+        let binding_key = sender_track_id(kind, index);
         let mut pre_rid: Option<String> = None;
+        // the layer follows the bandwidth estimate until one is pinned
+        let mut auto = true;
+        let mut layer_selector = LayerSelector::default();
+        let mut layer_tick = tokio::time::interval(Duration::from_secs(1));
+        // the layer being switched to, forwarded from its next keyframe on
+        let mut pending: Option<(String, broadcast::Receiver<ForwardData>)> = None;
+        let mut pending_pli = Instant::now();
+        // the publisher's packets since its last keyframe, sent once the transport is up
+        let mut replay: Option<GopCache> = None;
+        // paused by the subscriber, muted by the publisher
+        let mut paused = (
+            false,
+            forward_channel
+                .publish_muted
+                .borrow_and_update()
+                .contains(&kind),
+        );
+        if paused.1 && kind == RTPCodecType::Video {
+            bandwidth.lock().unwrap().on_video_paused(true);
+        }
         // empty broadcast channel
         let (virtual_sender, _) = broadcast::channel::<ForwardData>(100);
         let mut recv = virtual_sender.subscribe();
         let mut track = None;
-        let mut sequence_number: u16 = 0;
+        let kind_label = kind.to_string();
         loop {
             tokio::select! {
                 publish_change = forward_channel.publish_track_change.recv() =>{
@@ -128,15 +205,17 @@ impl SubscribeRTCPeerConnection {
                     }
                     let mut track_binding_publish_rid = track_binding_publish_rid.write().await;
                     let publish_tracks = publish_tracks.read().await;
-                    let current_rid = track_binding_publish_rid.get(&kind.clone().to_string());
+                    let current_rid = track_binding_publish_rid.get(&binding_key);
                     if publish_tracks.len() == 0 {
                         debug!("{} {} publish track len 0 , probably offline",stream,id);
                         recv = virtual_sender.subscribe();
                         let _ = sender.replace_track(None).await;
                         track = None;
                         pre_rid = None;
+                        pending = None;
+                        replay = None;
                         if current_rid.is_some() && current_rid.cloned().unwrap() != constant::RID_DISABLE {
-                            track_binding_publish_rid.remove(&kind.clone().to_string());
+                            track_binding_publish_rid.remove(&binding_key);
                         };
                         continue;
                     }
@@ -146,26 +225,27 @@ impl SubscribeRTCPeerConnection {
                     if current_rid.is_some() && current_rid.cloned().unwrap() == constant::RID_DISABLE {
                         continue;
                     }
-                    for publish_track in publish_tracks.iter() {
-                        if publish_track.kind != kind {
-                            continue;
-                        }
+                    if let Some(publish_track) = source_tracks(&publish_tracks, kind, index).first() {
                         let new_track= Arc::new(
-                            TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability, "webrtc".to_string(),format!("{}-{}","webrtc",kind))
+                            TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability, binding_key.clone(),format!("{}-{}","webrtc",kind))
                         );
                         match sender.replace_track(Some(new_track.clone())).await {
                             Ok(_) => {
                                 debug!("[{}] [{}] {} track replace ok", stream, id, kind);
-                                recv = publish_track.subscribe();
+                                let (gop, receiver) = publish_track.subscribe_with_gop();
+                                recv = receiver;
+                                if gop.is_empty() {
+                                    let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
+                                } else {
+                                    replay = Some(GopCache::with_packets(new_track.codec().mime_type, gop));
+                                }
                                 track = Some(new_track);
-                                let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
-                                track_binding_publish_rid.insert(kind.clone().to_string(), publish_track.rid.clone());
+                                track_binding_publish_rid.insert(binding_key.clone(), publish_track.rid.clone());
                             }
                             Err(e) => {
                                 debug!("[{}] [{}] {} track replace err: {}", stream, id,kind, e);
                             }
                         };
-                        break;
                     }
                 }
                 rtp_result = recv.recv() => {
@@ -175,32 +255,168 @@ impl SubscribeRTCPeerConnection {
                                 None => {
                                     continue;
                                 }
+                                Some(_) if paused.0 || paused.1 => {
+                                    continue;
+                                }
                                 Some(ref track) => {
-                                    let mut packet = packet.as_ref().clone();
-                                    packet.header.sequence_number = sequence_number;
-                                    if let Err(err) = track.write_rtp(&packet).await {
+                                    if let Some(gop) = replay.as_mut() {
+                                        // packets written before the transport is up are lost,
+                                        // the replay grows with the live packets until then
+                                        gop.push(packet);
+                                        if sender.transport().state() != RTCDtlsTransportState::Connected {
+                                            continue;
+                                        }
+                                        let gop = replay.take().unwrap().packets();
+                                        if let Err(err) = Self::replay_gop(track, &gop, &munger, &kind_label, &bandwidth).await {
+                                            debug!("[{}] [{}] {} track write err: {}", stream, id,kind, err);
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                    if let Err(err) = Self::forward_packet(track, &packet, &munger, &kind_label, &bandwidth).await {
                                         debug!("[{}] [{}] {} track write err: {}", stream, id,kind, err);
                                         break;
                                     }
-                                    sequence_number = sequence_number.wrapping_add(1);
                                 }
                             }
                         }
                         Err(err) => {
+                            if let RecvError::Lagged(n) = err {
+                                metrics::BROADCAST_LAGGED
+                                    .with_label_values(&[metrics::CHANNEL_RTP])
+                                    .inc_by(n);
+                            }
                             debug!("[{}] [{}] {} rtp receiver err: {}", stream, id, kind,err);
                         }
                     }
                 }
-                select_layer_result = forward_channel.select_layer_recv.recv() => {
+                pending_result = async { pending.as_mut().unwrap().1.recv().await }, if pending.is_some() => {
+                    let packet = match pending_result {
+                        Ok(packet) => packet,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(_) => {
+                            pending = None;
+                            continue;
+                        }
+                    };
+                    let Some(ref track) = track else {
+                        pending = None;
+                        continue;
+                    };
+                    if paused.0 || paused.1 {
+                        pending = None;
+                        continue;
+                    }
+                    if !is_keyframe(&track.codec().mime_type, &packet.payload) {
+                        continue;
+                    }
+                    let (rid, pending_recv) = pending.take().unwrap();
+                    recv = pending_recv;
+                    replay = None;
+                    track_binding_publish_rid.write().await.insert(binding_key.clone(), rid.clone());
+                    info!("[{}] [{}] {} switch layer to {}", stream, id, kind, rid);
+                    if let Err(err) = Self::forward_packet(track, &packet, &munger, &kind_label, &bandwidth).await {
+                        debug!("[{}] [{}] {} track write err: {}", stream, id,kind, err);
+                        break;
+                    }
+                }
+                _ = layer_tick.tick(), if auto && kind == RTPCodecType::Video && track.is_some() && !(paused.0 || paused.1) => {
+                    let Some(budget) = bandwidth.lock().unwrap().video_budget() else {
+                        continue;
+                    };
+                    let publish_tracks = publish_tracks.read().await;
+                    let layers = source_tracks(&publish_tracks, kind, index);
+                    let Some(current_rid) = track_binding_publish_rid.read().await.get(&binding_key).cloned() else {
+                        continue;
+                    };
+                    if layers.len() < 2 {
+                        continue;
+                    }
+                    let bitrates: Vec<(String, u64)> = layers.iter().map(|t| (t.rid.clone(), t.bitrate())).collect();
+                    let target = layer_selector.next_layer(&bitrates, &current_rid, budget);
+                    let pending_rid = pending.as_ref().map(|(rid, _)| rid.clone());
+                    if let Some(target) = target.filter(|target| Some(target) != pending_rid.as_ref()) {
+                        if let Some(publish_track) = layers.iter().find(|t| t.rid == target) {
+                            debug!("[{}] [{}] {} budget {} bps, switching layer {} to {}", stream, id, kind, budget, current_rid, target);
+                            pending = Some((target, publish_track.subscribe()));
+                            pending_pli = Instant::now();
+                            let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
+                        }
+                    } else if let Some(pending_rid) = pending_rid {
+                        // the keyframe may have been lost, ask again
+                        if pending_pli.elapsed() >= Duration::from_secs(1) {
+                            if let Some(publish_track) = layers.iter().find(|t| t.rid == pending_rid) {
+                                pending_pli = Instant::now();
+                                let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
+                            }
+                        }
+                    }
+                }
+                change_resource_result = forward_channel.change_resource_recv.recv() => {
+                    let (resource_kind, enabled) = match change_resource_result {
+                        Ok(body) => body,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    };
+                    if resource_kind != kind {
+                        continue;
+                    }
+                    let next = (!enabled, paused.1);
+                    info!("[{}] [{}] {} {} by subscriber", stream, id, kind, if enabled { "resume" } else { "pause" });
+                    Self::change_paused(
+                        (kind, index),
+                        (&munger, &bandwidth),
+                        (paused, next),
+                        (&track_binding_publish_rid, &publish_tracks),
+                        &forward_channel.publish_rtcp_sender,
+                    ).await;
+                    paused = next;
+                    pending = None;
+                    replay = None;
+                }
+                muted_result = forward_channel.publish_muted.changed() => {
+                    if muted_result.is_err() {
+                        break;
+                    }
+                    let next = (paused.0, forward_channel.publish_muted.borrow_and_update().contains(&kind));
+                    if next == paused {
+                        continue;
+                    }
+                    info!("[{}] [{}] {} {} by publisher", stream, id, kind, if next.1 { "mute" } else { "unmute" });
+                    Self::change_paused(
+                        (kind, index),
+                        (&munger, &bandwidth),
+                        (paused, next),
+                        (&track_binding_publish_rid, &publish_tracks),
+                        &forward_channel.publish_rtcp_sender,
+                    ).await;
+                    paused = next;
+                    pending = None;
+                    replay = None;
+                }
+                select_layer_result = forward_channel.select_layer_recv.changed() => {
+                    let select_layer_result = select_layer_result
+                        .map(|_| forward_channel.select_layer_recv.borrow_and_update().clone());
                     match select_layer_result {
-                        Ok(select_layer_body) => {
+                        Ok(None) => continue,
+                        Ok(Some(select_layer_body)) => {
                             if select_layer_body.0 != kind {
                                 continue;
                             };
                              let select_rid = select_layer_body.1;
+                             pending = None;
+                             layer_selector.reset();
+                             if select_rid == constant::RID_AUTO {
+                                auto = true;
+                                info!("[{}] [{}] {} select layer auto", stream, id, kind);
+                                continue;
+                             }
+                             if select_rid != constant::RID_ENABLE && select_rid != constant::RID_DISABLE {
+                                auto = false;
+                             }
                              let mut track_binding_publish_rid = track_binding_publish_rid.write().await;
                              let publish_tracks =  publish_tracks.read().await;
-                             let current_rid = track_binding_publish_rid.get(&kind.to_string()).cloned();
+                             let current_rid = track_binding_publish_rid.get(&binding_key).cloned();
                              if current_rid == Some(select_rid.clone()){
                                 continue;
                              }
@@ -210,10 +426,10 @@ impl SubscribeRTCPeerConnection {
                                 }
                                 Some(current_rid) => {
                                     if current_rid == constant::RID_DISABLE && select_rid == constant::RID_ENABLE{
-                                        track_binding_publish_rid.remove(&kind.clone().to_string());
+                                        track_binding_publish_rid.remove(&binding_key);
                                         match &pre_rid{
                                             None => {
-                                                let next_rid = publish_tracks.iter().filter(|t|t.kind==kind).map(|t|t.rid.clone()).next();
+                                                let next_rid = source_tracks(&publish_tracks, kind, index).first().map(|t|t.rid.clone());
                                                 if next_rid.is_none(){
                                                     continue;
                                                 }
@@ -234,22 +450,33 @@ impl SubscribeRTCPeerConnection {
                                     let _ = sender.replace_track(None).await;
                                     track = None;
                                     pre_rid = Some(current_rid.unwrap());
+                                    replay = None;
                                 }
-                                track_binding_publish_rid.insert(kind.clone().to_string(), new_rid);
+                                track_binding_publish_rid.insert(binding_key.clone(), new_rid);
                                 continue;
                             };
-                            for  publish_track in publish_tracks.iter() {
+                            for  publish_track in source_tracks(&publish_tracks, kind, index) {
                                 if publish_track.kind == RTPCodecType::Video && (publish_track.rid == new_rid || new_rid == constant::RID_ENABLE) {
                                       let new_track= Arc::new(
-                                        TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability,"webrtc".to_string(),format!("{}-{}","webrtc",kind))
+                                        TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability,binding_key.clone(),format!("{}-{}","webrtc",kind))
                                     );
                                     match sender.replace_track(Some(new_track.clone())).await {
                                      Ok(_) => {
                                         debug!("[{}] [{}] {} track replace ok", stream, id,kind);
-                                        recv = publish_track.subscribe();
+                                        // replaying an older GOP to a playing track would rewind it
+                                        let (gop, receiver) = match track {
+                                            None => publish_track.subscribe_with_gop(),
+                                            Some(_) => (vec![], publish_track.subscribe()),
+                                        };
+                                        recv = receiver;
+                                        if gop.is_empty() {
+                                            let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc())).unwrap();
+                                            replay = None;
+                                        } else {
+                                            replay = Some(GopCache::with_packets(new_track.codec().mime_type, gop));
+                                        }
                                         track = Some(new_track);
-                                        let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc())).unwrap();
-                                        track_binding_publish_rid.insert(kind.clone().to_string(), new_rid.clone());
+                                        track_binding_publish_rid.insert(binding_key.clone(), new_rid.clone());
                                         info!("[{}] [{}] {} select layer to {}", stream, id, kind,new_rid);
                                     }
                                      Err(e) => {
@@ -267,12 +494,156 @@ impl SubscribeRTCPeerConnection {
                 }
             }
         }
-        info!("[{}] [{}] {} down", stream, id, kind);
+        info!("[{}] [{}] {} {} down", stream, id, kind, index);
+    }
+
+    // Applies a change of the paused state, paused by either the subscriber or
+    // the publisher. A paused sender takes no share of the bandwidth, and a
+    // resumed video sender asks the publisher for a keyframe to restart from.
+    async fn change_paused(
+        (kind, index): (RTPCodecType, usize),
+        (munger, bandwidth): (&Mutex<RtpMunger>, &Mutex<BandwidthEstimator>),
+        (paused, next): ((bool, bool), (bool, bool)),
+        (track_binding_publish_rid, publish_tracks): (
+            &RwLock<HashMap<String, String>>,
+            &RwLock<Vec<PublishTrackRemote>>,
+        ),
+        publish_rtcp_sender: &broadcast::Sender<(RtcpMessage, u32)>,
+    ) {
+        let (was, is) = (paused.0 || paused.1, next.0 || next.1);
+        if was == is {
+            return;
+        }
+        if kind == RTPCodecType::Video {
+            bandwidth.lock().unwrap().on_video_paused(is);
+        }
+        if is {
+            munger.lock().unwrap().pause();
+            return;
+        }
+        if kind != RTPCodecType::Video {
+            return;
+        }
+        let Some(rid) = track_binding_publish_rid
+            .read()
+            .await
+            .get(&sender_track_id(kind, index))
+            .cloned()
+        else {
+            return;
+        };
+        let publish_tracks = publish_tracks.read().await;
+        if let Some(publish_track) = source_tracks(&publish_tracks, kind, index)
+            .iter()
+            .find(|t| t.rid == rid)
+        {
+            let _ = publish_rtcp_sender.send((
+                RtcpMessage::PictureLossIndication,
+                publish_track.track.ssrc(),
+            ));
+        }
+    }
+
+    // Writes `packet` rewritten by the sender's munger, which may also drop it.
+    async fn forward_packet(
+        track: &TrackLocalStaticRTP,
+        packet: &ForwardData,
+        munger: &Mutex<RtpMunger>,
+        kind_label: &str,
+        bandwidth: &Mutex<BandwidthEstimator>,
+    ) -> webrtc::error::Result<()> {
+        let packet = munger.lock().unwrap().process(packet, &track.codec());
+        let Some(packet) = packet else {
+            return Ok(());
+        };
+        track.write_rtp(&packet).await?;
+        let size = packet.marshal_size();
+        bandwidth.lock().unwrap().on_sent(size);
+        metrics::RTP_PACKETS.with_label_values(&[kind_label]).inc();
+        metrics::RTP_BYTES
+            .with_label_values(&[kind_label])
+            .inc_by(size as u64);
+        Ok(())
+    }
+
+    // Sends the packets since the publisher's last keyframe, so that a newly
+    // bound track starts at once. The live packets follow through the munger.
+    async fn replay_gop(
+        track: &TrackLocalStaticRTP,
+        gop: &[ForwardData],
+        munger: &Mutex<RtpMunger>,
+        kind_label: &str,
+        bandwidth: &Mutex<BandwidthEstimator>,
+    ) -> webrtc::error::Result<()> {
+        for packet in gop {
+            Self::forward_packet(track, packet, munger, kind_label, bandwidth).await?;
+        }
+        Ok(())
+    }
+
+    // Answers NACKs from the cache of the publisher's tracks, through the
+    // munger's mapping. The packets no longer cached are NACKed upstream.
+    // RTX is not negotiated with subscribers, webrtc-rs senders have no RTX
+    // stream, so the packets are resent in the media stream.
+    async fn answer_nacks(
+        (kind, index): (RTPCodecType, usize),
+        (sender, munger): (&RTCRtpSender, &Mutex<RtpMunger>),
+        nack: &TransportLayerNack,
+        publish_tracks: &RwLock<Vec<PublishTrackRemote>>,
+        publish_rtcp_sender: &broadcast::Sender<(RtcpMessage, u32)>,
+    ) {
+        let lost: Vec<(u32, u16)> = {
+            let munger = munger.lock().unwrap();
+            nack.nacks
+                .iter()
+                .flat_map(|nack_pair| nack_pair.packet_list())
+                .filter_map(|sequence_number| munger.lookup(sequence_number))
+                .collect()
+        };
+        let Some(track) = sender.track().await else {
+            return;
+        };
+        let Some(track) = track.as_any().downcast_ref::<TrackLocalStaticRTP>() else {
+            return;
+        };
+        let codec = track.codec();
+        let publish_tracks = publish_tracks.read().await;
+        let sources = source_tracks(&publish_tracks, kind, index);
+        let mut upstream: HashMap<u32, Vec<u16>> = HashMap::new();
+        for (ssrc, sequence_number) in lost {
+            let Some(publish_track) = sources.iter().find(|t| t.track.ssrc() == ssrc) else {
+                continue;
+            };
+            let packet = publish_track
+                .cached(sequence_number)
+                .and_then(|packet| munger.lock().unwrap().retransmit(&packet, &codec));
+            match packet {
+                Some(packet) => {
+                    if track.write_rtp(&packet).await.is_err() {
+                        return;
+                    }
+                    metrics::RTP_NACKED
+                        .with_label_values(&[metrics::NACK_RETRANSMITTED])
+                        .inc();
+                }
+                None => {
+                    upstream.entry(ssrc).or_default().push(sequence_number);
+                    metrics::RTP_NACKED
+                        .with_label_values(&[metrics::NACK_UPSTREAM])
+                        .inc();
+                }
+            }
+        }
+        for (ssrc, sequence_numbers) in upstream {
+            for nack_pair in nack_pairs_from_sequence_numbers(&sequence_numbers) {
+                let _ = publish_rtcp_sender.send((RtcpMessage::Nack(nack_pair), ssrc));
+            }
+        }
     }
 
     async fn sender_forward_rtcp(
-        kind: RTPCodecType,
-        sender: Arc<RTCRtpSender>,
+        (kind, index): (RTPCodecType, usize),
+        (sender, munger, bandwidth): SenderState,
         publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
         track_binding_publish_rid: Arc<RwLock<HashMap<String, String>>>,
         publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
@@ -280,19 +651,32 @@ impl SubscribeRTCPeerConnection {
         loop {
             match sender.read_rtcp().await {
                 Ok((packets, _)) => {
-                    let track_binding_publish_rid = track_binding_publish_rid.read().await;
-                    let publish_rid = match track_binding_publish_rid.get(&kind.clone().to_string())
-                    {
-                        None => {
-                            continue;
+                    bandwidth.lock().unwrap().on_rtcp(&packets);
+                    for packet in &packets {
+                        if let Some(nack) = packet.as_any().downcast_ref::<TransportLayerNack>() {
+                            Self::answer_nacks(
+                                (kind, index),
+                                (&sender, &munger),
+                                nack,
+                                &publish_tracks,
+                                &publish_rtcp_sender,
+                            )
+                            .await;
                         }
-                        Some(rid) => rid,
-                    };
+                    }
+                    let track_binding_publish_rid = track_binding_publish_rid.read().await;
+                    let publish_rid =
+                        match track_binding_publish_rid.get(&sender_track_id(kind, index)) {
+                            None => {
+                                continue;
+                            }
+                            Some(rid) => rid,
+                        };
                     for packet in packets {
                         if let Some(msg) = RtcpMessage::from_rtcp_packet(packet) {
                             let publish_tracks = publish_tracks.read().await;
-                            for publish_track in publish_tracks.iter() {
-                                if publish_track.kind == kind && &publish_track.rid == publish_rid {
+                            for publish_track in source_tracks(&publish_tracks, kind, index) {
+                                if &publish_track.rid == publish_rid {
                                     if let Err(_err) =
                                         publish_rtcp_sender.send((msg, publish_track.track.ssrc()))
                                     {
@@ -1,37 +1,93 @@
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use tokio::sync::broadcast;
 use tracing::{debug, info};
 use webrtc::rtp::packet::Packet;
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::sdp::extmap::AUDIO_LEVEL_URI;
 use webrtc::track::track_remote::TrackRemote;
+use webrtc::util::MarshalSize;
+
+use super::audio_level::AudioLevel;
+use super::gop::GopCache;
+use super::nack::PacketCache;
 
 pub(crate) type ForwardData = Arc<Packet>;
 
+// what track_forward measures and caches for the subscribers
+type TrackState = (
+    Arc<AtomicU64>,
+    Arc<Mutex<PacketCache>>,
+    Option<Arc<Mutex<GopCache>>>,
+    Option<Arc<Mutex<AudioLevel>>>,
+);
+
 #[derive(Clone)]
 pub(crate) struct PublishTrackRemote {
+    /// mid of the publisher's transceiver, the simulcast layers of a track share it
+    pub(crate) mid: String,
     pub(crate) rid: String,
     pub(crate) kind: RTPCodecType,
     pub(crate) track: Arc<TrackRemote>,
+    /// received bitrate in bps, measured every second
+    bitrate: Arc<AtomicU64>,
+    cache: Arc<Mutex<PacketCache>>,
+    /// packets since the last keyframe, for video tracks when enabled
+    gop: Option<Arc<Mutex<GopCache>>>,
+    /// for audio tracks which negotiated the ssrc-audio-level extension
+    audio_level: Option<Arc<Mutex<AudioLevel>>>,
     rtp_broadcast: Arc<broadcast::Sender<ForwardData>>,
 }
 
 impl PublishTrackRemote {
-    pub async fn new(stream: String, id: String, track: Arc<TrackRemote>) -> Self {
+    pub async fn new(
+        stream: String,
+        id: String,
+        mid: String,
+        track: Arc<TrackRemote>,
+        gop_cache: bool,
+    ) -> Self {
         let (rtp_sender, mut rtp_recv) = broadcast::channel(100);
         tokio::spawn(async move { while rtp_recv.recv().await.is_ok() {} });
         let rid = track.rid().to_owned();
         let kind = track.kind();
+        let bitrate = Arc::new(AtomicU64::new(0));
+        let cache = Arc::new(Mutex::new(PacketCache::new()));
+        let gop = (gop_cache && kind == RTPCodecType::Video).then(|| {
+            Arc::new(Mutex::new(GopCache::new(
+                track.codec().capability.mime_type,
+            )))
+        });
+        let audio_level = track
+            .params()
+            .header_extensions
+            .iter()
+            .find(|extension| extension.uri == AUDIO_LEVEL_URI)
+            .filter(|_| kind == RTPCodecType::Audio)
+            .map(|extension| Arc::new(Mutex::new(AudioLevel::new(extension.id as u8))));
         tokio::spawn(Self::track_forward(
             stream,
             id,
             track.clone(),
+            (
+                bitrate.clone(),
+                cache.clone(),
+                gop.clone(),
+                audio_level.clone(),
+            ),
             rtp_sender.clone(),
         ));
         Self {
+            mid,
             rid,
             kind,
             track,
+            bitrate,
+            cache,
+            gop,
+            audio_level,
             rtp_broadcast: Arc::new(rtp_sender),
         }
     }
@@ -40,6 +96,7 @@ impl PublishTrackRemote {
         stream: String,
         id: String,
         track: Arc<TrackRemote>,
+        (bitrate, cache, gop, audio_level): TrackState,
         rtp_sender: broadcast::Sender<ForwardData>,
     ) {
         info!(
@@ -51,10 +108,33 @@ impl PublishTrackRemote {
             track.ssrc()
         );
         let mut b = vec![0u8; 1500];
+        let mut bytes = 0u64;
+        let mut since = Instant::now();
         loop {
             match track.read(&mut b).await {
                 Ok((rtp_packet, _)) => {
-                    if let Err(err) = rtp_sender.send(Arc::new(rtp_packet)) {
+                    bytes += rtp_packet.marshal_size() as u64;
+                    let elapsed = since.elapsed().as_millis() as u64;
+                    if elapsed >= 1000 {
+                        bitrate.store(bytes * 8 * 1000 / elapsed, Ordering::Relaxed);
+                        bytes = 0;
+                        since = Instant::now();
+                    }
+                    if let Some(audio_level) = &audio_level {
+                        audio_level.lock().unwrap().observe(&rtp_packet);
+                    }
+                    let rtp_packet = Arc::new(rtp_packet);
+                    cache.lock().unwrap().push(rtp_packet.clone());
+                    let sent = match &gop {
+                        // sent under the lock, see subscribe_with_gop
+                        Some(gop) => {
+                            let mut gop = gop.lock().unwrap();
+                            gop.push(rtp_packet.clone());
+                            rtp_sender.send(rtp_packet)
+                        }
+                        None => rtp_sender.send(rtp_packet),
+                    };
+                    if let Err(err) = sent {
                         debug!(
                             "[{}] [{}] track : {:?} {} rtp broadcast error : {}",
                             stream,
@@ -89,7 +169,90 @@ impl PublishTrackRemote {
         );
     }
 
+    pub(crate) fn bitrate(&self) -> u64 {
+        self.bitrate.load(Ordering::Relaxed)
+    }
+
+    /// The smoothed audio level, 0 (silence) to 127 (0 dBov), `None` without
+    /// the ssrc-audio-level extension.
+    pub(crate) fn audio_level(&self) -> Option<u8> {
+        self.audio_level
+            .as_ref()
+            .map(|audio_level| audio_level.lock().unwrap().level())
+    }
+
+    /// The packet with the publisher's `sequence_number`, if still cached.
+    pub(crate) fn cached(&self, sequence_number: u16) -> Option<ForwardData> {
+        self.cache.lock().unwrap().get(sequence_number)
+    }
+
     pub(crate) fn subscribe(&self) -> broadcast::Receiver<ForwardData> {
         self.rtp_broadcast.subscribe()
     }
+
+    /// Subscribes along with the cached packets since the last keyframe, the
+    /// receiver gets the packets following them, without gap nor overlap.
+    pub(crate) fn subscribe_with_gop(
+        &self,
+    ) -> (Vec<ForwardData>, broadcast::Receiver<ForwardData>) {
+        match &self.gop {
+            Some(gop) => {
+                let gop = gop.lock().unwrap();
+                (gop.packets(), self.rtp_broadcast.subscribe())
+            }
+            None => (vec![], self.subscribe()),
+        }
+    }
+}
+
+// video first, then audio
+fn kind_order(kind: RTPCodecType) -> u8 {
+    match kind {
+        RTPCodecType::Video => 0,
+        RTPCodecType::Audio => 1,
+        _ => 2,
+    }
+}
+
+// mids are numbers for every browser, fall back to the string order otherwise
+fn mid_order(mid: &str) -> (u32, &str) {
+    (mid.parse().unwrap_or(u32::MAX), mid)
+}
+
+/// Orders the tracks by kind, then by the order of the publisher's
+/// transceivers, then by rid.
+pub(crate) fn sort_tracks(tracks: &mut [PublishTrackRemote]) {
+    tracks.sort_by(|a, b| {
+        (kind_order(a.kind), mid_order(&a.mid), &a.rid).cmp(&(
+            kind_order(b.kind),
+            mid_order(&b.mid),
+            &b.rid,
+        ))
+    });
+}
+
+/// The layers of the `index`-th published track of `kind`.
+pub(crate) fn source_tracks(
+    tracks: &[PublishTrackRemote],
+    kind: RTPCodecType,
+    index: usize,
+) -> Vec<&PublishTrackRemote> {
+    let mut mids: Vec<&str> = tracks
+        .iter()
+        .filter(|t| t.kind == kind)
+        .map(|t| t.mid.as_str())
+        .collect();
+    mids.dedup();
+    match mids.get(index) {
+        Some(mid) => tracks
+            .iter()
+            .filter(|t| t.kind == kind && t.mid == *mid)
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// The id of the track sent by the `index`-th subscriber transceiver of `kind`.
+pub(crate) fn sender_track_id(kind: RTPCodecType, index: usize) -> String {
+    format!("webrtc-{}-{}", kind, index)
 }
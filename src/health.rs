@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+use crate::result::Result;
+use crate::ROOMS;
+
+/// How long readiness waits for the rooms lock before reporting it as stuck.
+const LOCK_DEADLINE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ServerStatus {
+    Starting = 0,
+    Ready = 1,
+    Draining = 2,
+}
+
+static STATUS: AtomicU8 = AtomicU8::new(ServerStatus::Starting as u8);
+
+pub fn set_status(status: ServerStatus) {
+    STATUS.store(status as u8, Ordering::SeqCst);
+}
+
+/// Fails once the server is draining, for routes which create rooms or sessions.
+pub fn accepting() -> Result<()> {
+    if status() == ServerStatus::Draining {
+        return Err(AppError::server_draining("server is shutting down"));
+    }
+    Ok(())
+}
+
+pub fn status() -> ServerStatus {
+    match STATUS.load(Ordering::SeqCst) {
+        0 => ServerStatus::Starting,
+        1 => ServerStatus::Ready,
+        _ => ServerStatus::Draining,
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct CheckJson {
+    pub name: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ReadinessJson {
+    pub ready: bool,
+    pub checks: Vec<CheckJson>,
+}
+
+impl CheckJson {
+    fn ok(name: &str) -> Self {
+        CheckJson {
+            name: name.to_string(),
+            ok: true,
+            message: None,
+        }
+    }
+
+    fn failed<T: ToString>(name: &str, message: T) -> Self {
+        CheckJson {
+            name: name.to_string(),
+            ok: false,
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+pub async fn readiness() -> ReadinessJson {
+    let mut checks = vec![];
+
+    checks.push(match status() {
+        ServerStatus::Ready => CheckJson::ok("status"),
+        ServerStatus::Starting => CheckJson::failed("status", "server is starting"),
+        ServerStatus::Draining => CheckJson::failed("status", "server is draining"),
+    });
+
+    match tokio::time::timeout(LOCK_DEADLINE, ROOMS.lock()).await {
+        Ok(rooms) => {
+            checks.push(CheckJson::ok("rooms_lock"));
+            let snapshot: Vec<_> = rooms.values().cloned().collect();
+            drop(rooms);
+
+            // A forwarder is held for as long as a subscriber waits for its
+            // publisher, so a busy one is only reported, it does not fail readiness.
+            let mut dead = vec![];
+            let mut busy = vec![];
+            for room in snapshot.iter() {
+                let forwarder = room.forwarder();
+                let alive = match forwarder.try_read() {
+                    Ok(forwarder) => forwarder.check_task_alive(),
+                    Err(_) => {
+                        busy.push(room.id().to_string());
+                        continue;
+                    }
+                };
+                if !alive {
+                    dead.push(room.id().to_string());
+                }
+            }
+            checks.push(CheckJson {
+                name: "forwarder_lock".to_string(),
+                ok: true,
+                message: (!busy.is_empty())
+                    .then(|| format!("forwarder lock busy in rooms: {}", busy.join(", "))),
+            });
+            checks.push(if dead.is_empty() {
+                CheckJson::ok("forwarder_check_task")
+            } else {
+                CheckJson::failed(
+                    "forwarder_check_task",
+                    format!("check task has stopped in rooms: {}", dead.join(", ")),
+                )
+            });
+        }
+        Err(_) => checks.push(CheckJson::failed(
+            "rooms_lock",
+            format!("ROOMS lock not acquired within {:?}", LOCK_DEADLINE),
+        )),
+    }
+
+    ReadinessJson {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
@@ -1,12 +1,23 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct ErrorJson {
+    pub code: String,
+    pub message: String,
+    /// The rejected field or parser error of a `BAD_REQUEST`, the unsupported
+    /// codecs of a `CODEC_NOT_SUPPORTED`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Layer {
     pub encoding_id: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct StreamInfo {
     pub id: String,
@@ -15,9 +26,30 @@ pub struct StreamInfo {
     pub subscribe_leave_time: i64,
     pub publish_session_info: Option<SessionInfo>,
     pub subscribe_session_infos: Vec<SessionInfo>,
+    /// The tracks of the publisher, video first then audio, each in the order of
+    /// its transceiver. The n-th recvonly transceiver of a kind in a subscriber
+    /// offer receives the n-th track of that kind.
+    pub publish_tracks: Vec<TrackInfo>,
+    /// The publisher's tracks are recorded to disk.
+    pub recording: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackInfo {
+    /// `video` or `audio`
+    pub kind: String,
+    pub mid: String,
+    /// Track id from the publisher's msid
+    pub id: String,
+    /// Stream id from the publisher's msid
+    pub stream_id: String,
+    /// Simulcast rids, a single empty rid without simulcast
+    pub rids: Vec<String>,
+    pub codec: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct SessionInfo {
     pub id: String,
@@ -25,7 +57,7 @@ pub struct SessionInfo {
     pub connect_state: RTCPeerConnectionState,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ReforwardInfo {
     pub target_url: String,
@@ -33,7 +65,7 @@ pub struct ReforwardInfo {
 }
 
 /// PeerConnectionState indicates the state of the PeerConnection.
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum RTCPeerConnectionState {
     #[default]
     #[serde(rename = "Unspecified")]
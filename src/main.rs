@@ -3,10 +3,12 @@ extern crate lazy_static;
 
 use axum::body::{Body, Bytes};
 use axum::extract::Request;
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
 use clap::{command, Parser};
 
 use http_body_util::BodyExt;
@@ -14,6 +16,7 @@ use room::Room;
 use route::r#static::static_server;
 use std::collections::HashMap;
 use std::future::IntoFuture;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
@@ -22,6 +25,7 @@ use tracing::{debug, error, info, info_span, warn};
 
 use crate::auth::ManyValidate;
 use crate::config::Config;
+use crate::health::ServerStatus;
 use crate::result::Result;
 use crate::route::AppState;
 
@@ -29,7 +33,9 @@ mod auth;
 mod config;
 mod constant;
 mod error;
+mod event;
 mod forward;
+mod health;
 mod http;
 mod r#macro;
 mod metrics;
@@ -37,10 +43,16 @@ mod result;
 mod room;
 mod route;
 mod rtc;
+mod shutdown;
 mod support;
+mod tls;
+mod turn;
 
 pub const HASH_LEN: usize = 8;
 
+const REDACTED: &str = "***";
+const REDACTED_FIELDS: [&str; 4] = ["shared_key", "master_key", "token", "credential"];
+
 lazy_static! {
     static ref ROOMS: Mutex<HashMap<i32, Room>> = Mutex::<HashMap<i32, Room>>::new(HashMap::new());
 }
@@ -83,12 +95,44 @@ where
     };
 
     if let Ok(body) = std::str::from_utf8(&bytes) {
+        let mut headers = headers;
+        if headers.contains_key(AUTHORIZATION) {
+            headers.insert(AUTHORIZATION, HeaderValue::from_static(REDACTED));
+        }
+        let body = redact(body);
         debug!("{direction} headers = {headers:?} body = {body:?}");
     }
 
     Ok(bytes)
 }
 
+// The credentials the json bodies carry are never logged.
+fn redact(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(mut json) => {
+            redact_value(&mut json);
+            json.to_string()
+        }
+        Err(_) => body.to_owned(),
+    }
+}
+
+fn redact_value(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Args {
@@ -100,10 +144,22 @@ struct Args {
 async fn main() -> Result<()> {
     let args = Args::parse();
     let cfg = Config::parse(args.config);
-    utils::set_log(format!("rust_server_for_multiplayer={},webrtc=error", cfg.log.level));
+    utils::set_log(format!(
+        "rust_server_for_multiplayer={},webrtc=error",
+        cfg.log.level
+    ));
 
     warn!("set log level : {}", cfg.log.level);
+    metrics::init();
     debug!("config : {:?}", cfg);
+    forward::rtc::ice::init(cfg.ice.clone())
+        .await
+        .unwrap_or_else(|e| panic!("ice config error [{}]", e));
+    if let Some(turn) = cfg.turn.clone() {
+        turn::start(turn)
+            .await
+            .unwrap_or_else(|e| panic!("turn server error [{}]", e));
+    }
     let listener = tokio::net::TcpListener::bind(&cfg.http.listen)
         .await
         .unwrap();
@@ -114,13 +170,14 @@ async fn main() -> Result<()> {
         config: cfg.clone(),
     };
     let auth_layer = ValidateRequestHeaderLayer::custom(ManyValidate::new(vec![cfg.auth]));
-    let app = Router::new()
+    let mut app = Router::new()
         .merge(
             route::room::room::route()
                 .merge(route::room::create::route())
                 .merge(route::room::delete::route())
                 .merge(route::room::join::route())
                 .merge(route::room::exit::route())
+                .merge(route::room::pins::route())
                 .merge(route::rtc::infos::route())
                 .merge(route::rtc::stream::route())
                 .merge(route::rtc::whip::route())
@@ -128,6 +185,20 @@ async fn main() -> Result<()> {
                 .merge(route::ws::route())
                 .layer(auth_layer),
         )
+        .merge(route::openapi::route())
+        .merge(route::metrics::route())
+        .merge(route::health::route());
+    // An empty `ManyValidate` lets every request through, so the admin API is
+    // left out entirely unless credentials are configured for it.
+    if cfg.admin.to_authorizations().is_empty() {
+        info!("admin api disabled, no [admin] credentials configured");
+    } else {
+        let admin_auth_layer =
+            ValidateRequestHeaderLayer::custom(ManyValidate::new(vec![cfg.admin.clone()]));
+        app = app.merge(route::admin::route().layer(admin_auth_layer));
+    }
+    let app = app
+        .route_layer(axum::middleware::from_fn(metrics::track_http))
         .with_state(app_state.clone())
         .layer(if cfg.http.cors {
             CorsLayer::permissive()
@@ -147,9 +218,35 @@ async fn main() -> Result<()> {
                 span
             }),
         );
+    health::set_status(ServerStatus::Ready);
+    // The server keeps serving while draining, so that connected clients still
+    // receive the shutdown event and can leave on their own.
+    let app = static_server(app);
+    let mut server = match cfg.http.tls.clone() {
+        Some(tls) => {
+            let rustls_config = RustlsConfig::from_config(
+                tls::server_config(&tls).unwrap_or_else(|e| panic!("tls config error [{}]", e)),
+            );
+            tls::watch(tls, rustls_config.clone());
+            info!("TLS enabled");
+            tokio::spawn(
+                axum_server::from_tcp_rustls(listener.into_std().unwrap(), rustls_config)
+                    .serve(app.into_make_service()),
+            )
+        }
+        None => tokio::spawn(axum::serve(listener, app).into_future()),
+    };
     tokio::select! {
-        Err(e) = axum::serve(listener, static_server(app)).into_future() => error!("Application error: {e}"),
-        msg = signal::wait_for_stop_signal() => debug!("Received signal: {}", msg),
+        result = &mut server => {
+            if let Ok(Err(e)) = result {
+                error!("Application error: {e}");
+            }
+        }
+        msg = signal::wait_for_stop_signal() => {
+            debug!("Received signal: {}", msg);
+            shutdown::drain(Duration::from_millis(cfg.shutdown.drain_period.0)).await;
+            server.abort();
+        }
     }
     info!("Server shutdown");
 
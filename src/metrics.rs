@@ -1,8 +1,256 @@
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
 use lazy_static::lazy_static;
-use prometheus::{Registry, TextEncoder};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::ROOMS;
 
 lazy_static! {
     pub static ref REGISTRY: Registry =
-        Registry::new_custom(Some("rust-server-for-multiplayer".to_string()), None).unwrap();
+        Registry::new_custom(Some("rust_server_for_multiplayer".to_string()), None).unwrap();
     pub static ref ENCODER: TextEncoder = TextEncoder::new();
+
+    // Gauges are recomputed from `ROOMS` on every scrape, see `gather`.
+    pub static ref ROOMS_TOTAL: IntGauge =
+        register(IntGauge::new("rooms", "Number of rooms").unwrap());
+    pub static ref CLIENTS: IntGaugeVec = register(
+        IntGaugeVec::new(Opts::new("clients", "Number of joined users"), &["room"]).unwrap()
+    );
+    pub static ref STREAMS: IntGaugeVec = register(
+        IntGaugeVec::new(Opts::new("streams", "Number of streams"), &["room"]).unwrap()
+    );
+    pub static ref PUBLISH_SESSIONS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("publish_sessions", "Number of publish sessions"),
+            &["room"]
+        )
+        .unwrap()
+    );
+    pub static ref SUBSCRIBE_SESSIONS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("subscribe_sessions", "Number of subscribe sessions"),
+            &["room"]
+        )
+        .unwrap()
+    );
+    pub static ref WEBSOCKET_GROUP_USERS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("websocket_group_users", "Number of users in WebSocket groups"),
+            &["room"]
+        )
+        .unwrap()
+    );
+
+    pub static ref RTP_PACKETS: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("rtp_forwarded_packets_total", "RTP packets written to subscribers"),
+            &["kind"]
+        )
+        .unwrap()
+    );
+    pub static ref RTP_BYTES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("rtp_forwarded_bytes_total", "RTP bytes written to subscribers"),
+            &["kind"]
+        )
+        .unwrap()
+    );
+    pub static ref RTP_NACKED: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("rtp_nacked_packets_total", "RTP packets NACKed by subscribers"),
+            &["result"]
+        )
+        .unwrap()
+    );
+    pub static ref KEYFRAME_REQUESTS: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new(
+                "keyframe_requests_total",
+                "Keyframe requests (PLI, FIR) of subscribers, and the ones forwarded to publishers"
+            ),
+            &["result"]
+        )
+        .unwrap()
+    );
+    pub static ref DATA_CHANNEL_MESSAGES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("data_channel_messages_total", "Data channel messages"),
+            &["direction"]
+        )
+        .unwrap()
+    );
+    pub static ref DATA_CHANNEL_BYTES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("data_channel_bytes_total", "Data channel bytes"),
+            &["direction"]
+        )
+        .unwrap()
+    );
+    pub static ref WEBSOCKET_MESSAGES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("websocket_messages_total", "WebSocket relay messages"),
+            &["direction"]
+        )
+        .unwrap()
+    );
+    pub static ref WEBSOCKET_BYTES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("websocket_bytes_total", "WebSocket relay bytes"),
+            &["direction"]
+        )
+        .unwrap()
+    );
+    pub static ref RECORDED_BYTES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("recorded_bytes_total", "Media bytes written to recordings"),
+            &["kind"]
+        )
+        .unwrap()
+    );
+    pub static ref BROADCAST_LAGGED: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new(
+                "broadcast_lagged_messages_total",
+                "Messages dropped because a broadcast receiver lagged behind"
+            ),
+            &["channel"]
+        )
+        .unwrap()
+    );
+
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register(
+        HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "path", "status"]
+        )
+        .unwrap()
+    );
+}
+
+pub const DIRECTION_IN: &str = "in";
+pub const DIRECTION_OUT: &str = "out";
+
+pub const NACK_RETRANSMITTED: &str = "retransmitted";
+pub const NACK_UPSTREAM: &str = "upstream";
+
+pub const KEYFRAME_REQUESTED: &str = "requested";
+pub const KEYFRAME_FORWARDED: &str = "forwarded";
+
+pub const CHANNEL_RTP: &str = "rtp";
+pub const CHANNEL_RTCP: &str = "rtcp";
+pub const CHANNEL_DATA_CHANNEL: &str = "data_channel";
+pub const CHANNEL_WEBSOCKET: &str = "websocket";
+pub const CHANNEL_SERVER_EVENT: &str = "server_event";
+
+fn register<T: Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY.register(Box::new(collector.clone())).unwrap();
+    collector
+}
+
+/// Registers every metric up front, so that `/metrics` lists them before their
+/// first sample.
+pub fn init() {
+    lazy_static::initialize(&ROOMS_TOTAL);
+    lazy_static::initialize(&CLIENTS);
+    lazy_static::initialize(&STREAMS);
+    lazy_static::initialize(&PUBLISH_SESSIONS);
+    lazy_static::initialize(&SUBSCRIBE_SESSIONS);
+    lazy_static::initialize(&WEBSOCKET_GROUP_USERS);
+    lazy_static::initialize(&RTP_PACKETS);
+    lazy_static::initialize(&RTP_BYTES);
+    lazy_static::initialize(&RTP_NACKED);
+    lazy_static::initialize(&KEYFRAME_REQUESTS);
+    lazy_static::initialize(&DATA_CHANNEL_MESSAGES);
+    lazy_static::initialize(&DATA_CHANNEL_BYTES);
+    lazy_static::initialize(&WEBSOCKET_MESSAGES);
+    lazy_static::initialize(&WEBSOCKET_BYTES);
+    lazy_static::initialize(&RECORDED_BYTES);
+    lazy_static::initialize(&BROADCAST_LAGGED);
+    lazy_static::initialize(&HTTP_REQUEST_DURATION);
+}
+
+async fn update_gauges() {
+    // Snapshot the rooms so that `ROOMS` is not held across the awaits below.
+    let rooms: Vec<_> = ROOMS.lock().await.values().cloned().collect();
+
+    // Drop the series of rooms which have been deleted since the last scrape.
+    CLIENTS.reset();
+    STREAMS.reset();
+    PUBLISH_SESSIONS.reset();
+    SUBSCRIBE_SESSIONS.reset();
+    WEBSOCKET_GROUP_USERS.reset();
+
+    ROOMS_TOTAL.set(rooms.len() as i64);
+    for room in rooms.iter() {
+        let label = room.id().to_string();
+        let label = [label.as_str()];
+
+        let clients = room.client_map().read().await.len();
+        CLIENTS.with_label_values(&label).set(clients as i64);
+
+        let forwarder = room.forwarder();
+        let forwarder = forwarder.read().await;
+        let forward_infos = forwarder.forward_infos(vec![]).await;
+        drop(forwarder);
+        let publish_sessions = forward_infos
+            .iter()
+            .filter(|forward_info| forward_info.publish_session_info.is_some())
+            .count();
+        let subscribe_sessions: usize = forward_infos
+            .iter()
+            .map(|forward_info| forward_info.subscribe_session_infos.len())
+            .sum();
+        STREAMS
+            .with_label_values(&label)
+            .set(forward_infos.len() as i64);
+        PUBLISH_SESSIONS
+            .with_label_values(&label)
+            .set(publish_sessions as i64);
+        SUBSCRIBE_SESSIONS
+            .with_label_values(&label)
+            .set(subscribe_sessions as i64);
+
+        let group_manager = room.group_manager();
+        let group_manager = group_manager.read().await;
+        let group_users: u32 = group_manager.group_user_counts().await.values().sum();
+        drop(group_manager);
+        WEBSOCKET_GROUP_USERS
+            .with_label_values(&label)
+            .set(group_users as i64);
+    }
+}
+
+/// Returns the text exposition of `REGISTRY`.
+pub async fn gather() -> String {
+    update_gauges().await;
+
+    let mut buffer = vec![];
+    ENCODER.encode(&REGISTRY.gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/// Records `HTTP_REQUEST_DURATION`. It is installed with `route_layer`, so only
+/// matched routes are observed and the path label stays the route template.
+pub async fn track_http(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+
+    let response = next.run(req).await;
+
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    response
 }
@@ -5,6 +5,8 @@ use std::sync::Arc;
 use libws::GroupsManager;
 
 use crate::config::Config;
+use crate::error::AppError;
+use crate::event::ServerEvent;
 use crate::forward::rtc::client::Client;
 use crate::result::Result;
 use crate::route::room::RoomInfoJson;
@@ -45,10 +47,15 @@ impl Room {
         config: Config,
     ) -> Self {
         let client_map: Arc<RwLock<HashMap<i32, Client>>> = Default::default();
-        let forwarder = Arc::new(RwLock::new(Forwarder::new(ForwarderConfig::from_config(
-            config.clone(),
-        ))));
+        let forwarder = Forwarder::new(ForwarderConfig::from_config(config.clone(), id));
+        let events = forwarder.subscribe_events();
+        let forwarder = Arc::new(RwLock::new(forwarder));
         let group_manager = Arc::new(RwLock::new(GroupsManager::new()));
+        tokio::spawn(ServerEvent::relay(
+            events,
+            Arc::downgrade(&forwarder),
+            group_manager.clone(),
+        ));
 
         let room: Room = Self {
             id: id,
@@ -82,6 +89,10 @@ impl Room {
         String::from_str(self.name.as_str()).unwrap()
     }
 
+    pub fn needs_host(&self) -> bool {
+        self.needs_host
+    }
+
     pub fn is_public(&self) -> bool {
         self.is_public
     }
@@ -117,6 +128,11 @@ impl Room {
         }
     }
 
+    /// Only rooms which need a host have one, and it always joins as user 0.
+    pub fn is_host(&self, user_id: i32) -> bool {
+        self.needs_host && user_id == 0
+    }
+
     pub fn auth_shared_key(&self, key: String) -> bool {
         let hash = utils::unique::hash_from_string(key);
 
@@ -152,19 +168,22 @@ impl Room {
         let client = clients.get(&user_id).cloned();
         drop(clients);
 
+        if let Some(client) = &client {
+            if check_token && !client.check_token(token.clone()) {
+                return Err(AppError::token_invalid("token does not match"));
+            }
+        }
+
         let group_manager = self.group_manager();
         let group_manager = group_manager.write().await;
         group_manager.end_user(user_id as u32).await;
         drop(group_manager);
 
         if let Some(mut client) = client {
-            if check_token && !client.check_token(token.clone()) {
-                return Ok(false);
-            }
-
             for stream in client.get_streams().await {
                 let forwarder = self.forwarder.write().await;
-                forwarder.stream_delete(stream.clone()).await?;
+                // The stream may already be gone after its publish leave timeout.
+                let _ = forwarder.stream_delete(stream.clone()).await;
                 drop(forwarder);
                 client.remove_stream(stream.clone()).await?;
             }
@@ -193,13 +212,16 @@ impl Room {
         master_key: String,
         user_id: &mut i32,
         token: &mut u32,
-    ) -> Result<bool> {
+    ) -> Result<()> {
         let mut clients = self.client_map.write().await;
 
         if self.needs_host {
             if master_key != "" {
-                if !self.auth_master_key(master_key) || clients.contains_key(&0) {
-                    return Ok(false);
+                if !self.auth_master_key(master_key) {
+                    return Err(AppError::bad_key("master key does not match"));
+                }
+                if clients.contains_key(&0) {
+                    return Err(AppError::host_exists("host has already joined"));
                 }
                 *user_id = 0;
                 *token = utils::unique::generate_unique_u32();
@@ -215,7 +237,7 @@ impl Room {
                     break;
                 }
                 if !is_ok {
-                    return Ok(false);
+                    return Err(AppError::room_full("room is full"));
                 }
             }
         } else {
@@ -230,7 +252,7 @@ impl Room {
                 break;
             }
             if !is_ok {
-                return Ok(false);
+                return Err(AppError::room_full("room is full"));
             }
         }
 
@@ -240,6 +262,6 @@ impl Room {
         );
         self._join(user_id.clone(), token.clone()).await?;
 
-        Ok(true)
+        Ok(())
     }
 }
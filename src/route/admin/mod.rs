@@ -0,0 +1,110 @@
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+use crate::http::response::StreamInfo;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::AppState;
+use crate::ROOMS;
+
+pub mod room;
+pub mod stats;
+pub mod stream;
+
+/// Server-wide routes for operators. They are not bound to room credentials, so
+/// `main` only mounts them behind the `[admin]` auth layer.
+pub fn route() -> Router<AppState> {
+    room::route().merge(stream::route()).merge(stats::route())
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AdminUserJson {
+    pub id: i32,
+    pub name: String,
+    pub streams: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AdminGroupJson {
+    pub name: String,
+    pub user_count: u32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AdminRoomJson {
+    pub id: i32,
+    pub name: String,
+    pub capacity: u32,
+    pub description: String,
+    pub needs_host: bool,
+    pub is_public: bool,
+    pub users: Vec<AdminUserJson>,
+    pub streams: Vec<StreamInfo>,
+    pub groups: Vec<AdminGroupJson>,
+}
+
+pub(crate) async fn rooms_snapshot() -> Vec<Room> {
+    let rooms = ROOMS.lock().await;
+    rooms.values().cloned().collect()
+}
+
+pub(crate) async fn find_room(room_id: i32) -> Result<Room> {
+    let rooms = ROOMS.lock().await;
+    rooms
+        .get(&room_id)
+        .cloned()
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            room_id
+        )))
+}
+
+pub(crate) async fn room_json(room: &Room) -> AdminRoomJson {
+    let client_map = room.client_map();
+    let clients = client_map.read().await;
+    let mut users = vec![];
+    for client in clients.values() {
+        users.push(AdminUserJson {
+            id: client.id(),
+            name: client.name(),
+            streams: client.get_streams().await,
+        });
+    }
+    drop(clients);
+    users.sort_by_key(|user| user.id);
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    let streams = forwarder
+        .forward_infos(vec![])
+        .await
+        .into_iter()
+        .map(|forward_info| forward_info.into())
+        .collect();
+    drop(forwarder);
+
+    let group_manager = room.group_manager();
+    let group_manager = group_manager.read().await;
+    let mut groups: Vec<AdminGroupJson> = group_manager
+        .group_user_counts()
+        .await
+        .into_iter()
+        .map(|(name, user_count)| AdminGroupJson { name, user_count })
+        .collect();
+    drop(group_manager);
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    AdminRoomJson {
+        id: room.id(),
+        name: room.name(),
+        capacity: room.capacity(),
+        description: room.description(),
+        needs_host: room.needs_host(),
+        is_public: room.is_public(),
+        users,
+        streams,
+        groups,
+    }
+}
@@ -0,0 +1,141 @@
+use axum::body::Body;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use http::StatusCode;
+use tracing::{debug, info};
+
+use crate::error::AppError;
+use crate::http;
+use crate::http::BodyUtil;
+use crate::result::Result;
+use crate::room::Room;
+use crate::route::admin::*;
+use crate::route::{AppState, PathParam};
+use crate::ROOMS;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/admin/rooms", get(rooms))
+        .merge(Router::new().route("/admin/rooms/:room_id", get(room).delete(delete_room)))
+        .merge(Router::new().route("/admin/rooms/:room_id/users/:user_id", delete(delete_user)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/rooms",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every room, including private ones", body = [AdminRoomJson]),
+    )
+)]
+async fn rooms() -> Result<Response> {
+    debug!("HTTP GET /admin/rooms");
+
+    let mut infos = vec![];
+    for room in rooms_snapshot().await.iter() {
+        infos.push(room_json(room).await);
+    }
+    infos.sort_by_key(|info| info.id);
+
+    Ok(Json(infos).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}",
+    tag = "admin",
+    params(("room_id" = i32, Path, description = "Room id")),
+    responses(
+        (status = 200, description = "Room with its users, streams and groups", body = AdminRoomJson),
+        (status = 400, description = "Malformed path", body = ErrorJson),
+        (status = 404, description = "Room not found", body = ErrorJson),
+    )
+)]
+async fn room(PathParam(room_id): PathParam<i32>) -> Result<Response> {
+    debug!("HTTP GET /admin/rooms/{}", room_id);
+
+    let room = find_room(room_id).await?;
+
+    Ok(Json(room_json(&room).await).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}",
+    tag = "admin",
+    params(("room_id" = i32, Path, description = "Room id")),
+    responses(
+        (status = 200, description = "Room closed", body = String),
+        (status = 400, description = "Malformed path", body = ErrorJson),
+        (status = 404, description = "Room not found", body = ErrorJson),
+    )
+)]
+async fn delete_room(PathParam(room_id): PathParam<i32>) -> Result<Response> {
+    debug!("HTTP DELETE /admin/rooms/{}", room_id);
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = rooms
+        .get_mut(&room_id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            room_id
+        )))?;
+    room.all_user_delete().await?;
+
+    // Streams nobody owns (e.g. virtual publishes) are not removed with the users.
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    for forward_info in forwarder.forward_infos(vec![]).await {
+        let _ = forwarder.stream_delete(forward_info.id).await;
+    }
+    drop(forwarder);
+
+    rooms.remove(&room_id);
+    info!("[admin] room {} closed", room_id);
+
+    Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/users/{user_id}",
+    tag = "admin",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("user_id" = i32, Path, description = "User id"),
+    ),
+    responses(
+        (status = 200, description = "User removed with its streams", body = String),
+        (status = 400, description = "Malformed path", body = ErrorJson),
+        (status = 404, description = "Room or user not found", body = ErrorJson),
+    )
+)]
+async fn delete_user(PathParam((room_id, user_id)): PathParam<(i32, i32)>) -> Result<Response> {
+    debug!("HTTP DELETE /admin/rooms/{}/users/{}", room_id, user_id);
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = rooms
+        .get_mut(&room_id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            room_id
+        )))?;
+    if !room.user_delete(user_id, 0, false).await? {
+        return Err(AppError::user_not_found(format!(
+            "user {} not found",
+            user_id
+        )));
+    }
+    info!("[admin] user {} removed from room {}", user_id, room_id);
+
+    Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ))
+}
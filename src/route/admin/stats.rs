@@ -0,0 +1,67 @@
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::result::Result;
+use crate::route::admin::*;
+use crate::route::AppState;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route("/admin/stats", get(stats))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Default)]
+pub struct AdminStatsJson {
+    pub rooms: usize,
+    pub users: usize,
+    pub streams: usize,
+    pub publish_sessions: usize,
+    pub subscribe_sessions: usize,
+    pub groups: usize,
+    pub group_users: u32,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Server-wide counts", body = AdminStatsJson),
+    )
+)]
+async fn stats() -> Result<Response> {
+    debug!("HTTP GET /admin/stats");
+
+    let rooms = rooms_snapshot().await;
+
+    let mut stats = AdminStatsJson {
+        rooms: rooms.len(),
+        ..Default::default()
+    };
+    for room in rooms.iter() {
+        stats.users += room.client_map().read().await.len();
+
+        let forwarder = room.forwarder();
+        let forwarder = forwarder.read().await;
+        for forward_info in forwarder.forward_infos(vec![]).await {
+            stats.streams += 1;
+            if forward_info.publish_session_info.is_some() {
+                stats.publish_sessions += 1;
+            }
+            stats.subscribe_sessions += forward_info.subscribe_session_infos.len();
+        }
+        drop(forwarder);
+
+        let group_manager = room.group_manager();
+        let group_manager = group_manager.read().await;
+        let group_user_counts = group_manager.group_user_counts().await;
+        drop(group_manager);
+        stats.groups += group_user_counts.len();
+        stats.group_users += group_user_counts.values().sum::<u32>();
+    }
+
+    Ok(Json(stats).into_response())
+}
@@ -0,0 +1,91 @@
+use axum::body::Body;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use http::StatusCode;
+use tracing::{debug, info};
+
+use crate::error::AppError;
+use crate::http;
+use crate::http::response::StreamInfo;
+use crate::result::Result;
+use crate::route::admin::*;
+use crate::route::{AppState, PathParam};
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/admin/rooms/:room_id/streams/:stream", get(stream))
+        .merge(Router::new().route(
+            "/admin/rooms/:room_id/streams/:stream/sessions/:session",
+            delete(close_session),
+        ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}/streams/{stream}",
+    tag = "admin",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    responses(
+        (status = 200, description = "Stream with its publish and subscribe sessions", body = StreamInfo),
+        (status = 400, description = "Malformed path", body = ErrorJson),
+        (status = 404, description = "Room or stream not found", body = ErrorJson),
+    )
+)]
+async fn stream(PathParam((room_id, stream)): PathParam<(i32, String)>) -> Result<Response> {
+    debug!("HTTP GET /admin/rooms/{}/streams/{}", room_id, stream);
+
+    let room = find_room(room_id).await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    let info: StreamInfo = forwarder
+        .forward_infos(vec![stream.clone()])
+        .await
+        .into_iter()
+        .find(|forward_info| forward_info.id == stream)
+        .ok_or(AppError::stream_not_found("stream not exists"))?
+        .into();
+    drop(forwarder);
+
+    Ok(Json(info).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/streams/{stream}/sessions/{session}",
+    tag = "admin",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+        ("session" = String, Path, description = "Publish or subscribe session id"),
+    ),
+    responses(
+        (status = 200, description = "Session closed"),
+        (status = 400, description = "Malformed path", body = ErrorJson),
+        (status = 404, description = "Room, stream or session not found", body = ErrorJson),
+    )
+)]
+async fn close_session(
+    PathParam((room_id, stream, session)): PathParam<(i32, String, String)>,
+) -> Result<Response> {
+    debug!(
+        "HTTP DELETE /admin/rooms/{}/streams/{}/sessions/{}",
+        room_id, stream, session
+    );
+
+    let room = find_room(room_id).await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    forwarder
+        .remove_peer(stream.clone(), session.clone())
+        .await?;
+    drop(forwarder);
+    info!("[admin] [{}] session {} closed", stream, session);
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
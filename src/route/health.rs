@@ -0,0 +1,42 @@
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use http::StatusCode;
+
+use crate::health::{self, ReadinessJson};
+use crate::route::AppState;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .merge(Router::new().route("/readyz", get(readyz)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The process is alive")),
+)]
+async fn healthz() -> Response {
+    (StatusCode::OK, "ok").into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every check passed", body = ReadinessJson),
+        (status = 503, description = "Starting, draining, or a check failed", body = ReadinessJson),
+    )
+)]
+async fn readyz() -> Response {
+    let readiness: ReadinessJson = health::readiness().await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
@@ -0,0 +1,19 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::Encoder;
+
+use crate::metrics::{self, ENCODER};
+use crate::route::AppState;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, ENCODER.format_type().to_owned())],
+        metrics::gather().await,
+    )
+}
@@ -1,19 +1,31 @@
 use std::collections::HashMap;
 
-use axum::body::Body;
+use axum::async_trait;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{FromRequest, FromRequestParts, Path, Request};
 use axum::response::Response;
+use axum::Json;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use http::StatusCode;
+use http::header::LINK;
+use http::request::Parts;
+use http::HeaderValue;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::config::Config;
+use crate::config::{Config, IceServer};
+use crate::error::AppError;
 use crate::forward::rtc::client::Client;
-use crate::http::BodyUtil;
+use crate::result::Result;
 use crate::room::Room;
-use crate::{http, ROOMS};
+use crate::turn;
+use crate::ROOMS;
 
+pub mod admin;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
 pub mod room;
 pub mod rtc;
 pub mod r#static;
@@ -24,58 +36,110 @@ pub struct AppState {
     pub config: Config,
 }
 
-pub fn parse_base64_into_json<T>(params: &HashMap<String, String>) -> Result<T, Response>
+/// Same as `axum::Json`, but rejects malformed bodies with an `AppError` so that
+/// the v2 routes answer with the same error json as every other failure.
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonBody<T>
 where
-    T: DeserializeOwned + Serialize,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
 {
-    if !params.contains_key("base64") {
-        return Err(http::create_response(
-            Body::from("Missing Params".to_string()),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
-    let json_obj = match BASE64_STANDARD.decode(params.get("base64").unwrap()) {
-        Ok(json_obj) => json_obj,
-        Err(err) => {
-            return Err(http::create_response(
-                Body::from(err.to_string()),
-                StatusCode::NOT_ACCEPTABLE,
-            ))
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(JsonBody(value)),
+            Err(rejection) => Err(AppError::bad_request_details(
+                "Invalid request body",
+                rejection.body_text(),
+            )),
         }
-    };
-    let json: T = match serde_json::from_slice(&json_obj) {
-        Ok(json) => json,
-        Err(err) => {
-            return Err(http::create_response(
-                Body::from(err.to_string()),
-                StatusCode::NOT_ACCEPTABLE,
-            ))
+    }
+}
+
+/// Same as `axum::extract::Path`, but rejects malformed path parameters with an
+/// `AppError`, like `JsonBody` does for bodies.
+pub struct PathParam<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for PathParam<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self> {
+        match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(value)) => Ok(PathParam(value)),
+            Err(rejection) => Err(AppError::bad_request_details(
+                "Invalid path parameter",
+                rejection.body_text(),
+            )),
         }
-    };
+    }
+}
+
+pub fn parse_base64_into_json<T>(params: &HashMap<String, String>) -> Result<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let base64 = params
+        .get("base64")
+        .ok_or(AppError::bad_request_details("Missing Params", "base64"))?;
+    let json_obj = BASE64_STANDARD
+        .decode(base64)
+        .map_err(|err| AppError::bad_request_details("Invalid base64 param", err))?;
+    let json: T = serde_json::from_slice(&json_obj)
+        .map_err(|err| AppError::bad_request_details("Invalid base64 param", err))?;
     Ok(json)
 }
 
+// The v2 WebSocket routes cannot carry a request body, so the client sends the
+// request json as the first message right after the upgrade instead.
+pub async fn recv_json<T>(socket: &mut WebSocket) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    while let Some(msg) = socket.recv().await {
+        let msg = msg.map_err(AppError::bad_request)?;
+        let json = match msg {
+            Message::Text(text) => serde_json::from_str(&text),
+            // Unity's NativeWebSocket may send the request as a byte array.
+            Message::Binary(binary) => serde_json::from_slice(&binary),
+            Message::Ping(_) | Message::Pong(_) => continue,
+            Message::Close(_) => break,
+        };
+        return json.map_err(|err| AppError::bad_request_details("Invalid request", err));
+    }
+    Err(AppError::bad_request("Missing Request"))
+}
+
+/// Reports an error on an already upgraded WebSocket, where a status code can
+/// no longer be sent.
+pub async fn send_error(socket: &mut WebSocket, err: AppError) {
+    let text = serde_json::to_string(&err.to_json()).unwrap();
+    let _ = socket.send(Message::Text(text)).await;
+}
+
 pub async fn auth_user(
     room_id: i32,
     shared_key: String,
     user_id: i32,
     token: u32,
-) -> Result<(Room, Client), Response> {
+) -> Result<(Room, Client)> {
     let mut rooms = ROOMS.lock().await;
 
-    if !rooms.contains_key(&room_id) {
-        return Err(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
-
-    let room: &mut Room = rooms.get_mut(&room_id).unwrap();
+    let room: &mut Room = rooms
+        .get_mut(&room_id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            room_id
+        )))?;
     if !room.auth_shared_key(shared_key.clone()) {
-        return Err(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::bad_key("shared key does not match"));
     }
 
     let client_map = room.client_map();
@@ -85,18 +149,46 @@ pub async fn auth_user(
 
     if let Some(client) = client {
         if !client.check_token(token.clone()) {
-            return Err(http::create_response(
-                Body::from(BodyUtil::INVILED_TOKEN),
-                StatusCode::NOT_ACCEPTABLE,
-            ));
+            return Err(AppError::token_invalid("token does not match"));
         }
 
         let room = rooms.get(&room_id).cloned().unwrap();
         return Ok((room, client));
     } else {
-        return Err(http::create_response(
-            Body::from(BodyUtil::UNKNOWN_ERROR),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::user_not_found(format!(
+            "user {} not found",
+            user_id
+        )));
+    }
+}
+
+/// The ICE servers a user of a room should use: the configured `ice_servers`,
+/// then the embedded TURN server with credentials minted for this user.
+pub fn ice_servers(config: &Config, room_id: i32, user_id: i32) -> Vec<IceServer> {
+    let mut ice_servers = config.ice_servers.clone();
+    ice_servers.extend(turn::credentials(&format!("{}_{}", room_id, user_id)));
+    ice_servers
+}
+
+/// Adds one `Link: <url>; rel="ice-server"` header per ICE server url, in the
+/// format used by WHIP and WHEP.
+pub fn with_ice_server_links(mut response: Response, ice_servers: &[IceServer]) -> Response {
+    for ice_server in ice_servers {
+        for url in &ice_server.urls {
+            let mut link = format!("<{}>; rel=\"ice-server\"", url);
+            if !ice_server.username.is_empty() {
+                link += &format!(
+                    "; username=\"{}\"; credential=\"{}\"",
+                    ice_server.username, ice_server.credential
+                );
+            }
+            if !ice_server.credential_type.is_empty() {
+                link += &format!("; credential-type=\"{}\"", ice_server.credential_type);
+            }
+            if let Ok(value) = HeaderValue::from_str(&link) {
+                response.headers_mut().append(LINK, value);
+            }
+        }
     }
+    response
 }
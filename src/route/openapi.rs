@@ -0,0 +1,108 @@
+use axum::routing::get;
+use axum::{Json, Router};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::config::IceServer;
+use crate::health::{CheckJson, ReadinessJson};
+use crate::http::response::{
+    ErrorJson, Layer, RTCPeerConnectionState, SessionInfo, StreamInfo, TrackInfo,
+};
+use crate::route::admin::stats::AdminStatsJson;
+use crate::route::admin::{AdminGroupJson, AdminRoomJson, AdminUserJson};
+use crate::route::room::RoomInfoJson;
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route("/openapi.json", get(openapi))
+}
+
+/// OpenAPI 3 description of the `/v2` JSON API.
+///
+/// Request bodies are inlined into each operation because every route module
+/// names its own body `BodyJson`; only the shared types are listed as components.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        room::room::room,
+        room::room::room_specific_v2,
+        room::create::create_room_v2,
+        room::delete::delete_room_v2,
+        room::join::room_join_v2,
+        room::exit::room_exit_v2,
+        room::pins::pins_v2,
+        rtc::infos::infos_v2,
+        rtc::stream::create_v2,
+        rtc::stream::destroy_v2,
+        rtc::stream::get_layer_v2,
+        rtc::stream::select_layer_v2,
+        rtc::stream::un_select_layer_v2,
+        rtc::stream::close_session_v2,
+        rtc::stream::change_resource_v2,
+        rtc::stream::start_record_v2,
+        rtc::stream::stop_record_v2,
+        rtc::stream::start_playback_v2,
+        rtc::stream::stop_playback_v2,
+        rtc::whip::whip_v2,
+        rtc::whep::whep_v2,
+        ws::stream_v2,
+        admin::room::rooms,
+        admin::room::room,
+        admin::room::delete_room,
+        admin::room::delete_user,
+        admin::stream::stream,
+        admin::stream::close_session,
+        admin::stats::stats,
+        health::healthz,
+        health::readyz,
+    ),
+    components(schemas(
+        ErrorJson,
+        IceServer,
+        RoomInfoJson,
+        StreamInfo,
+        SessionInfo,
+        TrackInfo,
+        Layer,
+        RTCPeerConnectionState,
+        rtc::whip::BodyJson,
+        rtc::whip::SignalingJson,
+        rtc::whep::BodyJson,
+        ws::BodyJson,
+        AdminRoomJson,
+        AdminUserJson,
+        AdminGroupJson,
+        AdminStatsJson,
+        CheckJson,
+        ReadinessJson,
+    )),
+    modifiers(&SecurityAddon),
+    security(("bearer" = []), ("basic" = [])),
+    tags(
+        (name = "room", description = "Room management"),
+        (name = "stream", description = "Streams, signaling and data relay"),
+        (name = "health", description = "Liveness and readiness probes"),
+        (name = "admin", description = "Server-wide operator API, only mounted when `[admin]` credentials are configured"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -8,7 +8,10 @@ use log::debug;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use utoipa::ToSchema;
 
+use crate::config::Config;
+use crate::health;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -16,10 +19,12 @@ use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/create/:base64/", post(create_room))
+    Router::new()
+        .route("/room/create/:base64/", post(create_room))
+        .merge(Router::new().route("/v2/rooms", post(create_room_v2)))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct RequestJson {
     name: String,
     capacity: u32,
@@ -28,6 +33,15 @@ struct RequestJson {
     shared_key: String,
     master_key: String,
     description: String,
+    /// Forward video from at most this many publishers to each subscriber,
+    /// pinned ones then the latest speakers. 0 forwards all, default from
+    /// `stream_info.last_n`.
+    #[serde(default)]
+    last_n: Option<u32>,
+    /// Record every stream of the room from the moment it is published,
+    /// default from `recorder.auto_start`.
+    #[serde(default)]
+    record: Option<bool>,
 }
 
 async fn create_room(
@@ -36,10 +50,41 @@ async fn create_room(
 ) -> Result<Response> {
     debug!("HTTP GET /room/create");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_create_room(state.config, request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/rooms",
+    tag = "room",
+    request_body = inline(RequestJson),
+    responses(
+        (status = 200, description = "Room created", body = RoomInfoJson),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 503, description = "Server is draining", body = ErrorJson),
+    )
+)]
+async fn create_room_v2(
+    State(state): State<AppState>,
+    JsonBody(request): JsonBody<RequestJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms");
+
+    do_create_room(state.config, request).await
+}
+
+async fn do_create_room(config: Config, request: RequestJson) -> Result<Response> {
+    health::accepting()?;
+
+    let mut config = config;
+    if let Some(last_n) = request.last_n {
+        config.stream_info.last_n = last_n;
+    }
+    if let Some(record) = request.record {
+        config.recorder.auto_start = record;
+    }
 
     let mut rooms = ROOMS.lock().await;
 
@@ -54,7 +99,7 @@ async fn create_room(
         request.shared_key,
         request.master_key,
         request.description,
-        state.config,
+        config,
     );
 
     let body = serde_json::to_string(&room.info()).unwrap().to_string();
@@ -1,7 +1,7 @@
 use axum::body::Body;
 use axum::extract::Path;
 use axum::response::Response;
-use axum::routing::post;
+use axum::routing::{delete, post};
 use axum::Router;
 use http::BodyUtil;
 use http::StatusCode;
@@ -9,7 +9,9 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::debug;
+use utoipa::ToSchema;
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -22,33 +24,64 @@ struct RequestJson {
     master_key: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    master_key: String,
+}
+
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/delete/:base64/", post(delete_room))
+    Router::new()
+        .route("/room/delete/:base64/", post(delete_room))
+        .merge(Router::new().route("/v2/rooms/:room_id", delete(delete_room_v2)))
 }
 
 async fn delete_room(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /room/delete");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let mut rooms = ROOMS.lock().await;
+    do_delete_room(request).await
+}
 
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Room deleted", body = String),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 403, description = "Key does not match", body = ErrorJson),
+        (status = 404, description = "Room not found", body = ErrorJson),
+    )
+)]
+async fn delete_room_v2(
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP DELETE /v2/rooms/{}", room_id);
+
+    do_delete_room(RequestJson {
+        id: room_id,
+        master_key: body.master_key,
+    })
+    .await
+}
+
+async fn do_delete_room(request: RequestJson) -> Result<Response> {
+    let mut rooms = ROOMS.lock().await;
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
+    let room: &mut Room = rooms
+        .get_mut(&request.id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            request.id
+        )))?;
     if !room.auth_master_key(request.master_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::bad_key("master key does not match"));
     }
 
     room.all_user_delete().await?;
@@ -9,7 +9,9 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::debug;
+use utoipa::ToSchema;
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -17,7 +19,9 @@ use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/exit/:base64/", post(room_exit))
+    Router::new()
+        .route("/room/exit/:base64/", post(room_exit))
+        .merge(Router::new().route("/v2/rooms/:room_id/exit", post(room_exit_v2)))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,43 +32,77 @@ struct RequestJson {
     shared_key: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+}
+
 async fn room_exit(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /room/exit");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let mut rooms = ROOMS.lock().await;
+    do_room_exit(request).await
+}
 
-    if !rooms.contains_key(&request.room_id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+#[utoipa::path(
+    post,
+    path = "/v2/rooms/{room_id}/exit",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Exited", body = String),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room or user not found", body = ErrorJson),
+    )
+)]
+async fn room_exit_v2(
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms/{}/exit", room_id);
+
+    do_room_exit(RequestJson {
+        room_id,
+        user_id: body.user_id,
+        token: body.token,
+        shared_key: body.shared_key,
+    })
+    .await
+}
 
-    let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
+async fn do_room_exit(request: RequestJson) -> Result<Response> {
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = rooms
+        .get_mut(&request.room_id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            request.room_id
+        )))?;
     if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::bad_key("shared key does not match"));
     }
 
-    if room
-        .user_delete(request.user_id.clone(), request.token.clone(), true)
+    if !room
+        .user_delete(request.user_id, request.token, true)
         .await?
     {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::SUCCEED),
-            StatusCode::OK,
-        ));
-    } else {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::user_not_found(format!(
+            "user {} not found",
+            request.user_id
+        )));
     }
+
+    return Ok(http::create_response(
+        Body::from(BodyUtil::SUCCEED),
+        StatusCode::OK,
+    ));
 }
@@ -1,15 +1,18 @@
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::Response;
 use axum::routing::post;
 use axum::Router;
-use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::debug;
+use utoipa::ToSchema;
 
+use crate::config::{Config, IceServer};
+use crate::error::AppError;
+use crate::health;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -17,7 +20,9 @@ use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/room/join/:base64/", post(room_join))
+    Router::new()
+        .route("/room/join/:base64/", post(room_join))
+        .merge(Router::new().route("/v2/rooms/:room_id/join", post(room_join_v2)))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,59 +33,104 @@ struct RequestJson {
     master_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    name: String,
+    shared_key: String,
+    #[serde(default)]
+    master_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ResponseJson {
     id: i32,
     token: u32,
+    /// ICE servers to use for the WHIP and WHEP sessions of this user, including
+    /// the embedded TURN server with time-limited credentials when enabled.
+    ice_servers: Vec<IceServer>,
 }
 
-async fn room_join(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+async fn room_join(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Result<Response> {
     debug!("HTTP GET /room/join");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let mut rooms = ROOMS.lock().await;
+    do_room_join(state.config, request).await
+}
 
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+#[utoipa::path(
+    post,
+    path = "/v2/rooms/{room_id}/join",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Joined, returns the user id, token and ICE servers. The ICE servers are also sent as `Link: <url>; rel=\"ice-server\"` headers", body = inline(ResponseJson)),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 403, description = "Key does not match", body = ErrorJson),
+        (status = 404, description = "Room not found", body = ErrorJson),
+        (status = 409, description = "Room is full or host has already joined", body = ErrorJson),
+        (status = 503, description = "Server is draining", body = ErrorJson),
+    )
+)]
+async fn room_join_v2(
+    State(state): State<AppState>,
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms/{}/join", room_id);
+
+    do_room_join(
+        state.config,
+        RequestJson {
+            name: body.name,
+            id: room_id,
+            shared_key: body.shared_key,
+            master_key: body.master_key,
+        },
+    )
+    .await
+}
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
+async fn do_room_join(config: Config, request: RequestJson) -> Result<Response> {
+    health::accepting()?;
+
+    let mut rooms = ROOMS.lock().await;
+
+    let room: &mut Room = rooms
+        .get_mut(&request.id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            request.id
+        )))?;
     if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::bad_key("shared key does not match"));
     }
 
     let mut user_id = i32::default();
     let mut token = u32::default();
-    if !room
-        .join(
-            request.name.clone(),
-            request.master_key.clone(),
-            &mut user_id,
-            &mut token,
-        )
-        .await?
-    {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::REJECTED),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    room.join(
+        request.name.clone(),
+        request.master_key.clone(),
+        &mut user_id,
+        &mut token,
+    )
+    .await?;
 
     let response = ResponseJson {
         id: user_id.clone(),
         token: token.clone(),
+        ice_servers: ice_servers(&config, request.id, user_id),
     };
     let body = serde_json::to_string(&response).unwrap().to_string();
 
-    return Ok(http::create_response(Body::from(body), StatusCode::OK));
+    return Ok(with_ice_server_links(
+        http::create_response(Body::from(body), StatusCode::OK),
+        &response.ice_servers,
+    ));
 }
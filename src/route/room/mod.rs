@@ -1,12 +1,14 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 pub mod create;
 pub mod delete;
 pub mod exit;
 pub mod join;
+pub mod pins;
 pub mod room;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RoomInfoJson {
     pub id: i32,
     pub name: String,
@@ -0,0 +1,98 @@
+use axum::body::Body;
+use axum::extract::Path;
+use axum::response::Response;
+use axum::routing::{post, put};
+use axum::Router;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::http;
+use crate::result::Result;
+use crate::route::*;
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route("/room/pins/:base64/", post(pins))
+        .merge(Router::new().route("/v2/rooms/:room_id/pins", put(pins_v2)))
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestJson {
+    room_id: i32,
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+    pins: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+    /// User ids of the publishers to receive video from first, in order.
+    /// Empty to follow the speakers only.
+    pins: Vec<u32>,
+}
+
+async fn pins(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /room/pins");
+
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_pins(request).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/pins",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Pins set, used when the room forwards video from the last N publishers"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room or user not found", body = ErrorJson),
+    )
+)]
+async fn pins_v2(
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP PUT /v2/rooms/{}/pins", room_id);
+
+    do_pins(RequestJson {
+        room_id,
+        user_id: body.user_id,
+        token: body.token,
+        shared_key: body.shared_key,
+        pins: body.pins,
+    })
+    .await
+}
+
+async fn do_pins(request: RequestJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.read().await;
+    forwarder
+        .set_pins(request.user_id as u32, request.pins)
+        .await;
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
@@ -1,15 +1,16 @@
 use axum::body::Body;
 use axum::extract::Path;
 use axum::response::Response;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::Router;
-use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::debug;
+use utoipa::ToSchema;
 
+use crate::error::AppError;
 use crate::http;
 use crate::result::Result;
 use crate::room::Room;
@@ -21,6 +22,8 @@ pub fn route() -> Router<AppState> {
     Router::new()
         .route("/room", post(room))
         .merge(Router::new().route("/room/:base64/", post(room_specific)))
+        .merge(Router::new().route("/v2/rooms", get(room)))
+        .merge(Router::new().route("/v2/rooms/:room_id/info", post(room_specific_v2)))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -29,11 +32,24 @@ struct RequestJson {
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ResponseJson {
     infos: Vec<RoomInfoJson>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/v2/rooms",
+    tag = "room",
+    responses(
+        (status = 200, description = "Public rooms", body = inline(ResponseJson)),
+    )
+)]
 async fn room() -> Result<Response> {
     debug!("HTTP GET /room");
 
@@ -56,26 +72,50 @@ async fn room() -> Result<Response> {
 async fn room_specific(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     println!("HTTP GET /room");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let mut rooms = ROOMS.lock().await;
+    do_room_specific(request).await
+}
 
-    if !rooms.contains_key(&request.id) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::ROOM_ID_NOTFOUND),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+#[utoipa::path(
+    post,
+    path = "/v2/rooms/{room_id}/info",
+    tag = "room",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Room info", body = inline(ResponseJson)),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 403, description = "Key does not match", body = ErrorJson),
+        (status = 404, description = "Room not found", body = ErrorJson),
+    )
+)]
+async fn room_specific_v2(
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms/{}/info", room_id);
+
+    do_room_specific(RequestJson {
+        id: room_id,
+        shared_key: body.shared_key,
+    })
+    .await
+}
+
+async fn do_room_specific(request: RequestJson) -> Result<Response> {
+    let mut rooms = ROOMS.lock().await;
 
-    let room: &mut Room = rooms.get_mut(&request.id).unwrap();
+    let room: &mut Room = rooms
+        .get_mut(&request.id)
+        .ok_or(AppError::room_not_found(format!(
+            "room {} not found",
+            request.id
+        )))?;
     if !room.auth_shared_key(request.shared_key.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_PASSWORD),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
+        return Err(AppError::bad_key("shared key does not match"));
     }
 
     let mut response = ResponseJson { infos: Vec::new() };
@@ -1,15 +1,14 @@
-use axum::body::Body;
 use axum::extract::Path;
 use axum::response::Response;
 use axum::routing::post;
 use axum::Router;
 use http::response::StreamInfo;
-use http::BodyUtil;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tracing::debug;
+use utoipa::ToSchema;
 
 use crate::http;
 use crate::result::Result;
@@ -24,36 +23,66 @@ struct RequestJson {
     shared_key: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+}
+
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/infos/:base64/", post(infos))
+    Router::new()
+        .route("/stream/infos/:base64/", post(infos))
+        .merge(Router::new().route("/v2/rooms/:room_id/streams/infos", post(infos_v2)))
 }
 
 async fn infos(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/infos");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_infos(request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/rooms/{room_id}/streams/infos",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Streams owned by the user", body = [StreamInfo]),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room or user not found", body = ErrorJson),
+    )
+)]
+async fn infos_v2(
+    PathParam(room_id): PathParam<i32>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms/{}/streams/infos", room_id);
 
-    let (room, client) = match auth_user(
-        request.room_id.clone(),
+    do_infos(RequestJson {
+        room_id,
+        user_id: body.user_id,
+        token: body.token,
+        shared_key: body.shared_key,
+    })
+    .await
+}
+
+async fn do_infos(request: RequestJson) -> Result<Response> {
+    let (room, client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
-
-    if !client.check_token(request.token.clone()) {
-        return Ok(http::create_response(
-            Body::from(BodyUtil::INVILED_TOKEN),
-            StatusCode::NOT_ACCEPTABLE,
-        ));
-    }
+    .await?;
 
     let streams = client.get_streams().await;
     let forwarder = room.forwarder();
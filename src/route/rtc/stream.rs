@@ -2,19 +2,23 @@ use axum::body::Body;
 use axum::extract::Path;
 use axum::response::IntoResponse;
 use axum::response::Response;
-use axum::routing::post;
+use axum::routing::{delete, post, put};
 use axum::Json;
 use axum::Router;
 use http::StatusCode;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use utoipa::ToSchema;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 use tracing::debug;
 
 use crate::constant;
 use crate::error::AppError;
+use crate::event::ServerEvent;
 use crate::forward::rtc::message::Layer;
+use crate::health;
 use crate::http;
 use crate::result::Result;
 use crate::route::*;
@@ -26,6 +30,40 @@ pub fn route() -> Router<AppState> {
         .merge(Router::new().route("/stream/get_layer/:base64/", post(get_layer)))
         .merge(Router::new().route("/stream/select_layer/:base64/", post(select_layer)))
         .merge(Router::new().route("/stream/un_select_layer/:base64/", post(un_select_layer)))
+        .merge(Router::new().route("/stream/close_session/:base64/", post(close_session)))
+        .merge(Router::new().route("/stream/change_resource/:base64/", post(change_resource)))
+        .merge(Router::new().route("/stream/start_record/:base64/", post(start_record)))
+        .merge(Router::new().route("/stream/stop_record/:base64/", post(stop_record)))
+        .merge(Router::new().route("/stream/start_playback/:base64/", post(start_playback)))
+        .merge(Router::new().route("/stream/stop_playback/:base64/", post(stop_playback)))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream",
+            put(create_v2).delete(destroy_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/layers",
+            post(get_layer_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/layer",
+            put(select_layer_v2).delete(un_select_layer_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/sessions/:session",
+            delete(close_session_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/sessions/:session/resource",
+            put(change_resource_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/recording",
+            put(start_record_v2).delete(stop_record_v2),
+        ))
+        .merge(Router::new().route(
+            "/v2/rooms/:room_id/streams/:stream/playback",
+            put(start_playback_v2).delete(stop_playback_v2),
+        ))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -48,39 +86,212 @@ struct SelectLayerJson {
     shared_key: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct SessionJson {
+    room_id: i32,
+    user_id: i32,
+    token: u32,
+    stream: String,
+    session: String,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChangeResourceJson {
+    room_id: i32,
+    user_id: i32,
+    token: u32,
+    stream: String,
+    session: String,
+    kind: String,
+    enabled: bool,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlaybackJson {
+    room_id: i32,
+    user_id: i32,
+    token: u32,
+    stream: String,
+    shared_key: String,
+    files: Vec<String>,
+    #[serde(default, rename = "loop")]
+    looping: bool,
+    #[serde(default)]
+    publisher_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct BodyJson {
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct SelectLayerBodyJson {
+    user_id: i32,
+    token: u32,
+    session: String,
+    #[serde(default)]
+    layer: String,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ChangeResourceBodyJson {
+    user_id: i32,
+    token: u32,
+    /// `audio` or `video`
+    kind: String,
+    enabled: bool,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PlaybackBodyJson {
+    user_id: i32,
+    token: u32,
+    shared_key: String,
+    /// Relative to the recorder directory, one track per file: `.ivf` (VP8,
+    /// VP9, AV1) or `.ogg` (Opus), such as the files of a recording.
+    files: Vec<String>,
+    /// Start over when every file has ended, instead of leaving.
+    #[serde(default, rename = "loop")]
+    looping: bool,
+    /// The user the stream is published as, the caller by default.
+    #[serde(default)]
+    publisher_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PlaybackResponseJson {
+    /// The publish session, closed like any other session.
+    session: String,
+}
+
+impl BodyJson {
+    fn into_request(self, room_id: i32, stream: String) -> RequestJson {
+        RequestJson {
+            room_id,
+            user_id: self.user_id,
+            token: self.token,
+            stream,
+            shared_key: self.shared_key,
+        }
+    }
+
+    fn into_session_request(self, room_id: i32, stream: String, session: String) -> SessionJson {
+        SessionJson {
+            room_id,
+            user_id: self.user_id,
+            token: self.token,
+            stream,
+            session,
+            shared_key: self.shared_key,
+        }
+    }
+}
+
+impl SelectLayerBodyJson {
+    fn into_request(self, room_id: i32, stream: String) -> SelectLayerJson {
+        SelectLayerJson {
+            room_id,
+            user_id: self.user_id,
+            token: self.token,
+            stream,
+            session: self.session,
+            layer: self.layer,
+            shared_key: self.shared_key,
+        }
+    }
+}
+
+impl PlaybackBodyJson {
+    fn into_request(self, room_id: i32, stream: String) -> PlaybackJson {
+        PlaybackJson {
+            room_id,
+            user_id: self.user_id,
+            token: self.token,
+            stream,
+            shared_key: self.shared_key,
+            files: self.files,
+            looping: self.looping,
+            publisher_id: self.publisher_id,
+        }
+    }
+}
+
+impl ChangeResourceBodyJson {
+    fn into_request(self, room_id: i32, stream: String, session: String) -> ChangeResourceJson {
+        ChangeResourceJson {
+            room_id,
+            user_id: self.user_id,
+            token: self.token,
+            stream,
+            session,
+            kind: self.kind,
+            enabled: self.enabled,
+            shared_key: self.shared_key,
+        }
+    }
+}
+
 async fn create(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/create");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_create(request).await
+}
 
-    let (room, client) = match auth_user(
-        request.room_id.clone(),
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/streams/{stream}",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Stream created"),
+        (status = 400, description = "Malformed request, or a reserved stream name such as `infos`", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room or user not found", body = ErrorJson),
+        (status = 409, description = "Stream already exists", body = ErrorJson),
+        (status = 503, description = "Server is draining", body = ErrorJson),
+    )
+)]
+async fn create_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP PUT /v2/rooms/{}/streams/{}", room_id, stream);
+
+    do_create(body.into_request(room_id, stream)).await
+}
+
+async fn do_create(request: RequestJson) -> Result<Response> {
+    health::accepting()?;
+
+    let (room, client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
-    let _ = match forwarder.stream_create(request.stream.clone()).await {
-        Ok(_) => {
-            let mut client = client;
-            client.add_stream(request.stream.clone()).await?;
-
-            Ok(Response::builder()
-                .status(StatusCode::NO_CONTENT)
-                .body("".to_string())?)
-        }
-        Err(e) => Err(AppError::StreamAlreadyExists(e.to_string())),
-    };
+    forwarder.stream_create(request.stream.clone()).await?;
+    drop(forwarder);
+
+    let mut client = client;
+    client.add_stream(request.stream.clone()).await?;
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
@@ -88,36 +299,53 @@ async fn create(Path(params): Path<HashMap<String, String>>) -> Result<Response>
 async fn destroy(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/destroy");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_destroy(request).await
+}
 
-    let (room, client) = match auth_user(
-        request.room_id.clone(),
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}/streams/{stream}",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Stream destroyed"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room, user or stream not found", body = ErrorJson),
+    )
+)]
+async fn destroy_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP DELETE /v2/rooms/{}/streams/{}", room_id, stream);
+
+    do_destroy(body.into_request(room_id, stream)).await
+}
+
+async fn do_destroy(request: RequestJson) -> Result<Response> {
+    let (room, client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
-    let _ = match forwarder.stream_delete(request.stream.clone()).await {
-        Ok(_) => {
-            let mut client = client;
-            client.remove_stream(request.stream.clone()).await?;
-
-            Ok(Response::builder()
-                .status(StatusCode::NO_CONTENT)
-                .body("".to_string())?)
-        }
-        Err(e) => Err(AppError::StreamAlreadyExists(e.to_string())),
-    };
+    forwarder.stream_delete(request.stream.clone()).await?;
+    drop(forwarder);
+
+    let mut client = client;
+    client.remove_stream(request.stream.clone()).await?;
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
@@ -125,22 +353,45 @@ async fn destroy(Path(params): Path<HashMap<String, String>>) -> Result<Response
 async fn get_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/get_layer");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let (room, _client) = match auth_user(
-        request.room_id.clone(),
+    do_get_layer(request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/v2/rooms/{room_id}/streams/{stream}/layers",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Simulcast layers of the stream", body = [Layer]),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room, user or stream not found, or the stream is not simulcast (`LAYER_NOT_FOUND`)", body = ErrorJson),
+    )
+)]
+async fn get_layer_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!("HTTP POST /v2/rooms/{}/streams/{}/layers", room_id, stream);
+
+    do_get_layer(body.into_request(room_id, stream)).await
+}
+
+async fn do_get_layer(request: RequestJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -157,22 +408,45 @@ async fn get_layer(Path(params): Path<HashMap<String, String>>) -> Result<Respon
 async fn select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/select_layer");
 
-    let request: SelectLayerJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: SelectLayerJson = parse_base64_into_json(&params)?;
 
-    let (room, _client) = match auth_user(
-        request.room_id.clone(),
+    do_select_layer(request).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/streams/{stream}/layer",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(SelectLayerBodyJson),
+    responses(
+        (status = 200, description = "Layer selected"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room, user, stream or session not found, or the stream is not simulcast", body = ErrorJson),
+    )
+)]
+async fn select_layer_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<SelectLayerBodyJson>,
+) -> Result<Response> {
+    debug!("HTTP PUT /v2/rooms/{}/streams/{}/layer", room_id, stream);
+
+    do_select_layer(body.into_request(room_id, stream)).await
+}
+
+async fn do_select_layer(request: SelectLayerJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -192,22 +466,45 @@ async fn select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Res
 async fn un_select_layer(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
     debug!("HTTP GET /stream/un_select_layer");
 
-    let request: SelectLayerJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: SelectLayerJson = parse_base64_into_json(&params)?;
 
-    let (room, _client) = match auth_user(
-        request.room_id.clone(),
+    do_un_select_layer(request).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}/streams/{stream}/layer",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(SelectLayerBodyJson),
+    responses(
+        (status = 200, description = "Layer selection reset"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match", body = ErrorJson),
+        (status = 404, description = "Room, user, stream or session not found, or the stream is not simulcast", body = ErrorJson),
+    )
+)]
+async fn un_select_layer_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<SelectLayerBodyJson>,
+) -> Result<Response> {
+    debug!("HTTP DELETE /v2/rooms/{}/streams/{}/layer", room_id, stream);
+
+    do_un_select_layer(body.into_request(room_id, stream)).await
+}
+
+async fn do_un_select_layer(request: SelectLayerJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
 
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
@@ -223,3 +520,393 @@ async fn un_select_layer(Path(params): Path<HashMap<String, String>>) -> Result<
 
     return Ok(http::create_response(Body::from(""), StatusCode::OK));
 }
+
+async fn close_session(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/close_session");
+
+    let request: SessionJson = parse_base64_into_json(&params)?;
+
+    do_close_session(request).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}/streams/{stream}/sessions/{session}",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+        ("session" = String, Path, description = "Session id returned in `SignalingJson.session`"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Session closed"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the session belongs to another user", body = ErrorJson),
+        (status = 404, description = "Room, user, stream or session not found", body = ErrorJson),
+    )
+)]
+async fn close_session_v2(
+    PathParam((room_id, stream, session)): PathParam<(i32, String, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!(
+        "HTTP DELETE /v2/rooms/{}/streams/{}/sessions/{}",
+        room_id, stream, session
+    );
+
+    do_close_session(body.into_session_request(room_id, stream, session)).await
+}
+
+// Users may close their own sessions, and the host may close any session.
+async fn do_close_session(request: SessionJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    let owner = forwarder
+        .session_owner(request.stream.clone(), request.session.clone())
+        .await?;
+    if owner != request.user_id as u32 && !room.is_host(request.user_id) {
+        return Err(AppError::forbidden("session belongs to another user"));
+    }
+    forwarder
+        .remove_peer(request.stream.clone(), request.session.clone())
+        .await?;
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
+
+async fn change_resource(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/change_resource");
+
+    let request: ChangeResourceJson = parse_base64_into_json(&params)?;
+
+    do_change_resource(request).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/streams/{stream}/sessions/{session}/resource",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+        ("session" = String, Path, description = "Session id returned in `SignalingJson.session`"),
+    ),
+    request_body = inline(ChangeResourceBodyJson),
+    responses(
+        (status = 200, description = "Tracks of the kind enabled or disabled. For the publish session, for every subscriber"),
+        (status = 400, description = "Malformed request, or unknown kind", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the session belongs to another user", body = ErrorJson),
+        (status = 404, description = "Room, user, stream or session not found", body = ErrorJson),
+    )
+)]
+async fn change_resource_v2(
+    PathParam((room_id, stream, session)): PathParam<(i32, String, String)>,
+    JsonBody(body): JsonBody<ChangeResourceBodyJson>,
+) -> Result<Response> {
+    debug!(
+        "HTTP PUT /v2/rooms/{}/streams/{}/sessions/{}/resource",
+        room_id, stream, session
+    );
+
+    do_change_resource(body.into_request(room_id, stream, session)).await
+}
+
+// A subscriber pauses its own session, a publisher mutes its tracks for
+// everyone and the room is told. The host may do both for any session.
+async fn do_change_resource(request: ChangeResourceJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+
+    let kind = RTPCodecType::from(request.kind.as_str());
+    if kind == RTPCodecType::Unspecified {
+        return Err(AppError::bad_request_details(
+            "kind must be audio or video",
+            "kind",
+        ));
+    }
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    let owner = forwarder
+        .session_owner(request.stream.clone(), request.session.clone())
+        .await?;
+    if owner != request.user_id as u32 && !room.is_host(request.user_id) {
+        return Err(AppError::forbidden("session belongs to another user"));
+    }
+    let publish = forwarder
+        .change_resource(
+            request.stream.clone(),
+            request.session.clone(),
+            kind,
+            request.enabled,
+        )
+        .await?;
+    drop(forwarder);
+
+    if publish {
+        ServerEvent::ResourceChanged {
+            user_id: owner,
+            stream: request.stream.clone(),
+            kind: kind.to_string(),
+            enabled: request.enabled,
+        }
+        .send_to_room(&room)
+        .await;
+    }
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
+
+async fn start_record(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/start_record");
+
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_record(request, true).await
+}
+
+async fn stop_record(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/stop_record");
+
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_record(request, false).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/streams/{stream}/recording",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Recording started, until stopped or the publisher leaves"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the user is neither the host nor the publisher", body = ErrorJson),
+        (status = 404, description = "Room, user or stream not found", body = ErrorJson),
+        (status = 409, description = "Stream is already recording", body = ErrorJson),
+        (status = 503, description = "Stream is not published yet", body = ErrorJson),
+    )
+)]
+async fn start_record_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!(
+        "HTTP PUT /v2/rooms/{}/streams/{}/recording",
+        room_id, stream
+    );
+
+    do_record(body.into_request(room_id, stream), true).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}/streams/{stream}/recording",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Recording stopped, its files closed"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the user is neither the host nor the publisher", body = ErrorJson),
+        (status = 404, description = "Room, user or stream not found, or the stream is not recording", body = ErrorJson),
+        (status = 503, description = "Stream is not published yet", body = ErrorJson),
+    )
+)]
+async fn stop_record_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!(
+        "HTTP DELETE /v2/rooms/{}/streams/{}/recording",
+        room_id, stream
+    );
+
+    do_record(body.into_request(room_id, stream), false).await
+}
+
+// The host records any stream, a publisher its own.
+async fn do_record(request: RequestJson, start: bool) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    let Some(publisher) = forwarder.publish_user(request.stream.clone()).await? else {
+        return Err(AppError::publish_not_ready("stream is not published yet"));
+    };
+    if publisher != request.user_id as u32 && !room.is_host(request.user_id) {
+        return Err(AppError::forbidden("stream belongs to another user"));
+    }
+    if start {
+        forwarder.start_record(request.stream.clone()).await?;
+    } else {
+        forwarder.stop_record(request.stream.clone()).await?;
+    }
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
+
+async fn start_playback(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/start_playback");
+
+    let request: PlaybackJson = parse_base64_into_json(&params)?;
+
+    do_start_playback(request).await
+}
+
+async fn stop_playback(Path(params): Path<HashMap<String, String>>) -> Result<Response> {
+    debug!("HTTP GET /stream/stop_playback");
+
+    let request: RequestJson = parse_base64_into_json(&params)?;
+
+    do_stop_playback(request).await
+}
+
+#[utoipa::path(
+    put,
+    path = "/v2/rooms/{room_id}/streams/{stream}/playback",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(PlaybackBodyJson),
+    responses(
+        (status = 200, description = "Files published at real-time pace, subscribed to like any stream", body = inline(PlaybackResponseJson)),
+        (status = 400, description = "Malformed request, a reserved stream name, no file, or a file outside the recorder directory, not readable or of a format not played", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the user is not the host", body = ErrorJson),
+        (status = 404, description = "Room or user not found, or the publisher is not in the room", body = ErrorJson),
+        (status = 409, description = "Stream is already published", body = ErrorJson),
+        (status = 503, description = "Server is draining", body = ErrorJson),
+    )
+)]
+async fn start_playback_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<PlaybackBodyJson>,
+) -> Result<Response> {
+    debug!("HTTP PUT /v2/rooms/{}/streams/{}/playback", room_id, stream);
+
+    do_start_playback(body.into_request(room_id, stream)).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/v2/rooms/{room_id}/streams/{stream}/playback",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    request_body = inline(BodyJson),
+    responses(
+        (status = 200, description = "Playback stopped, the file publisher leaves"),
+        (status = 400, description = "Malformed request", body = ErrorJson),
+        (status = 401, description = "Token does not match", body = ErrorJson),
+        (status = 403, description = "Shared key does not match, or the user is not the host", body = ErrorJson),
+        (status = 404, description = "Room, user or stream not found, or the stream is not played from files", body = ErrorJson),
+    )
+)]
+async fn stop_playback_v2(
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    JsonBody(body): JsonBody<BodyJson>,
+) -> Result<Response> {
+    debug!(
+        "HTTP DELETE /v2/rooms/{}/streams/{}/playback",
+        room_id, stream
+    );
+
+    do_stop_playback(body.into_request(room_id, stream)).await
+}
+
+// Only the host plays files from the server's disk.
+async fn do_start_playback(request: PlaybackJson) -> Result<Response> {
+    health::accepting()?;
+    let (room, client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+    if !room.is_host(request.user_id) {
+        return Err(AppError::forbidden("only the host plays files"));
+    }
+    let publisher_id = request.publisher_id.unwrap_or(request.user_id);
+    if !room.client_map().read().await.contains_key(&publisher_id) {
+        return Err(AppError::user_not_found(format!(
+            "publisher {} is not in the room",
+            publisher_id
+        )));
+    }
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    let session = forwarder
+        .file_publish(
+            request.stream.clone(),
+            publisher_id as u32,
+            request.files,
+            request.looping,
+        )
+        .await?;
+    drop(forwarder);
+
+    // registered on the host, the stream goes when the host leaves; it may be
+    // registered already when the host created it before playing
+    let mut client = client;
+    let _ = client.add_stream(request.stream.clone()).await;
+
+    let body = serde_json::to_string(&PlaybackResponseJson { session }).unwrap();
+    Ok(http::create_response(Body::from(body), StatusCode::OK))
+}
+
+async fn do_stop_playback(request: RequestJson) -> Result<Response> {
+    let (room, _client) = auth_user(
+        request.room_id,
+        request.shared_key.clone(),
+        request.user_id,
+        request.token,
+    )
+    .await?;
+    if !room.is_host(request.user_id) {
+        return Err(AppError::forbidden("only the host plays files"));
+    }
+
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+    forwarder.stop_playback(request.stream.clone()).await?;
+
+    Ok(http::create_response(Body::from(""), StatusCode::OK))
+}
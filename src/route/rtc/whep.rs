@@ -1,6 +1,6 @@
 use axum::extract::ws;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
@@ -9,20 +9,32 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, RwLock};
+use utoipa::ToSchema;
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 use tracing::{debug, error};
 
+use crate::config::IceServer;
+use crate::error::AppError;
+use crate::health;
+use crate::http::request::ChangeResource;
 use crate::result::Result;
 use crate::room::Room;
 use crate::route::*;
 use crate::ROOMS;
 
+// how often a connected session's peer is checked, to close its socket once gone
+const PEER_STATE_INTERVAL: Duration = Duration::from_secs(1);
+
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/whep/:base64/", get(whep))
+    Router::new()
+        .route("/stream/whep/:base64/", get(whep))
+        .merge(Router::new().route("/v2/rooms/:room_id/streams/:stream/whep", get(whep_v2)))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -35,40 +47,130 @@ struct RequestJson {
     shared_key: String,
 }
 
+#[derive(Serialize, Deserialize, ToSchema)]
+#[schema(as = WhepBodyJson)]
+pub(crate) struct BodyJson {
+    user_id: i32,
+    token: u32,
+    offer: String,
+    shared_key: String,
+}
+
 #[derive(Serialize, Deserialize)]
 struct SignalingJson {
+    #[serde(default)]
     is_candidate: bool,
+    #[serde(default)]
     sdp: String,
+    #[serde(default)]
     session: String,
+    #[serde(default)]
     candidate: String,
+    /// Sent with the answer only: the ICE servers to use for this session.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ice_servers: Vec<IceServer>,
+    /// Sent by the subscriber, also once connected: pauses or resumes a kind.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    change_resource: Option<ChangeResource>,
 }
 
 async fn whep(
+    State(state): State<AppState>,
     Path(params): Path<HashMap<String, String>>,
     ws: WebSocketUpgrade,
 ) -> Result<Response> {
     debug!("HTTP GET /stream/whep");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let (room, _client) = match auth_user(
-        request.room_id.clone(),
+    let (room, _client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
-    {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
-    };
+    .await?;
+
+    prepare_virtual_publish(&room, request.stream.clone(), request.user_id).await?;
+
+    let ice_servers = ice_servers(&state.config, request.room_id, request.user_id);
+    let links = ice_servers.clone();
+    let response =
+        ws.on_upgrade(move |socket: WebSocket| whep_session(socket, request, ice_servers));
+    return Ok(with_ice_server_links(response, &links));
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/rooms/{room_id}/streams/{stream}/whep",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket upgrade. The first message is a `WhepBodyJson`, then `SignalingJson` messages are exchanged. The answer carries the `ice_servers` of the session. The server closes the socket once the session's peer is closed or failed."),
+    )
+)]
+async fn whep_v2(
+    State(state): State<AppState>,
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    ws: WebSocketUpgrade,
+) -> Result<Response> {
+    debug!("HTTP GET /v2/rooms/{}/streams/{}/whep", room_id, stream);
+
+    Ok(ws.on_upgrade(move |mut socket: WebSocket| async move {
+        let body: BodyJson = match recv_json(&mut socket).await {
+            Ok(body) => body,
+            Err(err) => {
+                send_error(&mut socket, err).await;
+                return;
+            }
+        };
+        let request = RequestJson {
+            room_id,
+            user_id: body.user_id,
+            token: body.token,
+            stream,
+            offer: body.offer,
+            shared_key: body.shared_key,
+        };
 
+        let room = match auth_user(
+            request.room_id,
+            request.shared_key.clone(),
+            request.user_id,
+            request.token,
+        )
+        .await
+        {
+            Ok((room, _client)) => room,
+            Err(err) => {
+                send_error(&mut socket, err).await;
+                return;
+            }
+        };
+
+        if let Err(err) =
+            prepare_virtual_publish(&room, request.stream.clone(), request.user_id).await
+        {
+            error!("[vhost] virtual publish err: {:?}", err);
+            send_error(&mut socket, err).await;
+            return;
+        }
+
+        let ice_servers = ice_servers(&state.config, request.room_id, request.user_id);
+        whep_session(socket, request, ice_servers).await
+    }))
+}
+
+// When nobody publishes the stream yet, a virtual publisher is connected to it so
+// that subscribers can still exchange data channel messages.
+async fn prepare_virtual_publish(room: &Room, stream: String, user_id: i32) -> Result<()> {
+    health::accepting()?;
     let forwarder = room.forwarder();
     let forwarder = forwarder.write().await;
-    if !forwarder.is_stream_exists(request.stream.clone()).await? {
+    if !forwarder.is_stream_exists(stream.clone()).await? {
         let (tx0, mut rx) = mpsc::channel::<(u8, String)>(32);
         let tx1 = tx0.clone();
         let caches0: Arc<RwLock<Vec<String>>> = Default::default();
@@ -96,7 +198,7 @@ async fn whep(
         });
         let (peer0, sdp, _session) = forwarder
             .virtual_publish(
-                request.stream.clone(),
+                stream.clone(),
                 Box::new(move |candidate: Option<RTCIceCandidate>| {
                     let candidate = candidate.clone();
                     let tx0 = tx0.clone();
@@ -110,10 +212,10 @@ async fn whep(
                 }),
             )
             .await?;
-        let id = request.user_id as u32;
+        let id = user_id as u32;
         let (peer1, answer, _session) = forwarder
             .publish(
-                request.stream.clone(),
+                stream.clone(),
                 id,
                 sdp,
                 Box::new(move |candidate: Option<RTCIceCandidate>| {
@@ -165,134 +267,201 @@ async fn whep(
         }
     }
 
-    return Ok(ws.on_upgrade(|mut socket: WebSocket| {
-        let request = request;
-        Box::pin(async move {
-            let stream = request.stream;
-            let id = request.user_id as u32;
-            let offer = RTCSessionDescription::offer(request.offer).unwrap();
+    Ok(())
+}
 
-            let mut rooms = ROOMS.lock().await;
-            if !rooms.contains_key(&request.room_id) {
-                error!("room does not exist");
-            }
+async fn whep_session(mut socket: WebSocket, request: RequestJson, ice_servers: Vec<IceServer>) {
+    if let Err(err) = health::accepting() {
+        send_error(&mut socket, err).await;
+        return;
+    }
 
-            let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
-            let forwarder = room.forwarder();
-            let forwarder = forwarder.write().await;
-
-            drop(rooms);
-
-            while !forwarder.publish_is_ok(stream.clone()).await.unwrap() {}
-
-            let (tx0, mut rx0) = mpsc::channel::<(bool, String)>(32);
-            let tx1 = tx0.clone();
-            let (peer, answer, session) = forwarder
-                .subscribe(
-                    stream.clone(),
-                    id.clone(),
-                    offer.clone(),
-                    Box::new(move |candidate: Option<RTCIceCandidate>| {
-                        let candidate = candidate.clone();
-                        let tx0 = tx0.clone();
-                        if let Some(candidate) = candidate {
-                            return Box::pin(async move {
-                                let c = candidate.to_json().unwrap().candidate;
-                                if let Err(_err) = tx0.clone().send((false, c.clone())).await {}
-                            });
-                        }
-                        Box::pin(async {})
-                    }),
-                    Box::new(move || {
-                        let tx1 = tx1.clone();
-                        Box::pin(async move {
-                            if let Err(_err) = tx1.clone().send((true, "".to_string())).await {}
-                        })
-                    }),
-                )
-                .await
-                .unwrap();
-            drop(forwarder);
-
-            let answer = SignalingJson {
-                is_candidate: false,
-                sdp: answer.sdp,
-                session: session,
-                candidate: String::new(),
-            };
+    let stream = request.stream;
+    let id = request.user_id as u32;
+    let offer = match RTCSessionDescription::offer(request.offer) {
+        Ok(offer) => offer,
+        Err(err) => {
+            send_error(&mut socket, AppError::invalid_offer(err)).await;
+            return;
+        }
+    };
 
-            if socket
-                .send(ws::Message::Text(serde_json::to_string(&answer).unwrap()))
-                .await
-                .is_err()
-            {
-                return;
-            };
+    let mut rooms = ROOMS.lock().await;
+    if !rooms.contains_key(&request.room_id) {
+        error!("room does not exist");
+    }
 
-            let (mut sender, mut receiver) = socket.split();
+    let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
+    let room_forwarder = room.forwarder();
+    let forwarder = room_forwarder.write().await;
 
-            let mut send_task = tokio::spawn(async move {
-                while let Some((peer_connected, candidate)) = rx0.recv().await {
-                    if peer_connected {
-                        return;
-                    }
+    drop(rooms);
+
+    while !forwarder.publish_is_ok(stream.clone()).await.unwrap() {}
+
+    let (tx0, mut rx0) = mpsc::channel::<(bool, String)>(32);
+    let tx1 = tx0.clone();
+    let result = forwarder
+        .subscribe(
+            stream.clone(),
+            id.clone(),
+            offer.clone(),
+            Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let candidate = candidate.clone();
+                let tx0 = tx0.clone();
+                if let Some(candidate) = candidate {
+                    return Box::pin(async move {
+                        let c = candidate.to_json().unwrap().candidate;
+                        if let Err(_err) = tx0.clone().send((false, c.clone())).await {}
+                    });
+                }
+                Box::pin(async {})
+            }),
+            Box::new(move || {
+                let tx1 = tx1.clone();
+                Box::pin(async move {
+                    if let Err(_err) = tx1.clone().send((true, "".to_string())).await {}
+                })
+            }),
+        )
+        .await;
+    drop(forwarder);
+    let (peer, answer, session) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            send_error(&mut socket, err).await;
+            return;
+        }
+    };
+
+    let answer = SignalingJson {
+        is_candidate: false,
+        sdp: answer.sdp,
+        session: session,
+        candidate: String::new(),
+        ice_servers,
+        change_resource: None,
+    };
+
+    if socket
+        .send(ws::Message::Text(serde_json::to_string(&answer).unwrap()))
+        .await
+        .is_err()
+    {
+        return;
+    };
+
+    let (mut sender, mut receiver) = socket.split();
+    let session = answer.session;
+
+    // the socket stays open once connected, for change_resource messages,
+    // until the peer is closed or failed
+    let (state_peer, state_session) = (peer.clone(), session.clone());
+    let mut send_task = tokio::spawn(async move {
+        let mut trickling = true;
+        let mut state_tick = tokio::time::interval(PEER_STATE_INTERVAL);
+        loop {
+            tokio::select! {
+                message = rx0.recv(), if trickling => {
+                    let Some((false, candidate)) = message else {
+                        trickling = false;
+                        continue;
+                    };
                     let signaling = SignalingJson {
                         is_candidate: true,
                         sdp: String::new(),
                         session: String::new(),
                         candidate: candidate,
+                        ice_servers: vec![],
+                        change_resource: None,
                     };
                     let msg = ws::Message::Text(serde_json::to_string(&signaling).unwrap());
                     if sender.send(msg).await.is_err() {
                         return;
                     }
                 }
-            });
-
-            let mut recv_task = tokio::spawn(async move {
-                while let Some(msg) = receiver.next().await {
-                    let msg = if let Ok(msg) = msg {
-                        msg
-                    } else {
-                        return;
-                    };
+                _ = state_tick.tick() => {
+                    if matches!(
+                        state_peer.connection_state(),
+                        RTCPeerConnectionState::Closed | RTCPeerConnectionState::Failed
+                    ) {
+                        break;
+                    }
+                }
+            }
+        }
+        debug!("[{}] whep peer gone, closing the socket", state_session);
+        let _ = sender.send(ws::Message::Close(None)).await;
+    });
 
-                    match msg {
-                        ws::Message::Text(t) => {
-                            if peer.connection_state() == RTCPeerConnectionState::Connected {
-                                return;
-                            }
+    let mut recv_task = tokio::spawn(async move {
+        while let Some(msg) = receiver.next().await {
+            let msg = if let Ok(msg) = msg {
+                msg
+            } else {
+                return;
+            };
 
-                            let message = t.clone();
+            match msg {
+                ws::Message::Text(t) => {
+                    let message = t.clone();
 
-                            debug!("signaling message received: {}", message.clone());
+                    debug!("signaling message received: {}", message.clone());
 
-                            let signaling: SignalingJson =
-                                serde_json::from_str(&message.as_str()).unwrap();
+                    let signaling: SignalingJson = match serde_json::from_str(&message.as_str()) {
+                        Ok(signaling) => signaling,
+                        Err(err) => {
+                            debug!("signaling message err: {}", err);
+                            continue;
+                        }
+                    };
 
-                            let _ = peer
-                                .add_ice_candidate(RTCIceCandidateInit {
-                                    candidate: signaling.candidate.clone(),
-                                    ..Default::default()
-                                })
-                                .await;
+                    if let Some(change_resource) = signaling.change_resource {
+                        let kind = RTPCodecType::from(change_resource.kind.as_str());
+                        if kind == RTPCodecType::Unspecified {
+                            debug!("change resource unknown kind: {}", change_resource.kind);
+                            continue;
+                        }
+                        let forwarder = room_forwarder.read().await;
+                        if let Err(err) = forwarder
+                            .change_resource(
+                                stream.clone(),
+                                session.clone(),
+                                kind,
+                                change_resource.enabled,
+                            )
+                            .await
+                        {
+                            debug!("change resource err: {:?}", err);
                         }
-                        ws::Message::Binary(_b) => {}
-                        ws::Message::Ping(_vec) => {}
-                        ws::Message::Pong(_vec) => {}
-                        ws::Message::Close(_close_frame) => {}
+                        continue;
                     }
-                }
-            });
 
-            tokio::select! {
-                _rv_a = (&mut send_task) => {
-                    recv_task.abort();
-                },
-                _rv_b = (&mut recv_task) => {
-                    send_task.abort();
+                    if peer.connection_state() == RTCPeerConnectionState::Connected {
+                        continue;
+                    }
+
+                    let _ = peer
+                        .add_ice_candidate(RTCIceCandidateInit {
+                            candidate: signaling.candidate.clone(),
+                            ..Default::default()
+                        })
+                        .await;
                 }
+                ws::Message::Binary(_b) => {}
+                ws::Message::Ping(_vec) => {}
+                ws::Message::Pong(_vec) => {}
+                ws::Message::Close(_close_frame) => {}
             }
-        })
-    }));
+        }
+    });
+
+    tokio::select! {
+        _rv_a = (&mut send_task) => {
+            recv_task.abort();
+        },
+        _rv_b = (&mut recv_task) => {
+            send_task.abort();
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use axum::extract::ws;
 use axum::extract::ws::{WebSocket, WebSocketUpgrade};
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::Response;
 use axum::routing::get;
 use axum::Router;
@@ -9,19 +9,26 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
 use tokio::sync::mpsc;
+use utoipa::ToSchema;
 use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 use tracing::{debug, error};
 
+use crate::config::IceServer;
+use crate::error::AppError;
+use crate::forward::rtc::client::Client;
+use crate::health;
 use crate::result::Result;
 use crate::room::Room;
 use crate::route::*;
 use crate::ROOMS;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route("/stream/whip/:base64/", get(whip))
+    Router::new()
+        .route("/stream/whip/:base64/", get(whip))
+        .merge(Router::new().route("/v2/rooms/:room_id/streams/:stream/whip", get(whip_v2)))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,166 +41,254 @@ struct RequestJson {
     shared_key: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct SignalingJson {
+#[derive(Serialize, Deserialize, ToSchema)]
+#[schema(as = WhipBodyJson)]
+pub(crate) struct BodyJson {
+    user_id: i32,
+    token: u32,
+    offer: String,
+    shared_key: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub(crate) struct SignalingJson {
     is_candidate: bool,
     sdp: String,
     session: String,
     candidate: String,
+    /// Sent with the answer only: the ICE servers to use for this session.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ice_servers: Vec<IceServer>,
 }
 
 async fn whip(
+    State(state): State<AppState>,
     Path(params): Path<HashMap<String, String>>,
     ws: WebSocketUpgrade,
 ) -> Result<Response> {
     debug!("HTTP GET /stream/whip");
 
-    let request: RequestJson = match parse_base64_into_json(&params) {
-        Ok(request) => request,
-        Err(err_response) => return Ok(err_response),
-    };
+    let request: RequestJson = parse_base64_into_json(&params)?;
 
-    let (_room, client) = match auth_user(
-        request.room_id.clone(),
+    let (_room, client) = auth_user(
+        request.room_id,
         request.shared_key.clone(),
         request.user_id,
         request.token,
     )
-    .await
+    .await?;
+
+    let ice_servers = ice_servers(&state.config, request.room_id, request.user_id);
+    let links = ice_servers.clone();
+    let response =
+        ws.on_upgrade(move |socket: WebSocket| whip_session(socket, request, client, ice_servers));
+    return Ok(with_ice_server_links(response, &links));
+}
+
+#[utoipa::path(
+    get,
+    path = "/v2/rooms/{room_id}/streams/{stream}/whip",
+    tag = "stream",
+    params(
+        ("room_id" = i32, Path, description = "Room id"),
+        ("stream" = String, Path, description = "Stream name"),
+    ),
+    responses(
+        (status = 101, description = "WebSocket upgrade. The first message is a `WhipBodyJson`, then `SignalingJson` messages are exchanged. The answer carries the `ice_servers` of the session."),
+    )
+)]
+async fn whip_v2(
+    State(state): State<AppState>,
+    PathParam((room_id, stream)): PathParam<(i32, String)>,
+    ws: WebSocketUpgrade,
+) -> Result<Response> {
+    debug!("HTTP GET /v2/rooms/{}/streams/{}/whip", room_id, stream);
+
+    Ok(ws.on_upgrade(move |mut socket: WebSocket| async move {
+        let body: BodyJson = match recv_json(&mut socket).await {
+            Ok(body) => body,
+            Err(err) => {
+                send_error(&mut socket, err).await;
+                return;
+            }
+        };
+        let request = RequestJson {
+            room_id,
+            user_id: body.user_id,
+            token: body.token,
+            stream,
+            offer: body.offer,
+            shared_key: body.shared_key,
+        };
+
+        let client = match auth_user(
+            request.room_id,
+            request.shared_key.clone(),
+            request.user_id,
+            request.token,
+        )
+        .await
+        {
+            Ok((_room, client)) => client,
+            Err(err) => {
+                send_error(&mut socket, err).await;
+                return;
+            }
+        };
+
+        let ice_servers = ice_servers(&state.config, request.room_id, request.user_id);
+        whip_session(socket, request, client, ice_servers).await
+    }))
+}
+
+async fn whip_session(
+    mut socket: WebSocket,
+    request: RequestJson,
+    client: Client,
+    ice_servers: Vec<IceServer>,
+) {
+    if let Err(err) = health::accepting() {
+        send_error(&mut socket, err).await;
+        return;
+    }
+
+    let stream = request.stream;
+    let id = request.user_id as u32;
+    let offer = match RTCSessionDescription::offer(request.offer) {
+        Ok(offer) => offer,
+        Err(err) => {
+            send_error(&mut socket, AppError::invalid_offer(err)).await;
+            return;
+        }
+    };
+
+    let mut rooms = ROOMS.lock().await;
+    if !rooms.contains_key(&request.room_id) {
+        error!("room does not exist");
+    }
+
+    let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
+    let forwarder = room.forwarder();
+    let forwarder = forwarder.write().await;
+
+    drop(rooms);
+
+    let (tx0, mut rx0) = mpsc::channel::<(bool, String)>(32);
+    let tx1 = tx0.clone();
+    let result = forwarder
+        .publish(
+            stream.clone(),
+            id.clone(),
+            offer.clone(),
+            Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let candidate = candidate.clone();
+                let tx0 = tx0.clone();
+                if let Some(candidate) = candidate {
+                    return Box::pin(async move {
+                        let c = candidate.to_json().unwrap().candidate;
+                        if let Err(_err) = tx0.clone().send((false, c.clone())).await {}
+                    });
+                }
+                Box::pin(async {})
+            }),
+            Box::new(move || {
+                let tx1 = tx1.clone();
+                Box::pin(async move {
+                    if let Err(_err) = tx1.clone().send((true, "".to_string())).await {}
+                })
+            }),
+        )
+        .await;
+    drop(forwarder);
+    let (peer, answer, session) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            send_error(&mut socket, err).await;
+            return;
+        }
+    };
+
+    let mut client = client;
+    let _ = client.add_stream(stream.clone()).await;
+
+    let answer = SignalingJson {
+        is_candidate: false,
+        sdp: answer.sdp,
+        session: session,
+        candidate: String::new(),
+        ice_servers,
+    };
+
+    if socket
+        .send(ws::Message::Text(serde_json::to_string(&answer).unwrap()))
+        .await
+        .is_err()
     {
-        Ok((room, client)) => (room, client),
-        Err(err_response) => return Ok(err_response),
+        return;
     };
 
-    return Ok(ws.on_upgrade(|mut socket: WebSocket| {
-        let request = request;
-        Box::pin(async move {
-            let stream = request.stream;
-            let id = request.user_id as u32;
-            let offer = RTCSessionDescription::offer(request.offer).unwrap();
+    let (mut sender, mut receiver) = socket.split();
 
-            let mut rooms = ROOMS.lock().await;
-            if !rooms.contains_key(&request.room_id) {
-                error!("room does not exist");
+    let mut send_task = tokio::spawn(async move {
+        while let Some((peer_connected, candidate)) = rx0.recv().await {
+            if peer_connected {
+                return;
             }
-
-            let room: &mut Room = rooms.get_mut(&request.room_id).unwrap();
-            let forwarder = room.forwarder();
-            let forwarder = forwarder.write().await;
-
-            drop(rooms);
-
-            let (tx0, mut rx0) = mpsc::channel::<(bool, String)>(32);
-            let tx1 = tx0.clone();
-            let (peer, answer, session) = forwarder
-                .publish(
-                    stream.clone(),
-                    id.clone(),
-                    offer.clone(),
-                    Box::new(move |candidate: Option<RTCIceCandidate>| {
-                        let candidate = candidate.clone();
-                        let tx0 = tx0.clone();
-                        if let Some(candidate) = candidate {
-                            return Box::pin(async move {
-                                let c = candidate.to_json().unwrap().candidate;
-                                if let Err(_err) = tx0.clone().send((false, c.clone())).await {}
-                            });
-                        }
-                        Box::pin(async {})
-                    }),
-                    Box::new(move || {
-                        let tx1 = tx1.clone();
-                        Box::pin(async move {
-                            if let Err(_err) = tx1.clone().send((true, "".to_string())).await {}
-                        })
-                    }),
-                )
-                .await
-                .unwrap();
-            drop(forwarder);
-
-            let mut client = client;
-            let _ = client.add_stream(stream.clone()).await;
-
-            let answer = SignalingJson {
-                is_candidate: false,
-                sdp: answer.sdp,
-                session: session,
-                candidate: String::new(),
+            let signaling = SignalingJson {
+                is_candidate: true,
+                sdp: String::new(),
+                session: String::new(),
+                candidate: candidate,
+                ice_servers: vec![],
             };
+            let msg = ws::Message::Text(serde_json::to_string(&signaling).unwrap());
+            if sender.send(msg).await.is_err() {
+                return;
+            }
+        }
+    });
 
-            if socket
-                .send(ws::Message::Text(serde_json::to_string(&answer).unwrap()))
-                .await
-                .is_err()
-            {
+    let mut recv_task = tokio::spawn(async move {
+        while let Some(msg) = receiver.next().await {
+            let msg = if let Ok(msg) = msg {
+                msg
+            } else {
                 return;
             };
 
-            let (mut sender, mut receiver) = socket.split();
-
-            let mut send_task = tokio::spawn(async move {
-                while let Some((peer_connected, candidate)) = rx0.recv().await {
-                    if peer_connected {
-                        return;
-                    }
-                    let signaling = SignalingJson {
-                        is_candidate: true,
-                        sdp: String::new(),
-                        session: String::new(),
-                        candidate: candidate,
-                    };
-                    let msg = ws::Message::Text(serde_json::to_string(&signaling).unwrap());
-                    if sender.send(msg).await.is_err() {
+            match msg {
+                ws::Message::Text(t) => {
+                    if peer.connection_state() == RTCPeerConnectionState::Connected {
                         return;
                     }
-                }
-            });
 
-            let mut recv_task = tokio::spawn(async move {
-                while let Some(msg) = receiver.next().await {
-                    let msg = if let Ok(msg) = msg {
-                        msg
-                    } else {
-                        return;
-                    };
-
-                    match msg {
-                        ws::Message::Text(t) => {
-                            if peer.connection_state() == RTCPeerConnectionState::Connected {
-                                return;
-                            }
-
-                            let message = t.clone();
-
-                            debug!("signaling message received: {}", message.clone());
-
-                            let signaling: SignalingJson =
-                                serde_json::from_str(&message.as_str()).unwrap();
-
-                            let _ = peer
-                                .add_ice_candidate(RTCIceCandidateInit {
-                                    candidate: signaling.candidate.clone(),
-                                    ..Default::default()
-                                })
-                                .await;
-                        }
-                        ws::Message::Binary(_b) => {}
-                        ws::Message::Ping(_vec) => {}
-                        ws::Message::Pong(_vec) => {}
-                        ws::Message::Close(_close_frame) => {}
-                    }
-                }
-            });
-
-            tokio::select! {
-                _rv_a = (&mut send_task) => {
-                    recv_task.abort();
-                },
-                _rv_b = (&mut recv_task) => {
-                    send_task.abort();
+                    let message = t.clone();
+
+                    debug!("signaling message received: {}", message.clone());
+
+                    let signaling: SignalingJson = serde_json::from_str(&message.as_str()).unwrap();
+
+                    let _ = peer
+                        .add_ice_candidate(RTCIceCandidateInit {
+                            candidate: signaling.candidate.clone(),
+                            ..Default::default()
+                        })
+                        .await;
                 }
+                ws::Message::Binary(_b) => {}
+                ws::Message::Ping(_vec) => {}
+                ws::Message::Pong(_vec) => {}
+                ws::Message::Close(_close_frame) => {}
             }
-        })
-    }));
+        }
+    });
+
+    tokio::select! {
+        _rv_a = (&mut send_task) => {
+            recv_task.abort();
+        },
+        _rv_b = (&mut recv_task) => {
+            send_task.abort();
+        }
+    }
 }
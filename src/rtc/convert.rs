@@ -23,6 +23,25 @@ impl From<crate::forward::rtc::message::ForwardInfo> for http::response::StreamI
                 .into_iter()
                 .map(|session| session.into())
                 .collect(),
+            publish_tracks: value
+                .publish_tracks
+                .into_iter()
+                .map(|track| track.into())
+                .collect(),
+            recording: value.recording,
+        }
+    }
+}
+
+impl From<crate::forward::rtc::message::TrackInfo> for http::response::TrackInfo {
+    fn from(value: crate::forward::rtc::message::TrackInfo) -> Self {
+        http::response::TrackInfo {
+            kind: value.kind.to_string(),
+            mid: value.mid,
+            id: value.id,
+            stream_id: value.stream_id,
+            rids: value.rids,
+            codec: value.codec,
         }
     }
 }
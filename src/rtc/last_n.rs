@@ -0,0 +1,79 @@
+use std::cmp::Reverse;
+use std::time::Instant;
+
+/// The publishers whose video is forwarded to a subscriber: the ones it
+/// pinned, in its order, then the latest speakers, `n` at most.
+pub(crate) fn last_n(
+    n: usize,
+    pins: &[u32],
+    publishers: &[u32],
+    last_speaking: impl Fn(u32) -> Option<Instant>,
+) -> Vec<u32> {
+    let mut ranked: Vec<u32> = vec![];
+    for pin in pins {
+        if publishers.contains(pin) && !ranked.contains(pin) {
+            ranked.push(*pin);
+        }
+    }
+    let mut others: Vec<u32> = publishers
+        .iter()
+        .copied()
+        .filter(|publisher| !ranked.contains(publisher))
+        .collect();
+    // the ones who never spoke last, by user id for a stable order
+    others.sort_by_key(|&publisher| (Reverse(last_speaking(publisher)), publisher));
+    others.dedup();
+    ranked.extend(others);
+    ranked.truncate(n);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+
+    // user ids with how long ago they last spoke
+    fn speaking(ago: &[(u32, u64)]) -> impl Fn(u32) -> Option<Instant> {
+        let now = Instant::now();
+        let last: HashMap<u32, Instant> = ago
+            .iter()
+            .map(|&(user_id, secs)| (user_id, now - Duration::from_secs(secs)))
+            .collect();
+        move |user_id| last.get(&user_id).copied()
+    }
+
+    #[test]
+    fn test_latest_speakers_first() {
+        let last_speaking = speaking(&[(1, 30), (2, 5), (3, 10)]);
+        assert_eq!(last_n(2, &[], &[1, 2, 3, 4], &last_speaking), [2, 3]);
+        assert_eq!(last_n(10, &[], &[1, 2, 3, 4], &last_speaking), [2, 3, 1, 4]);
+    }
+
+    #[test]
+    fn test_never_spoke_by_user_id() {
+        assert_eq!(last_n(3, &[], &[9, 4, 7, 1], speaking(&[])), [1, 4, 7]);
+    }
+
+    #[test]
+    fn test_pins_first_in_order() {
+        let last_speaking = speaking(&[(1, 1), (2, 2)]);
+        assert_eq!(last_n(3, &[4, 3], &[1, 2, 3, 4], &last_speaking), [4, 3, 1]);
+        // pins over n are cut too
+        assert_eq!(last_n(1, &[4, 3], &[1, 2, 3, 4], &last_speaking), [4]);
+    }
+
+    #[test]
+    fn test_pins_not_publishing_skipped() {
+        let last_speaking = speaking(&[]);
+        assert_eq!(last_n(2, &[5, 2, 2], &[1, 2, 3], &last_speaking), [2, 1]);
+    }
+
+    #[test]
+    fn test_publishers_deduplicated() {
+        let last_speaking = speaking(&[(2, 1)]);
+        assert_eq!(last_n(3, &[], &[1, 2, 1, 2], &last_speaking), [2, 1]);
+    }
+}
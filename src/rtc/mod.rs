@@ -1,5 +1,5 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
 use std::time::Duration;
 use std::vec;
 
@@ -7,33 +7,65 @@ use webrtc::ice_transport::ice_gatherer::OnLocalCandidateHdlrFn;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 
 use crate::config::Config;
+use crate::constant;
 use crate::error::AppError;
+use crate::event::ServerEvent;
 use crate::forward::rtc::message::{ForwardInfo, Layer};
+use crate::forward::rtc::player::{self, PlayConfig};
+use crate::forward::rtc::recorder::RecordConfig;
 use crate::forward::rtc::{OnPeerConnectionEvtHdlrFn, PeerForward};
 use crate::result::Result;
 
 use chrono::{DateTime, Utc};
 
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tracing::{debug, info};
 
+use last_n::last_n;
+use speaker::SpeakerDetector;
+
 pub mod convert;
+mod last_n;
+mod speaker;
+
+// taken by the routes under `/v2/rooms/{room_id}/streams/`
+const RESERVED_STREAMS: [&str; 1] = ["infos"];
+
+// how often the audio levels of the publishers are sampled
+const SPEAKER_TICK: Duration = Duration::from_millis(200);
+
+// the publishers each subscriber pinned, forwarded first under Last-N
+type Pins = Arc<RwLock<HashMap<u32, Vec<u32>>>>;
+// the subscribe sessions whose video Last-N suspended
+type Suspended = Arc<RwLock<HashSet<String>>>;
 
 pub struct Forwarder {
     stream_map: Arc<RwLock<HashMap<String, PeerForward>>>,
     config: ForwarderConfig,
+    check_task: JoinHandle<()>,
+    // room events, such as who is speaking
+    events: broadcast::Sender<ServerEvent>,
+    pins: Pins,
+    suspended: Suspended,
 }
 
 pub struct ForwarderConfig {
     pub ice_servers: Vec<RTCIceServer>,
     pub reforward_close_sub: bool,
     pub publish_leave_timeout: u64,
+    pub keyframe_request_interval: u64,
+    pub gop_cache: bool,
+    // video is forwarded from this many publishers per subscriber, 0 for all
+    pub last_n: usize,
+    pub record: RecordConfig,
 }
 
 impl ForwarderConfig {
-    pub fn from_config(cfg: Config) -> Self {
+    pub fn from_config(cfg: Config, room_id: i32) -> Self {
         let ice_servers: Vec<RTCIceServer> = cfg
             .ice_servers
             .clone()
@@ -44,6 +76,16 @@ impl ForwarderConfig {
             ice_servers,
             reforward_close_sub: cfg.stream_info.reforward_close_sub,
             publish_leave_timeout: cfg.stream_info.publish_leave_timeout.0,
+            keyframe_request_interval: cfg.stream_info.keyframe_request_interval.0,
+            gop_cache: cfg.stream_info.gop_cache,
+            last_n: cfg.stream_info.last_n as usize,
+            record: RecordConfig {
+                dir: cfg.recorder.dir.into(),
+                room_id,
+                auto_start: cfg.recorder.auto_start,
+                max_size: cfg.recorder.max_size,
+                max_duration: Duration::from_millis(cfg.recorder.max_duration),
+            },
         }
     }
 }
@@ -51,19 +93,152 @@ impl ForwarderConfig {
 impl Forwarder {
     pub fn new(cfg: ForwarderConfig) -> Self {
         let stream_map: Arc<RwLock<HashMap<String, PeerForward>>> = Default::default();
-        tokio::spawn(Self::publish_check_tick(
+        let check_task = tokio::spawn(Self::publish_check_tick(
             stream_map.clone(),
             cfg.publish_leave_timeout,
         ));
 
+        let (events, _) = broadcast::channel(16);
+        let pins: Pins = Default::default();
+        let suspended: Suspended = Default::default();
+        tokio::spawn(Self::speaker_tick(
+            Arc::downgrade(&stream_map),
+            events.clone(),
+            (cfg.last_n, pins.clone(), suspended.clone()),
+        ));
+
         let live: Forwarder = Self {
             stream_map: stream_map,
             config: cfg,
+            check_task,
+            events,
+            pins,
+            suspended,
         };
 
         live
     }
 
+    /// The check task never returns, so a finished one has panicked.
+    pub fn check_task_alive(&self) -> bool {
+        !self.check_task.is_finished()
+    }
+
+    /// The room's event stream.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sets the publishers whose video `user_id` receives first under Last-N.
+    pub async fn set_pins(&self, user_id: u32, pins: Vec<u32>) {
+        let mut all_pins = self.pins.write().await;
+        if pins.is_empty() {
+            all_pins.remove(&user_id);
+        } else {
+            all_pins.insert(user_id, pins);
+        }
+    }
+
+    // Samples the audio levels of the publishers for speaker detection, and
+    // applies Last-N from it, until the forwarder is dropped.
+    async fn speaker_tick(
+        stream_map: Weak<RwLock<HashMap<String, PeerForward>>>,
+        events: broadcast::Sender<ServerEvent>,
+        (n, pins, suspended): (usize, Pins, Suspended),
+    ) {
+        let mut detector = SpeakerDetector::default();
+        let mut tick = tokio::time::interval(SPEAKER_TICK);
+        loop {
+            tick.tick().await;
+            let Some(stream_map) = stream_map.upgrade() else {
+                break;
+            };
+            let forwards: Vec<PeerForward> = stream_map.read().await.values().cloned().collect();
+            drop(stream_map);
+            // a user may publish several streams
+            let mut levels: HashMap<u32, u8> = HashMap::new();
+            for forward in &forwards {
+                if let Some((user_id, level)) = forward.audio_level().await {
+                    let entry = levels.entry(user_id).or_default();
+                    *entry = (*entry).max(level);
+                }
+            }
+            for event in detector.tick(&levels) {
+                debug!("speaker event: {:?}", event);
+                let _ = events.send(event);
+            }
+            if n > 0 {
+                let pins = pins.read().await.clone();
+                let mut suspended = suspended.write().await;
+                Self::apply_last_n(&forwards, &detector, (n, &pins), &mut suspended).await;
+            }
+        }
+    }
+
+    // Suspends the video of the subscribe sessions whose publisher is not in
+    // the subscriber's Last-N, and resumes the ones it suspended once it is.
+    // The sessions stay up, so that resuming is instant. Only the publishers
+    // sending video compete for the subscriber's Last-N.
+    async fn apply_last_n(
+        forwards: &[PeerForward],
+        detector: &SpeakerDetector,
+        (n, pins): (usize, &HashMap<u32, Vec<u32>>),
+        suspended: &mut HashSet<String>,
+    ) {
+        let mut streams = vec![];
+        let mut sessions = HashSet::new();
+        for forward in forwards {
+            let subscribe_sessions = forward.subscribe_sessions().await;
+            sessions.extend(
+                subscribe_sessions
+                    .iter()
+                    .map(|(session, _)| session.clone()),
+            );
+            if !forward.publish_has_video().await {
+                continue;
+            }
+            if let Some(publisher) = forward.publish_user().await {
+                streams.push((forward, publisher, subscribe_sessions));
+            }
+        }
+        // forget the sessions which closed
+        suspended.retain(|session| sessions.contains(session));
+
+        let publishers: Vec<u32> = streams.iter().map(|(_, publisher, _)| *publisher).collect();
+        let mut forwarded: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (forward, publisher, subscribe_sessions) in streams {
+            for (session, subscriber) in subscribe_sessions {
+                let selected = forwarded.entry(subscriber).or_insert_with(|| {
+                    let others: Vec<u32> = publishers
+                        .iter()
+                        .copied()
+                        .filter(|publisher| *publisher != subscriber)
+                        .collect();
+                    let pins = pins.get(&subscriber).map(Vec::as_slice).unwrap_or_default();
+                    last_n(n, pins, &others, |user_id| detector.last_speaking(user_id))
+                });
+                let rid = match (selected.contains(&publisher), suspended.contains(&session)) {
+                    (false, false) => constant::RID_DISABLE,
+                    (true, true) => constant::RID_ENABLE,
+                    _ => continue,
+                };
+                let layer = Layer {
+                    encoding_id: rid.to_string(),
+                };
+                if let Err(err) = forward.select_layer(session.clone(), Some(layer)).await {
+                    debug!("last-n select layer {} for {} err: {:?}", rid, session, err);
+                    continue;
+                }
+                debug!("last-n {} video of {} for {}", rid, publisher, subscriber);
+                if rid == constant::RID_DISABLE {
+                    suspended.insert(session);
+                } else {
+                    suspended.remove(&session);
+                }
+            }
+        }
+    }
+
     async fn publish_check_tick(
         stream_map: Arc<RwLock<HashMap<String, PeerForward>>>,
         publish_leave_timeout: u64,
@@ -111,30 +286,38 @@ impl Forwarder {
         }
     }
 
-    pub async fn stream_create(&self, stream: String) -> std::result::Result<(), anyhow::Error> {
+    pub async fn stream_create(&self, stream: String) -> Result<()> {
         let mut stream_map = self.stream_map.write().await;
         let forward = stream_map.get(&stream).cloned();
         if forward.is_some() {
-            return Err(anyhow::anyhow!("stream already exists"));
+            return Err(AppError::stream_already_exists("stream already exists"));
         }
         debug!("create stream: {}", stream.clone());
-        let forward = self.do_stream_create(stream.clone()).await;
+        let forward = self.do_stream_create(stream.clone()).await?;
         stream_map.insert(stream.clone(), forward);
         drop(stream_map);
         Ok(())
     }
 
-    async fn do_stream_create(&self, stream: String) -> PeerForward {
-        let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
-        forward
+    // Every stream of the room is created here, under a name which is not reserved.
+    async fn do_stream_create(&self, stream: String) -> Result<PeerForward> {
+        check_stream_name(&stream)?;
+        let forward = PeerForward::new(
+            stream.clone(),
+            self.config.ice_servers.clone(),
+            self.config.keyframe_request_interval,
+            self.config.gop_cache,
+            self.config.record.clone(),
+        );
+        Ok(forward)
     }
 
-    pub async fn stream_delete(&self, stream: String) -> std::result::Result<(), anyhow::Error> {
+    pub async fn stream_delete(&self, stream: String) -> Result<()> {
         let mut stream_map = self.stream_map.write().await;
         let forward = stream_map.get(&stream).cloned();
         let _ = match forward {
             Some(forward) => forward.close().await,
-            None => return Err(anyhow::anyhow!("stream not exists")),
+            None => return Err(AppError::stream_not_found("stream not exists")),
         };
         stream_map.remove(&stream);
         drop(stream_map);
@@ -165,11 +348,138 @@ impl Forwarder {
         session: String,
         layer: Option<Layer>,
     ) -> Result<()> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        let Some(forward) = forward else {
+            return Err(AppError::stream_not_found("stream not exists"));
+        };
+        forward.select_layer(session.clone(), layer).await?;
+        // the layer is the subscriber's choice now, Last-N suspends it again
+        // from it when the publisher is not in its Last-N
+        self.suspended.write().await.remove(&session);
+        Ok(())
+    }
+
+    pub async fn change_resource(
+        &self,
+        stream: String,
+        session: String,
+        kind: RTPCodecType,
+        enabled: bool,
+    ) -> Result<bool> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.change_resource(session, kind, enabled).await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn start_record(&self, stream: String) -> Result<()> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.start_record().await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn stop_record(&self, stream: String) -> Result<()> {
         let stream_map = self.stream_map.read().await;
         let forward = stream_map.get(&stream).cloned();
         drop(stream_map);
         if let Some(forward) = forward {
-            forward.select_layer(session, layer).await
+            forward.stop_record().await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    /// Publishes `files`, relative to the recorder directory, as user `id`.
+    /// Returns the publish session.
+    pub async fn file_publish(
+        &self,
+        stream: String,
+        id: u32,
+        files: Vec<String>,
+        looping: bool,
+    ) -> Result<String> {
+        let mut paths = vec![];
+        for file in files {
+            paths.push(player::resolve(&self.config.record.dir, &file).ok_or(
+                AppError::bad_request_details(
+                    format!("{} is not under the recorder directory", file),
+                    "files",
+                ),
+            )?);
+        }
+        let config = PlayConfig {
+            files: paths,
+            looping,
+        };
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.file_publish(id, config).await
+        } else {
+            let forward = self.do_stream_create(stream.clone()).await?;
+            let session = forward.file_publish(id, config).await?;
+            let mut stream_map = self.stream_map.write().await;
+            if stream_map.contains_key(&stream) {
+                let _ = forward.close().await;
+                return Err(AppError::stream_already_exists("stream already exists"));
+            }
+            info!("add stream : {}", stream);
+            stream_map.insert(stream.clone(), forward);
+            Ok(session)
+        }
+    }
+
+    pub async fn stop_playback(&self, stream: String) -> Result<()> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.stop_playback().await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn publish_user(&self, stream: String) -> Result<Option<u32>> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            Ok(forward.publish_user().await)
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn session_owner(&self, stream: String, session: String) -> Result<u32> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.session_owner(session).await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn remove_peer(&self, stream: String, session: String) -> Result<bool> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.remove_peer(session).await
         } else {
             Err(AppError::stream_not_found("stream not exists"))
         }
@@ -186,7 +496,7 @@ impl Forwarder {
         if let Some(forward) = forward {
             forward.gen_virtual_publish(on_ice_candidate).await
         } else {
-            let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+            let forward = self.do_stream_create(stream.clone()).await?;
             let (peer, sdp, session) = forward.gen_virtual_publish(on_ice_candidate).await?;
             let mut stream_map = self.stream_map.write().await;
             if stream_map.contains_key(&stream) {
@@ -215,7 +525,7 @@ impl Forwarder {
                 .set_publish(id, offer, on_ice_candidate, on_peer_connected)
                 .await
         } else {
-            let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+            let forward = self.do_stream_create(stream.clone()).await?;
             let (peer, sdp, session) = forward
                 .set_publish(id, offer, on_ice_candidate, on_peer_connected)
                 .await?;
@@ -262,6 +572,14 @@ impl Forwarder {
         }
     }
 
+    /// Sends a server event frame over the data channels of every stream.
+    pub async fn send_server_event(&self, frame: Vec<u8>) {
+        let stream_map = self.stream_map.read().await;
+        for forward in stream_map.values() {
+            forward.send_server_event(frame.clone());
+        }
+    }
+
     pub async fn forward_infos(&self, streams: Vec<String>) -> Vec<ForwardInfo> {
         let mut streams = streams.clone();
         streams.retain(|stream| !stream.trim().is_empty());
@@ -275,3 +593,13 @@ impl Forwarder {
         resp
     }
 }
+
+fn check_stream_name(stream: &str) -> Result<()> {
+    if RESERVED_STREAMS.contains(&stream) {
+        return Err(AppError::bad_request_details(
+            format!("stream name {} is reserved", stream),
+            "stream",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::event::ServerEvent;
+
+// louder than -45 dBov is taken for speech
+const SPEAKING_LEVEL: u8 = 127 - 45;
+// ticks over the level to start speaking, under it to stop
+const START_TICKS: u32 = 2;
+const STOP_TICKS: u32 = 5;
+// ticks a louder speaker needs to take over from a dominant speaker still speaking
+const DOMINANT_TICKS: u32 = 3;
+
+#[derive(Default)]
+struct Speaker {
+    speaking: bool,
+    last_speaking: Option<Instant>,
+    // consecutive ticks against the current state
+    ticks: u32,
+}
+
+/// Detects who speaks in a room, and its dominant speaker, from the audio
+/// levels of the publishers sampled at a regular tick.
+#[derive(Default)]
+pub(crate) struct SpeakerDetector {
+    speakers: HashMap<u32, Speaker>,
+    dominant: Option<u32>,
+    candidate: Option<(u32, u32)>,
+}
+
+impl SpeakerDetector {
+    /// `levels` are the users publishing audio with their level, 0 (silence)
+    /// to 127 (0 dBov). Returns the events of this tick.
+    pub(crate) fn tick(&mut self, levels: &HashMap<u32, u8>) -> Vec<ServerEvent> {
+        let mut events = vec![];
+        self.speakers.retain(|&user_id, speaker| {
+            let publishing = levels.contains_key(&user_id);
+            if !publishing && speaker.speaking {
+                events.push(ServerEvent::SpeakingStopped { user_id });
+            }
+            publishing
+        });
+        for (&user_id, &level) in levels {
+            let speaker = self.speakers.entry(user_id).or_default();
+            if (level >= SPEAKING_LEVEL) != speaker.speaking {
+                speaker.ticks += 1;
+            } else {
+                speaker.ticks = 0;
+            }
+            let needed = if speaker.speaking {
+                STOP_TICKS
+            } else {
+                START_TICKS
+            };
+            if speaker.ticks >= needed {
+                speaker.speaking = !speaker.speaking;
+                speaker.ticks = 0;
+                events.push(if speaker.speaking {
+                    ServerEvent::SpeakingStarted { user_id }
+                } else {
+                    ServerEvent::SpeakingStopped { user_id }
+                });
+            }
+            if speaker.speaking {
+                speaker.last_speaking = Some(Instant::now());
+            }
+        }
+
+        let loudest = levels
+            .iter()
+            .filter(|(user_id, _)| self.speakers[user_id].speaking)
+            .max_by_key(|(user_id, level)| (**level, std::cmp::Reverse(**user_id)))
+            .map(|(user_id, _)| *user_id);
+        let dominant_speaking = self
+            .dominant
+            .and_then(|user_id| self.speakers.get(&user_id))
+            .is_some_and(|speaker| speaker.speaking);
+        // the dominant speaker stays after everyone is silent, until it stops publishing
+        let next = match loudest {
+            Some(loudest) if Some(loudest) == self.dominant => {
+                self.candidate = None;
+                None
+            }
+            Some(loudest) if !dominant_speaking => Some(Some(loudest)),
+            Some(loudest) => {
+                let ticks = match self.candidate {
+                    Some((user_id, ticks)) if user_id == loudest => ticks + 1,
+                    _ => 1,
+                };
+                self.candidate = Some((loudest, ticks));
+                (ticks >= DOMINANT_TICKS).then_some(Some(loudest))
+            }
+            None => self
+                .dominant
+                .filter(|user_id| !self.speakers.contains_key(user_id))
+                .map(|_| None),
+        };
+        if let Some(dominant) = next {
+            self.dominant = dominant;
+            self.candidate = None;
+            events.push(ServerEvent::DominantSpeakerChanged { user_id: dominant });
+        }
+        events
+    }
+
+    /// When `user_id` was last speaking, `None` if never since it publishes.
+    pub(crate) fn last_speaking(&self, user_id: u32) -> Option<Instant> {
+        self.speakers.get(&user_id)?.last_speaking
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: u8 = 100;
+    const LOUDER: u8 = 120;
+    const QUIET: u8 = 20;
+
+    fn tick(detector: &mut SpeakerDetector, levels: &[(u32, u8)]) -> Vec<String> {
+        detector
+            .tick(&levels.iter().copied().collect())
+            .iter()
+            .map(|event| format!("{:?}", event))
+            .collect()
+    }
+
+    fn started(user_id: u32) -> String {
+        format!("{:?}", ServerEvent::SpeakingStarted { user_id })
+    }
+
+    fn stopped(user_id: u32) -> String {
+        format!("{:?}", ServerEvent::SpeakingStopped { user_id })
+    }
+
+    fn dominant(user_id: Option<u32>) -> String {
+        format!("{:?}", ServerEvent::DominantSpeakerChanged { user_id })
+    }
+
+    #[test]
+    fn test_speaking_hysteresis() {
+        let mut detector = SpeakerDetector::default();
+        // a single loud tick is noise
+        assert!(tick(&mut detector, &[(1, LOUD)]).is_empty());
+        assert!(tick(&mut detector, &[(1, QUIET)]).is_empty());
+        assert!(tick(&mut detector, &[(1, LOUD)]).is_empty());
+        assert_eq!(
+            tick(&mut detector, &[(1, LOUD)]),
+            [started(1), dominant(Some(1))]
+        );
+        assert!(detector.last_speaking(1).is_some());
+
+        // short pauses keep it speaking
+        for _ in 1..STOP_TICKS {
+            assert!(tick(&mut detector, &[(1, QUIET)]).is_empty());
+        }
+        assert!(tick(&mut detector, &[(1, LOUD)]).is_empty());
+        for _ in 1..STOP_TICKS {
+            assert!(tick(&mut detector, &[(1, QUIET)]).is_empty());
+        }
+        // the dominant speaker stays after it stops speaking
+        assert_eq!(tick(&mut detector, &[(1, QUIET)]), [stopped(1)]);
+    }
+
+    #[test]
+    fn test_dominant_takeover() {
+        let mut detector = SpeakerDetector::default();
+        for _ in 0..START_TICKS {
+            tick(&mut detector, &[(1, LOUD), (2, QUIET)]);
+        }
+        tick(&mut detector, &[(1, LOUD), (2, LOUDER)]);
+        assert_eq!(tick(&mut detector, &[(1, LOUD), (2, LOUDER)]), [started(2)]);
+        // louder while the dominant speaker still speaks, for a few ticks
+        for _ in 2..DOMINANT_TICKS {
+            assert!(tick(&mut detector, &[(1, LOUD), (2, LOUDER)]).is_empty());
+        }
+        assert_eq!(
+            tick(&mut detector, &[(1, LOUD), (2, LOUDER)]),
+            [dominant(Some(2))]
+        );
+    }
+
+    #[test]
+    fn test_dominant_candidate_resets() {
+        let mut detector = SpeakerDetector::default();
+        for _ in 0..START_TICKS {
+            tick(&mut detector, &[(1, LOUD), (2, LOUD)]);
+        }
+        // ties go to the lowest user id
+        assert_eq!(detector.dominant, Some(1));
+        tick(&mut detector, &[(1, LOUD), (2, LOUDER)]);
+        tick(&mut detector, &[(1, LOUDER), (2, LOUD)]);
+        for _ in 1..DOMINANT_TICKS {
+            assert!(tick(&mut detector, &[(1, LOUD), (2, LOUDER)]).is_empty());
+        }
+        assert_eq!(detector.dominant, Some(1));
+    }
+
+    #[test]
+    fn test_dominant_when_silent_speaker_leaves() {
+        let mut detector = SpeakerDetector::default();
+        for _ in 0..START_TICKS {
+            tick(&mut detector, &[(1, LOUD)]);
+        }
+        // the dominant speaker is replaced at once when it is silent
+        for _ in 0..STOP_TICKS {
+            tick(&mut detector, &[(1, QUIET), (2, QUIET)]);
+        }
+        tick(&mut detector, &[(1, QUIET), (2, LOUD)]);
+        assert_eq!(
+            tick(&mut detector, &[(1, QUIET), (2, LOUD)]),
+            [started(2), dominant(Some(2))]
+        );
+
+        // leaving while speaking
+        assert_eq!(
+            tick(&mut detector, &[(1, QUIET)]),
+            [stopped(2), dominant(None)]
+        );
+        assert!(detector.last_speaking(2).is_none());
+    }
+}
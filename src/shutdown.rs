@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::event::ServerEvent;
+use crate::health::{self, ServerStatus};
+use crate::ROOMS;
+
+/// Stops accepting rooms and sessions, tells every client that the server is
+/// going away, waits `drain_period` and then closes every room.
+pub async fn drain(drain_period: Duration) {
+    health::set_status(ServerStatus::Draining);
+    info!("draining for {:?}", drain_period);
+
+    let rooms = ROOMS.lock().await;
+    let snapshot: Vec<_> = rooms.values().cloned().collect();
+    drop(rooms);
+
+    let event = ServerEvent::Shutdown {
+        drain_period: drain_period.as_millis() as u64,
+    };
+    for room in snapshot.iter() {
+        event.send_to_room(room).await;
+    }
+
+    tokio::time::sleep(drain_period).await;
+
+    let mut rooms = ROOMS.lock().await;
+    for (room_id, room) in rooms.iter_mut() {
+        let forwarder = room.forwarder();
+        let forwarder = forwarder.write().await;
+        for forward_info in forwarder.forward_infos(vec![]).await {
+            if let Err(err) = forwarder.stream_delete(forward_info.id.clone()).await {
+                warn!(
+                    "[{}] close stream {} err: {:?}",
+                    room_id, forward_info.id, err
+                );
+            }
+        }
+        drop(forwarder);
+        if let Err(err) = room.all_user_delete().await {
+            warn!("[{}] delete users err: {:?}", room_id, err);
+        }
+    }
+    rooms.clear();
+    info!("all rooms closed");
+}
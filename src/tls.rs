@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tracing::{info, warn};
+
+use crate::config::Tls;
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| anyhow!("{}: {}", path, e))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(anyhow!("{}: no certificate found", path));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| anyhow!("{}: {}", path, e))?);
+    rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| anyhow!("{}: {}", path, e))?
+        .ok_or(anyhow!("{}: no private key found", path))
+}
+
+/// Builds the rustls server config from the PEM files of `[http.tls]`. Client
+/// certificates are required when `client_ca` is set.
+pub fn server_config(tls: &Tls) -> anyhow::Result<Arc<ServerConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(load_certs(&tls.cert)?, load_key(&tls.key)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn modified(tls: &Tls) -> Vec<Option<SystemTime>> {
+    let mut paths = vec![&tls.cert, &tls.key];
+    if let Some(client_ca) = &tls.client_ca {
+        paths.push(client_ca);
+    }
+    paths
+        .into_iter()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Polls the certificate files every `reload_interval` and swaps the config
+/// of `rustls_config` when one of them changes. Connections which are already
+/// established keep their certificate. A config which fails to load is
+/// retried on the next tick, while the previous one stays in use.
+pub fn watch(tls: Tls, rustls_config: RustlsConfig) {
+    tokio::spawn(async move {
+        let mut last = modified(&tls);
+        let mut interval = tokio::time::interval(Duration::from_millis(tls.reload_interval.0));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let current = modified(&tls);
+            if current == last {
+                continue;
+            }
+            match server_config(&tls) {
+                Ok(config) => {
+                    rustls_config.reload_from_config(config);
+                    last = current;
+                    info!("tls certificates reloaded");
+                }
+                Err(err) => warn!("tls certificates reload err: {}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,286 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::hmac;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info, warn};
+use webrtc::turn::auth::{generate_auth_key, AuthHandler};
+use webrtc::turn::relay::relay_range::RelayAddressGeneratorRanges;
+use webrtc::turn::relay::relay_static::RelayAddressGeneratorStatic;
+use webrtc::turn::relay::RelayAddressGenerator;
+use webrtc::turn::server::config::{ConnConfig, ServerConfig};
+use webrtc::turn::server::Server;
+use webrtc::util::vnet::net::Net;
+use webrtc::util::Conn;
+
+use crate::config::{IceServer, Turn};
+
+static CREDENTIALS: OnceLock<Credentials> = OnceLock::new();
+
+struct Credentials {
+    secret: String,
+    ttl: Duration,
+    urls: Vec<String>,
+}
+
+/// Mints TURN REST credentials: the username is `{expiry}:{user}` and the
+/// password is the base64 HMAC-SHA1 of the username with the shared secret.
+fn password(secret: &str, username: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret.as_bytes());
+    STANDARD.encode(hmac::sign(&key, username.as_bytes()).as_ref())
+}
+
+/// Returns the embedded TURN server with credentials for `user`, or `None`
+/// when `[turn]` is not configured.
+pub fn credentials(user: &str) -> Option<IceServer> {
+    let credentials = CREDENTIALS.get()?;
+    let expiry = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + credentials.ttl;
+    let username = format!("{}:{}", expiry.as_secs(), user);
+    Some(IceServer {
+        urls: credentials.urls.clone(),
+        credential: password(&credentials.secret, &username),
+        username,
+        credential_type: "password".to_string(),
+    })
+}
+
+struct RestAuthHandler {
+    secret: String,
+}
+
+impl AuthHandler for RestAuthHandler {
+    fn auth_handle(
+        &self,
+        username: &str,
+        realm: &str,
+        src_addr: SocketAddr,
+    ) -> Result<Vec<u8>, webrtc::turn::Error> {
+        let expiry = username
+            .split(':')
+            .next()
+            .and_then(|expiry| expiry.parse::<u64>().ok())
+            .ok_or(webrtc::turn::Error::Other(format!(
+                "invalid username {}",
+                username
+            )))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if Duration::from_secs(expiry) < now {
+            debug!("[turn] expired username {} from {}", username, src_addr);
+            return Err(webrtc::turn::Error::Other(format!(
+                "expired username {}",
+                username
+            )));
+        }
+        Ok(generate_auth_key(
+            username,
+            realm,
+            &password(&self.secret, username),
+        ))
+    }
+}
+
+/// A TURN-over-TCP connection seen as a `Conn`. STUN messages and ChannelData
+/// carry their own length, so every `recv_from` reads exactly one of them.
+struct TcpConn {
+    reader: Mutex<OwnedReadHalf>,
+    writer: Mutex<OwnedWriteHalf>,
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+    closed: Notify,
+}
+
+impl TcpConn {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        let local_addr = stream.local_addr()?;
+        let remote_addr = stream.peer_addr()?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            local_addr,
+            remote_addr,
+            closed: Notify::new(),
+        })
+    }
+
+    async fn read_frame(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.reader.lock().await;
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let (size, padded) = if header[0] & 0xC0 == 0 {
+            // STUN, the 20 byte header is not part of the length
+            (20 + length, 20 + length)
+        } else {
+            // ChannelData, padded to 4 bytes over TCP
+            (4 + length, (4 + length).div_ceil(4) * 4)
+        };
+        if padded > buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("turn message of {} bytes is too large", padded),
+            ));
+        }
+        buf[..4].copy_from_slice(&header);
+        reader.read_exact(&mut buf[4..padded]).await?;
+        Ok(size)
+    }
+}
+
+#[async_trait]
+impl Conn for TcpConn {
+    async fn connect(&self, _addr: SocketAddr) -> webrtc::util::Result<()> {
+        Err(io::Error::other("not applicable").into())
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        Ok(self.read_frame(buf).await?)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc::util::Result<(usize, SocketAddr)> {
+        Ok((self.read_frame(buf).await?, self.remote_addr))
+    }
+
+    async fn send(&self, buf: &[u8]) -> webrtc::util::Result<usize> {
+        self.writer.lock().await.write_all(buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn send_to(&self, buf: &[u8], _target: SocketAddr) -> webrtc::util::Result<usize> {
+        self.send(buf).await
+    }
+
+    fn local_addr(&self) -> webrtc::util::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(self.remote_addr)
+    }
+
+    async fn close(&self) -> webrtc::util::Result<()> {
+        let _ = self.writer.lock().await.shutdown().await;
+        self.closed.notify_one();
+        Ok(())
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+fn relay_addr_generator(turn: &Turn) -> Box<dyn RelayAddressGenerator + Send + Sync> {
+    let address = if turn.public_ip.is_ipv4() {
+        "0.0.0.0"
+    } else {
+        "::"
+    };
+    match (turn.relay_port_min, turn.relay_port_max) {
+        (Some(min_port), Some(max_port)) => Box::new(RelayAddressGeneratorRanges {
+            relay_address: turn.public_ip,
+            min_port,
+            max_port,
+            max_retries: 10,
+            address: address.to_string(),
+            net: Arc::new(Net::new(None)),
+        }),
+        _ => Box::new(RelayAddressGeneratorStatic {
+            relay_address: turn.public_ip,
+            address: address.to_string(),
+            net: Arc::new(Net::new(None)),
+        }),
+    }
+}
+
+fn server_config(
+    turn: &Turn,
+    conn: Arc<dyn Conn + Send + Sync>,
+    auth_handler: Arc<dyn AuthHandler + Send + Sync>,
+) -> ServerConfig {
+    ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: relay_addr_generator(turn),
+        }],
+        realm: turn.realm.clone(),
+        auth_handler,
+        channel_bind_timeout: Duration::from_secs(0),
+        alloc_close_notify: None,
+    }
+}
+
+// A TURN server only reads from the conns it was created with, so every TCP
+// connection gets its own server, which is closed with the connection.
+async fn serve_tcp(
+    turn: Arc<Turn>,
+    stream: TcpStream,
+    auth_handler: Arc<dyn AuthHandler + Send + Sync>,
+) -> anyhow::Result<()> {
+    let conn = Arc::new(TcpConn::new(stream)?);
+    let server = Server::new(server_config(&turn, conn.clone(), auth_handler)).await?;
+    conn.closed.notified().await;
+    server.close().await?;
+    Ok(())
+}
+
+/// Starts the embedded TURN server on UDP and TCP `turn.listen`.
+pub async fn start(turn: Turn) -> anyhow::Result<()> {
+    let auth_handler: Arc<dyn AuthHandler + Send + Sync> = Arc::new(RestAuthHandler {
+        secret: turn.secret.clone(),
+    });
+
+    let udp = Arc::new(UdpSocket::bind(turn.listen).await?);
+    let udp_server = Server::new(server_config(&turn, udp, auth_handler.clone())).await?;
+    let tcp = TcpListener::bind(turn.listen).await?;
+    info!("TURN server listening on {} (udp, tcp)", turn.listen);
+
+    let public_addr = SocketAddr::new(turn.public_ip, turn.listen.port());
+    let credentials = Credentials {
+        secret: turn.secret.clone(),
+        ttl: Duration::from_secs(turn.credential_ttl.0),
+        urls: vec![
+            format!("turn:{}?transport=udp", public_addr),
+            format!("turn:{}?transport=tcp", public_addr),
+        ],
+    };
+    if CREDENTIALS.set(credentials).is_err() {
+        return Err(anyhow::anyhow!("turn server already started"));
+    }
+
+    let turn = Arc::new(turn);
+    tokio::spawn(async move {
+        // Dropping the server would stop the UDP listener.
+        let _udp_server = udp_server;
+        loop {
+            let (stream, addr) = match tcp.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("[turn] accept err: {}", err);
+                    continue;
+                }
+            };
+            debug!("[turn] tcp connection from {}", addr);
+            let turn = turn.clone();
+            let auth_handler = auth_handler.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_tcp(turn, stream, auth_handler).await {
+                    warn!("[turn] tcp connection {} err: {}", addr, err);
+                }
+            });
+        }
+    });
+    Ok(())
+}